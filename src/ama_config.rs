@@ -0,0 +1,197 @@
+//! This module defines which AMA is to be archived.
+//! - AmaConfig: Names the subreddit, thread, and link compendium of an AMA.
+//! - load_ama_config: Reads an AmaConfig from a TOML file.
+//! - get_url_template: Returns the URL template whose url_id segment is left blank.
+//! - get_thread_url: Returns the URL of the AMA thread itself.
+//! - is_creator: Returns whether a Reddit username belongs to any of the creators.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::ama_corrections::{Correction, RecordFields};
+use crate::error::{Error, Result};
+
+pub(crate) const REDDIT_HOST: &str = "https://old.reddit.com";
+const URL_QUERY: &str = "?context=3";
+
+/// Describes the AMA thread to archive, and where its link compendium is found.
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+pub struct AmaConfig {
+    pub subreddit: String, // r/{subreddit}
+    pub thread_id: String, // comments/{thread_id}
+    #[serde(default = "default_thread_slug")]
+    pub thread_slug: String, // comments/{thread_id}/{thread_slug}; Reddit accepts '_' for any thread.
+    pub compendium_url: String, // Post that links to every exchange.
+    pub first_cc_name: String, // Text of the first <strong> heading in the compendium, colon included.
+    #[serde(default)]
+    pub creators: BTreeMap<String, Vec<String>>, // Reddit usernames of each creator, by display name.
+    #[serde(default)]
+    pub corrections: Vec<Correction>, // Known errors in the compendium, applied after indexing.
+}
+
+fn default_thread_slug() -> String {
+    "_".to_string()
+}
+
+impl Default for AmaConfig {
+    /// The Star vs. the Forces of Evil AMA of August 3rd, 2019.
+    fn default() -> Self {
+        AmaConfig {
+            subreddit: "StarVStheForcesofEvil".to_string(),
+            thread_id: "cll9u5".to_string(),
+            thread_slug: "star_vs_the_forces_of_evil_ask_me_anything".to_string(),
+            compendium_url: "https://old.reddit.com/r/StarVStheForcesofEvil/comments/clnrdv/link_compendium_of_questions_and_answers_from_the/".to_string(),
+            first_cc_name: "Daron Nefcy:".to_string(),
+            creators: BTreeMap::from([
+                ("Aaron Hammersley".to_string(), vec!["AaronHammersley".to_string()]),
+                ("Adam McArthur".to_string(), vec!["AdamMcArthur".to_string()]),
+                ("Daron Nefcy".to_string(), vec!["DaronNefcy".to_string()]),
+                ("Dominic Bisignano".to_string(), vec!["DominicBisignano".to_string()]),
+            ]),
+            corrections: vec![
+                get_url_id_correction("Daron Nefcy", "Joe_Zt", "evw8g9o", "The compendium links Joe_Zt to evw8mcl, which is ShinySaturn's question."),
+                get_url_id_correction("Adam McArthur", "sloppyjeaux", "evwbgza", "The compendium links sloppyjeaux to evwbcnk, which is Hiofshao_Quask's question."),
+            ],
+        }
+    }
+}
+
+fn get_url_id_correction(cc_name: &str, fan_name: &str, url_id: &str, reason: &str) -> Correction {
+    Correction {
+        criteria: RecordFields {
+            cc_name: Some(cc_name.to_string()),
+            fan_name: Some(fan_name.to_string()),
+            url_id: None,
+        },
+        set: RecordFields {
+            url_id: Some(url_id.to_string()),
+            ..Default::default()
+        },
+        reason: reason.to_string(),
+    }
+}
+
+/// Reads the AMA definition from a TOML file of the form:
+/// subreddit = "StarVStheForcesofEvil"
+/// thread_id = "cll9u5"
+/// compendium_url = "https://old.reddit.com/r/StarVStheForcesofEvil/comments/clnrdv/..."
+/// first_cc_name = "Daron Nefcy:"
+///
+/// [creators]
+/// "Daron Nefcy" = ["DaronNefcy"]
+///
+/// [[corrections]]
+/// match = { cc_name = "Daron Nefcy", fan_name = "Joe_Zt" }
+/// set = { url_id = "evw8g9o" }
+/// reason = "The compendium links Joe_Zt to ShinySaturn's question."
+/// - config_path: Location of the TOML file.
+pub fn load_ama_config(config_path: impl AsRef<Path>) -> Result<AmaConfig> {
+    let config_path: &Path = config_path.as_ref();
+    let raw_toml: String = match fs::read_to_string(config_path) {
+        Ok(raw_toml) => raw_toml,
+        Err(io_err) => return Err(Error::io(config_path, io_err)),
+    };
+    match toml::from_str(&raw_toml) {
+        Ok(ama_config) => Ok(ama_config),
+        Err(toml_err) => Err(Error::Parse(format!("Could not parse '{}': {}", config_path.display(), toml_err))),
+    }
+}
+
+/// Returns the URL of the AMA thread, i.e. '.../comments/{thread_id}/{thread_slug}/'
+/// - ama_config: The AMA whose thread is to be linked.
+pub fn get_thread_url(ama_config: &AmaConfig) -> String {
+    format!("{}/r/{}/comments/{}/{}/", REDDIT_HOST, ama_config.subreddit, ama_config.thread_id, ama_config.thread_slug)
+}
+
+/// Returns the URL of the AMA thread with an empty url_id segment, i.e. '.../{thread_slug}//?context=3'
+/// - ama_config: The AMA whose URL template is to be built.
+pub fn get_url_template(ama_config: &AmaConfig) -> String {
+    format!(
+        "{}/r/{}/comments/{}/{}//{}",
+        REDDIT_HOST,
+        ama_config.subreddit,
+        ama_config.thread_id,
+        ama_config.thread_slug,
+        URL_QUERY,
+    )
+}
+
+/// Returns whether `author` is one of the Reddit accounts that `ama_config.creators` lists, for any
+/// creator. Usernames are compared without regard to case, as Reddit does.
+/// - ama_config: Maps each creator's display name to their Reddit usernames.
+/// - author: Reddit username to look for.
+pub fn is_creator(ama_config: &AmaConfig, author: &str) -> bool {
+    ama_config.creators.values()
+        .flatten()
+        .any(|username| username.eq_ignore_ascii_case(author))
+}
+
+#[cfg(test)]
+mod ama_config_tests {
+    use std::fs;
+
+    #[test]
+    fn test_get_url_template() {
+        let expected: &str = "https://old.reddit.com/r/StarVStheForcesofEvil/comments/cll9u5/star_vs_the_forces_of_evil_ask_me_anything//?context=3";
+        let actual: String = super::get_url_template(&super::AmaConfig::default());
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_load_ama_config() {
+        let config_path: &str = "output/ama_config-load_test.toml";
+        let raw_toml: &str = r#"
+            subreddit = "subreddit"
+            thread_id = "thread_id"
+            compendium_url = "compendium_url"
+            first_cc_name = "cc_name1:"
+
+            [creators]
+            cc_name1 = ["username1", "username2"]
+
+            [[corrections]]
+            match = { fan_name = "fan_name1" }
+            set = { url_id = "url_id1" }
+            reason = "reason"
+        "#;
+        fs::write(config_path, raw_toml).unwrap();
+        let actual: super::AmaConfig = super::load_ama_config(config_path).unwrap();
+        let _ = fs::remove_file(config_path);
+        let expected = super::AmaConfig {
+            subreddit: "subreddit".to_string(),
+            thread_id: "thread_id".to_string(),
+            thread_slug: "_".to_string(),
+            compendium_url: "compendium_url".to_string(),
+            first_cc_name: "cc_name1:".to_string(),
+            creators: super::BTreeMap::from([
+                ("cc_name1".to_string(), vec!["username1".to_string(), "username2".to_string()]),
+            ]),
+            corrections: vec![
+                super::Correction {
+                    criteria: super::RecordFields {
+                        fan_name: Some("fan_name1".to_string()),
+                        ..Default::default()
+                    },
+                    set: super::RecordFields {
+                        url_id: Some("url_id1".to_string()),
+                        ..Default::default()
+                    },
+                    reason: "reason".to_string(),
+                },
+            ],
+        };
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_default_matches_ama_toml() {
+        let actual: super::AmaConfig = super::load_ama_config("ama.toml").unwrap();
+        assert_eq!(actual, super::AmaConfig::default());
+    }
+
+}
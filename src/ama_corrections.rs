@@ -0,0 +1,271 @@
+//! This module applies hand-made corrections to the compiled index.
+//! - Correction: Which records to change, what to change them to, and why.
+//! - CorrectionStatus: What applying a correction did.
+//! - apply_corrections: Applies corrections to the `ama_index` table, skipping any already applied.
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::ama_indexer::AmaRecord;
+use crate::error::{Error, Result};
+
+/// Values of the `ama_index` columns that a correction matches on, or sets. Columns left as None
+/// are ignored.
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecordFields {
+    pub cc_name: Option<String>,
+    pub fan_name: Option<String>,
+    pub url_id: Option<String>,
+}
+
+impl RecordFields {
+    /// Returns (column, value) for every column that is given.
+    fn get_columns(&self) -> Vec<(&'static str, &str)> {
+        let mut columns: Vec<(&'static str, &str)> = Vec::new();
+        for (column, value) in [("cc_name", &self.cc_name), ("fan_name", &self.fan_name), ("url_id", &self.url_id)] {
+            if let Some(value) = value {
+                columns.push((column, value.as_str()));
+            }
+        }
+        columns
+    }
+}
+
+/// One override of the compiled index, as written in the AMA definition:
+/// [[corrections]]
+/// match = { cc_name = "Daron Nefcy", fan_name = "Joe_Zt" }
+/// set = { url_id = "evw8g9o" }
+/// reason = "The compendium links Joe_Zt to ShinySaturn's question."
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Correction {
+    #[serde(rename = "match")]
+    pub criteria: RecordFields, // Records to change.
+    pub set: RecordFields, // What to change them to.
+    pub reason: String,
+}
+
+impl Correction {
+    /// Returns whether `ama_record` is one of the records that this correction changes.
+    pub fn matches(&self, ama_record: &AmaRecord) -> bool {
+        let criteria: Vec<(&str, &str)> = self.criteria.get_columns();
+        !criteria.is_empty() && criteria.iter().all(|(column, value)| match *column {
+            "cc_name" => ama_record.cc_name == *value,
+            "fan_name" => ama_record.fan_name == *value,
+            _ => ama_record.url_id == *value,
+        })
+    }
+
+    /// Sets the corrected values on `ama_record`, if it is one of the records that this correction
+    /// changes. Returns whether it was.
+    pub fn apply(&self, ama_record: &mut AmaRecord) -> bool {
+        if !self.matches(ama_record) {
+            return false;
+        }
+        for (column, value) in self.set.get_columns() {
+            let field: &mut String = match column {
+                "cc_name" => &mut ama_record.cc_name,
+                "fan_name" => &mut ama_record.fan_name,
+                _ => &mut ama_record.url_id,
+            };
+            *field = value.to_string();
+        }
+        true
+    }
+}
+
+/// What applying a correction did.
+#[derive(PartialEq)]
+#[derive(Debug)]
+pub enum CorrectionStatus {
+    /// This many records were changed.
+    Applied(usize),
+    /// The matched records already have the corrected values.
+    AlreadyApplied,
+    /// No record matches the correction, before or after it is applied.
+    NoMatch,
+}
+
+impl fmt::Display for CorrectionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CorrectionStatus::Applied(num_rows) => write!(f, "applied to {} records", num_rows),
+            CorrectionStatus::AlreadyApplied => write!(f, "already applied"),
+            CorrectionStatus::NoMatch => write!(f, "matches no record"),
+        }
+    }
+}
+
+/// Applies every correction to the `ama_index` table in `full_dbpath`, in order. Records that
+/// already have the corrected values are left alone, so that applying the same corrections again
+/// changes nothing. The queries that a corrected url_id no longer links to are deleted, to be
+/// fetched again for the new one. Returns the status of each correction.
+/// - corrections: Overrides to apply.
+/// - full_dbpath: Tells function where to find `ama_index`
+/// - dry_run: Reports what would be applied, without saving any change.
+pub fn apply_corrections(corrections: &[Correction], full_dbpath: &str, dry_run: bool) -> Result<Vec<CorrectionStatus>> {
+    // Not migrated first: older databases cannot be until their shared url_ids are corrected.
+    let mut cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath)?;
+    cnxn.pragma_update(None, "foreign_keys", true)?;
+    let tx: rusqlite::Transaction = cnxn.transaction()?;
+    // The queries of a corrected url_id are only deleted once every correction is applied.
+    tx.pragma_update(None, "defer_foreign_keys", true)?;
+    let mut correction_statuses: Vec<CorrectionStatus> = Vec::new();
+    for correction in corrections {
+        let criteria: Vec<(&str, &str)> = correction.criteria.get_columns();
+        let set: Vec<(&str, &str)> = correction.set.get_columns();
+        if criteria.is_empty() || set.is_empty() {
+            return Err(Error::Validation(format!("Correction '{}' must both match and set at least one field.", correction.reason)));
+        }
+        // UPDATE ama_index SET {set} WHERE {criteria} AND NOT ({set}), where every '?' is bound in that order.
+        let set_sql: Vec<String> = set.iter().map(|(column, _)| format!("{} = ?", column)).collect();
+        let criteria_sql: Vec<String> = criteria.iter().map(|(column, _)| format!("{} = ?", column)).collect();
+        let applied_sql: Vec<String> = set.iter().map(|(column, _)| format!("{} IS ?", column)).collect();
+        let update_sql: String = format!(
+            "UPDATE ama_index SET {} WHERE {} AND NOT ({});",
+            set_sql.join(", "),
+            criteria_sql.join(" AND "),
+            applied_sql.join(" AND "),
+        );
+        let params = set.iter().chain(criteria.iter()).chain(set.iter()).map(|(_, value)| value);
+        let num_rows: usize = tx.execute(&update_sql, rusqlite::params_from_iter(params))?;
+        if num_rows > 0 {
+            correction_statuses.push(CorrectionStatus::Applied(num_rows));
+            continue;
+        }
+        // Once applied, the records are found by the corrected values instead of the matched ones.
+        let mut applied: Vec<(&str, &str)> = criteria.iter()
+            .filter(|(column, _)| !set.iter().any(|(set_column, _)| set_column == column))
+            .copied()
+            .collect();
+        applied.extend(set.iter().copied());
+        let applied_sql: Vec<String> = applied.iter().map(|(column, _)| format!("{} = ?", column)).collect();
+        let count_sql: String = format!("SELECT COUNT(*) FROM ama_index WHERE {};", applied_sql.join(" AND "));
+        let num_applied: usize = tx.query_row(&count_sql, rusqlite::params_from_iter(applied.iter().map(|(_, value)| value)), |row| row.get(0))?;
+        correction_statuses.push(
+            match num_applied {
+                0 => CorrectionStatus::NoMatch,
+                _ => CorrectionStatus::AlreadyApplied,
+            }
+        );
+    }
+    // Older databases are cleaned up by the migration that follows instead.
+    if crate::ama_schema::get_schema_version(&tx)? == crate::ama_schema::SCHEMA_VERSION {
+        let () = crate::ama_schema::delete_unlinked_rows(&tx)?;
+    }
+    if !dry_run {
+        tx.commit()?;
+    }
+    Ok(correction_statuses)
+}
+
+#[cfg(test)]
+mod ama_corrections_tests {
+    use super::{Correction, CorrectionStatus, RecordFields};
+
+    fn get_corrections() -> Vec<Correction> {
+        vec![
+            Correction {
+                criteria: RecordFields {
+                    cc_name: Some("cc_name1".to_string()),
+                    fan_name: Some("fan_name2".to_string()),
+                    url_id: None,
+                },
+                set: RecordFields {
+                    url_id: Some("url_id2".to_string()),
+                    ..Default::default()
+                },
+                reason: "fan_name2 was linked to fan_name1's question.".to_string(),
+            },
+            Correction {
+                criteria: RecordFields {
+                    fan_name: Some("fan_name3".to_string()),
+                    ..Default::default()
+                },
+                set: RecordFields {
+                    fan_name: Some("fan_name4".to_string()),
+                    ..Default::default()
+                },
+                reason: "fan_name3 was misspelled.".to_string(),
+            },
+            Correction {
+                criteria: RecordFields {
+                    fan_name: Some("nobody".to_string()),
+                    ..Default::default()
+                },
+                set: RecordFields {
+                    url_id: Some("url_id5".to_string()),
+                    ..Default::default()
+                },
+                reason: "There is no such record.".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_apply_corrections() {
+        let full_dbpath: &str = "output/ama_corrections-apply_test.db";
+        let _ = std::fs::remove_file(full_dbpath);
+        let _ = crate::ama_schema::migrate(full_dbpath).unwrap();
+        let cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath).unwrap();
+        cnxn.execute(
+            "INSERT INTO ama_index (url_id, cc_name, fan_name) VALUES ('url_id1', 'cc_name1', 'fan_name1'), ('url_id9', 'cc_name1', 'fan_name2'), ('url_id3', 'cc_name2', 'fan_name3');",
+            ()
+        ).unwrap();
+        let corrections: Vec<Correction> = get_corrections();
+        // A dry run reports what would be applied, and saves nothing.
+        let expected: Vec<CorrectionStatus> = vec![CorrectionStatus::Applied(1), CorrectionStatus::Applied(1), CorrectionStatus::NoMatch];
+        assert_eq!(super::apply_corrections(&corrections, full_dbpath, true).unwrap(), expected);
+        assert_eq!(super::apply_corrections(&corrections, full_dbpath, false).unwrap(), expected);
+        // Applying the same corrections again changes nothing.
+        let expected: Vec<CorrectionStatus> = vec![CorrectionStatus::AlreadyApplied, CorrectionStatus::AlreadyApplied, CorrectionStatus::NoMatch];
+        assert_eq!(super::apply_corrections(&corrections, full_dbpath, false).unwrap(), expected);
+        let ama_index = crate::ama_indexer::load_ama_index(full_dbpath).unwrap();
+        let _ = std::fs::remove_file(full_dbpath);
+        let actual: Vec<(&str, &str)> = ama_index.iter()
+            .map(|ama_record| (ama_record.fan_name.as_str(), ama_record.url_id.as_str()))
+            .collect();
+        assert_eq!(actual, vec![("fan_name1", "url_id1"), ("fan_name2", "url_id2"), ("fan_name4", "url_id3")]);
+    }
+
+    #[test]
+    fn test_apply_to_record() {
+        let corrections: Vec<Correction> = get_corrections();
+        let mut ama_record = crate::ama_indexer::AmaRecord {
+            cc_name: "cc_name1".to_string(),
+            fan_name: "fan_name2".to_string(),
+            url_id: "url_id1".to_string(),
+        };
+        assert!(corrections[0].apply(&mut ama_record));
+        assert!(!corrections[1].apply(&mut ama_record));
+        assert_eq!(ama_record.url_id, "url_id2");
+    }
+
+    #[test]
+    fn test_apply_empty_correction() {
+        let corrections: Vec<Correction> = vec![
+            Correction {
+                criteria: RecordFields::default(),
+                set: RecordFields {
+                    url_id: Some("url_id1".to_string()),
+                    ..Default::default()
+                },
+                reason: "Would overwrite every url_id.".to_string(),
+            },
+        ];
+        match super::apply_corrections(&corrections, "output/ama_corrections-empty_test.db", false) {
+            Err(super::Error::Validation(_)) => (),
+            other => panic!("Expected a validation error, got: {:?}", other),
+        };
+        let _ = std::fs::remove_file("output/ama_corrections-empty_test.db");
+    }
+
+}
@@ -0,0 +1,411 @@
+//! This module writes the joined archive as CSV or TSV for spreadsheets, and reads corrected sheets back.
+//! - CsvColumn: Every column that can be exported, named as in its header.
+//! - CsvOptions: Which columns to export, and whether to start with a byte order mark.
+//! - quote_field: Quotes a field, if its contents would otherwise break the row.
+//! - guard_formula: Keeps spreadsheets from reading a field as a formula.
+//! - write_csv: Writes a header row, then one row per exchange.
+//! - parse_csv: Splits CSV or TSV text into rows of fields.
+//! - read_csv_exchanges: Reads back the edited fields of every row in an exported sheet.
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::ama_export::Exchange;
+use crate::ama_filetree::{FiletreeEntry, FiletreeExchange};
+use crate::error::{Error, Result};
+
+/// Byte order mark, which tells spreadsheets that the file is UTF-8.
+const BOM: &str = "\u{feff}";
+/// Ends every row, as RFC 4180 asks.
+const ROW_END: &str = "\r\n";
+/// Spreadsheets read a cell that starts with any of these as a formula.
+const FORMULA_CHARS: [char; 4] = ['=', '+', '-', '@'];
+/// Makes spreadsheets read a cell as text, whatever it starts with.
+const TEXT_PREFIX: char = '\'';
+
+/// Every column that can be exported.
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+pub enum CsvColumn {
+    CcName,
+    FanName,
+    UrlId,
+    Url,
+    QuestionText,
+    AnswerText,
+    QuestionMarkdown,
+    AnswerMarkdown,
+    QuestionAuthor,
+    AnswerAuthor,
+    QuestionDatetime,
+    AnswerDatetime,
+    QuestionScore,
+    AnswerScore,
+    QuestionPermalink,
+    AnswerPermalink,
+}
+
+/// Every column, in the order in which `CsvColumn` lists them.
+pub const ALL_COLUMNS: [CsvColumn; 16] = [
+    CsvColumn::CcName, CsvColumn::FanName, CsvColumn::UrlId, CsvColumn::Url,
+    CsvColumn::QuestionText, CsvColumn::AnswerText,
+    CsvColumn::QuestionMarkdown, CsvColumn::AnswerMarkdown,
+    CsvColumn::QuestionAuthor, CsvColumn::AnswerAuthor,
+    CsvColumn::QuestionDatetime, CsvColumn::AnswerDatetime,
+    CsvColumn::QuestionScore, CsvColumn::AnswerScore,
+    CsvColumn::QuestionPermalink, CsvColumn::AnswerPermalink,
+];
+
+/// Columns that are exported unless others are asked for. Enough to review, and to import back.
+pub const DEFAULT_COLUMNS: [CsvColumn; 6] = [
+    CsvColumn::CcName, CsvColumn::FanName, CsvColumn::UrlId, CsvColumn::Url, CsvColumn::QuestionText, CsvColumn::AnswerText,
+];
+
+impl CsvColumn {
+    /// Returns the name of the column, as written in the header row.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            CsvColumn::CcName => "cc_name",
+            CsvColumn::FanName => "fan_name",
+            CsvColumn::UrlId => "url_id",
+            CsvColumn::Url => "url",
+            CsvColumn::QuestionText => "question_text",
+            CsvColumn::AnswerText => "answer_text",
+            CsvColumn::QuestionMarkdown => "question_markdown",
+            CsvColumn::AnswerMarkdown => "answer_markdown",
+            CsvColumn::QuestionAuthor => "question_author",
+            CsvColumn::AnswerAuthor => "answer_author",
+            CsvColumn::QuestionDatetime => "question_datetime",
+            CsvColumn::AnswerDatetime => "answer_datetime",
+            CsvColumn::QuestionScore => "question_score",
+            CsvColumn::AnswerScore => "answer_score",
+            CsvColumn::QuestionPermalink => "question_permalink",
+            CsvColumn::AnswerPermalink => "answer_permalink",
+        }
+    }
+
+    /// Returns the value of this column for `exchange`. Fields that are unknown are empty.
+    fn get_value<'a>(&self, exchange: &'a Exchange) -> Cow<'a, str> {
+        let get_text = |text: &'a Option<String>| Cow::Borrowed(text.as_deref().unwrap_or_default());
+        match self {
+            CsvColumn::CcName => Cow::Borrowed(&exchange.cc_name),
+            CsvColumn::FanName => Cow::Borrowed(&exchange.fan_name),
+            CsvColumn::UrlId => Cow::Borrowed(&exchange.url_id),
+            CsvColumn::Url => Cow::Borrowed(&exchange.url),
+            CsvColumn::QuestionText => Cow::Borrowed(&exchange.question_text),
+            CsvColumn::AnswerText => Cow::Borrowed(&exchange.answer_text),
+            CsvColumn::QuestionMarkdown => get_text(&exchange.question_markdown),
+            CsvColumn::AnswerMarkdown => get_text(&exchange.answer_markdown),
+            CsvColumn::QuestionAuthor => get_text(&exchange.question_meta.author),
+            CsvColumn::AnswerAuthor => get_text(&exchange.answer_meta.author),
+            CsvColumn::QuestionDatetime => get_text(&exchange.question_meta.datetime),
+            CsvColumn::AnswerDatetime => get_text(&exchange.answer_meta.datetime),
+            CsvColumn::QuestionScore => Cow::Owned(exchange.question_meta.score.map(|score| score.to_string()).unwrap_or_default()),
+            CsvColumn::AnswerScore => Cow::Owned(exchange.answer_meta.score.map(|score| score.to_string()).unwrap_or_default()),
+            CsvColumn::QuestionPermalink => get_text(&exchange.question_meta.permalink),
+            CsvColumn::AnswerPermalink => get_text(&exchange.answer_meta.permalink),
+        }
+    }
+}
+
+impl fmt::Display for CsvColumn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get_name())
+    }
+}
+
+impl FromStr for CsvColumn {
+    type Err = String;
+
+    fn from_str(column_name: &str) -> std::result::Result<Self, Self::Err> {
+        match ALL_COLUMNS.iter().find(|column| column.get_name() == column_name.trim()) {
+            Some(column) => Ok(*column),
+            None => {
+                let column_names: Vec<&str> = ALL_COLUMNS.iter().map(|column| column.get_name()).collect();
+                Err(format!("'{}' is not one of: {}", column_name, column_names.join(", ")))
+            },
+        }
+    }
+}
+
+/// Settings for `write_csv`.
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct CsvOptions {
+    pub columns: Vec<CsvColumn>, // Columns to write, in order.
+    pub bom: bool, // Start with a byte order mark, so that Excel reads the file as UTF-8.
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            columns: DEFAULT_COLUMNS.to_vec(),
+            bom: false,
+        }
+    }
+}
+
+/// Returns `field` as it must be written between delimiters: in double quotes, with its own double
+/// quotes doubled, if it contains the delimiter, a double quote, or a line break; and as is otherwise.
+/// - field: Contents of one cell.
+/// - delimiter: What separates the cells of a row, e.g. ',' or '\t'.
+pub fn quote_field(field: &str, delimiter: char) -> Cow<'_, str> {
+    match field.contains([delimiter, '"', '\r', '\n']) {
+        true => Cow::Owned(format!("\"{}\"", field.replace('"', "\"\""))),
+        false => Cow::Borrowed(field),
+    }
+}
+
+/// Returns whether `field` would be read as a formula, even after any text prefixes are removed.
+fn is_formula(field: &str) -> bool {
+    field.trim_start_matches(TEXT_PREFIX).starts_with(FORMULA_CHARS)
+}
+
+/// Returns `field` with a leading `'` if it starts with '=', '+', '-' or '@', so that a spreadsheet
+/// shows it as text instead of computing it. `read_csv_exchanges` removes the prefix again.
+/// - field: Contents of one cell.
+pub fn guard_formula(field: &str) -> Cow<'_, str> {
+    match is_formula(field) {
+        true => Cow::Owned(format!("{}{}", TEXT_PREFIX, field)),
+        false => Cow::Borrowed(field),
+    }
+}
+
+/// Returns `field` without the prefix that `guard_formula` added to it, if it did.
+fn unguard_formula(field: &str) -> &str {
+    match is_formula(field) {
+        true => field.strip_prefix(TEXT_PREFIX).unwrap_or(field),
+        false => field,
+    }
+}
+
+/// Writes a header row, then one row per exchange, to `writer`. Rows end in CRLF, as RFC 4180 asks.
+/// Cells that a spreadsheet would compute are written as text, with `guard_formula`.
+/// - exchanges: Exchanges to write, in order.
+/// - delimiter: What separates the cells of a row, e.g. ',' for CSV or '\t' for TSV.
+/// - csv_options: Which columns to write, and whether to start with a byte order mark.
+/// - writer: Where to write the rows.
+pub fn write_csv(exchanges: &[Exchange], delimiter: char, csv_options: &CsvOptions, writer: &mut dyn Write) -> io::Result<()> {
+    let delimiter_str: String = delimiter.to_string();
+    if csv_options.bom {
+        let () = writer.write_all(BOM.as_bytes())?;
+    }
+    let header_row: Vec<&str> = csv_options.columns.iter().map(|column| column.get_name()).collect();
+    let () = write!(writer, "{}{}", header_row.join(&delimiter_str), ROW_END)?;
+    for exchange in exchanges.iter() {
+        let row: Vec<String> = csv_options.columns.iter()
+            .map(|column| quote_field(&guard_formula(&column.get_value(exchange)), delimiter).into_owned())
+            .collect();
+        let () = write!(writer, "{}{}", row.join(&delimiter_str), ROW_END)?;
+    }
+    Ok(())
+}
+
+/// Splits `raw_csv` into rows of fields. Fields may be quoted, and quoted fields may contain the
+/// delimiter, doubled double quotes and line breaks. Rows may end in CRLF or LF, and a leading byte
+/// order mark is skipped. Blank lines are skipped.
+/// - raw_csv: Contents of a CSV or TSV file.
+/// - delimiter: What separates the cells of a row, e.g. ',' or '\t'.
+pub fn parse_csv(raw_csv: &str, delimiter: char) -> Result<Vec<Vec<String>>> {
+    let raw_csv: &str = raw_csv.strip_prefix(BOM).unwrap_or(raw_csv);
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut field: String = String::new();
+    let mut in_quotes: bool = false;
+    let mut was_quoted: bool = false;
+    let mut lineno: usize = 1;
+    let mut char_iter = raw_csv.chars().peekable();
+    while let Some(c) = char_iter.next() {
+        if c == '\n' {
+            lineno += 1;
+        }
+        match (in_quotes, c) {
+            (true, '"') if char_iter.peek() == Some(&'"') => {
+                field.push('"');
+                let _ = char_iter.next();
+            },
+            (true, '"') => in_quotes = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() && !was_quoted => {
+                in_quotes = true;
+                was_quoted = true;
+            },
+            (false, '"') => return Err(Error::Parse(format!("Stray double quote on line {} of the sheet.", lineno))),
+            (false, c) if c == delimiter => {
+                row.push(std::mem::take(&mut field));
+                was_quoted = false;
+            },
+            (false, '\r') if char_iter.peek() == Some(&'\n') => (),
+            (false, '\n') => {
+                if !(row.is_empty() && field.is_empty() && !was_quoted) {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                was_quoted = false;
+            },
+            (false, c) => match was_quoted {
+                true => return Err(Error::Parse(format!("Text follows a closing quote on line {} of the sheet.", lineno))),
+                false => field.push(c),
+            },
+        };
+    }
+    if in_quotes {
+        return Err(Error::Parse(format!("Quoted field is never closed, by the end of line {} of the sheet.", lineno)));
+    }
+    if !(row.is_empty() && field.is_empty() && !was_quoted) {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Reads back every row of a sheet written by `write_csv`, as the exchange it describes. Rows are
+/// matched to records by their 'cc_name' and 'fan_name' columns, which must both be present. The
+/// 'question_text', 'answer_text' and 'url_id' columns are read back if present, and every other
+/// column is ignored. Empty cells are taken to be unedited, and line breaks are read as LF. The
+/// prefix that `write_csv` adds to cells that would be formulas is removed.
+/// - raw_csv: Contents of the sheet.
+/// - delimiter: What separates the cells of a row, e.g. ',' or '\t'.
+pub fn read_csv_exchanges(raw_csv: &str, delimiter: char) -> Result<Vec<FiletreeExchange>> {
+    let mut rows = parse_csv(raw_csv, delimiter)?.into_iter();
+    let header_row: Vec<String> = rows.next().unwrap_or_default();
+    let get_index = |column: CsvColumn| header_row.iter().position(|column_name| column_name.trim() == column.get_name());
+    let (cc_name_index, fan_name_index): (usize, usize) = match (get_index(CsvColumn::CcName), get_index(CsvColumn::FanName)) {
+        (Some(cc_name_index), Some(fan_name_index)) => (cc_name_index, fan_name_index),
+        _ => return Err(Error::Validation("The sheet must have both a 'cc_name' and a 'fan_name' column.".to_string())),
+    };
+    let (question_index, answer_index, urlid_index) = (get_index(CsvColumn::QuestionText), get_index(CsvColumn::AnswerText), get_index(CsvColumn::UrlId));
+    let mut filetree_exchanges: Vec<FiletreeExchange> = Vec::new();
+    for (rowno, row) in rows.enumerate() {
+        if row.len() != header_row.len() {
+            return Err(Error::Parse(format!("Row {} of the sheet has {} fields, but its header has {}.", rowno + 1, row.len(), header_row.len())));
+        }
+        let get_field = |index: Option<usize>| index
+            .map(|index| unguard_formula(&row[index]).replace("\r\n", "\n"))
+            .filter(|field| !field.is_empty());
+        filetree_exchanges.push(
+            FiletreeExchange {
+                entry: FiletreeEntry {
+                    path: format!("row {}", rowno + 1),
+                    cc_name: unguard_formula(&row[cc_name_index]).to_string(),
+                    fan_name: unguard_formula(&row[fan_name_index]).to_string(),
+                    url_id: String::new(),
+                },
+                question_text: get_field(question_index),
+                answer_text: get_field(answer_index),
+                url_id: get_field(urlid_index).map(|url_id| url_id.trim().to_string()),
+            }
+        );
+    }
+    Ok(filetree_exchanges)
+}
+
+#[cfg(test)]
+mod ama_csv_tests {
+    use crate::ama_export::{get_test_exchange, Exchange};
+    use crate::ama_scraper::CommentMeta;
+
+    use super::{CsvColumn, CsvOptions};
+
+    #[test]
+    fn test_quote_field() {
+        assert_eq!(super::quote_field("plain text", ','), "plain text");
+        assert_eq!(super::quote_field("a, b", ','), "\"a, b\"");
+        assert_eq!(super::quote_field("a, b", '\t'), "a, b");
+        assert_eq!(super::quote_field("say \"hi\"", '\t'), "\"say \"\"hi\"\"\"");
+        assert_eq!(super::quote_field("line 1\nline 2", ','), "\"line 1\nline 2\"");
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let exchanges: Vec<Exchange> = vec![
+            Exchange {
+                answer_text: "Two\nlines, and \"quotes\".".to_string(),
+                answer_meta: CommentMeta { score: Some(12), ..Default::default() },
+                ..get_test_exchange("cc_name1", "fan_name1", "url_id1")
+            },
+        ];
+        let csv_options = CsvOptions {
+            columns: vec![CsvColumn::FanName, CsvColumn::AnswerText, CsvColumn::AnswerScore, CsvColumn::AnswerAuthor],
+            bom: true,
+        };
+        let mut raw_csv: Vec<u8> = Vec::new();
+        super::write_csv(&exchanges, ',', &csv_options, &mut raw_csv).unwrap();
+        let raw_csv: String = String::from_utf8(raw_csv).unwrap();
+        assert_eq!(raw_csv, "\u{feff}fan_name,answer_text,answer_score,answer_author\r\nfan_name1,\"Two\nlines, and \"\"quotes\"\".\",12,\r\n");
+        assert_eq!("question_score".parse::<CsvColumn>(), Ok(CsvColumn::QuestionScore));
+        assert!("question".parse::<CsvColumn>().is_err());
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let exchanges: Vec<Exchange> = vec![
+            Exchange {
+                question_text: "Question, with a comma?".to_string(),
+                answer_text: "Two\r\nlines, and \"quotes\".".to_string(),
+                ..get_test_exchange("cc_name1", "fan_name1", "url_id1")
+            },
+            Exchange { answer_text: String::new(), ..get_test_exchange("cc_name1", "fan_name2", "url_id2") },
+            Exchange { answer_text: "Tab\tseparated".to_string(), ..get_test_exchange("cc_name1", "fan_name3", "url_id3") },
+        ];
+        for delimiter in [',', '\t'] {
+            let mut raw_csv: Vec<u8> = Vec::new();
+            super::write_csv(&exchanges, delimiter, &CsvOptions { bom: true, ..Default::default() }, &mut raw_csv).unwrap();
+            let rows = super::parse_csv(&String::from_utf8(raw_csv).unwrap(), delimiter).unwrap();
+            assert_eq!(rows.len(), 4);
+            assert_eq!(rows[0], vec!["cc_name", "fan_name", "url_id", "url", "question_text", "answer_text"]);
+            for (row, exchange) in rows[1..].iter().zip(exchanges.iter()) {
+                assert_eq!(row[1], exchange.fan_name);
+                assert_eq!(row[4], exchange.question_text);
+                assert_eq!(row[5], exchange.answer_text);
+            }
+        }
+        // Spreadsheets may leave off the last line break, or the quotes on fields that need none.
+        assert_eq!(super::parse_csv("a,b\n1,\"\"\n\n2,x", ',').unwrap(), vec![vec!["a", "b"], vec!["1", ""], vec!["2", "x"]]);
+        assert!(super::parse_csv("a,b\n1,\"never closed\n", ',').is_err());
+        assert!(super::parse_csv("a,b\n1,un\"quoted\n", ',').is_err());
+    }
+
+    #[test]
+    fn test_guard_formula() {
+        let exchanges: Vec<Exchange> = vec![
+            Exchange { answer_text: "=1+1".to_string(), ..get_test_exchange("cc_name1", "@fan_name1", "url_id1") },
+            Exchange { answer_text: "-Daron".to_string(), ..get_test_exchange("cc_name1", "fan_name2", "url_id2") },
+            Exchange { answer_text: "'+ already quoted".to_string(), ..get_test_exchange("cc_name1", "fan_name3", "url_id3") },
+            Exchange { answer_text: "'Twas not a formula".to_string(), ..get_test_exchange("cc_name1", "fan_name4", "url_id4") },
+        ];
+        let csv_options = CsvOptions {
+            columns: vec![CsvColumn::CcName, CsvColumn::FanName, CsvColumn::AnswerText],
+            bom: false,
+        };
+        let mut raw_csv: Vec<u8> = Vec::new();
+        super::write_csv(&exchanges, ',', &csv_options, &mut raw_csv).unwrap();
+        let raw_csv: String = String::from_utf8(raw_csv).unwrap();
+        assert_eq!(raw_csv, "cc_name,fan_name,answer_text\r\ncc_name1,'@fan_name1,'=1+1\r\ncc_name1,fan_name2,'-Daron\r\ncc_name1,fan_name3,''+ already quoted\r\ncc_name1,fan_name4,'Twas not a formula\r\n");
+        // Every cell reads back as it was before the export.
+        let filetree_exchanges = super::read_csv_exchanges(&raw_csv, ',').unwrap();
+        for (filetree_exchange, exchange) in filetree_exchanges.iter().zip(exchanges.iter()) {
+            assert_eq!(filetree_exchange.entry.fan_name, exchange.fan_name);
+            assert_eq!(filetree_exchange.answer_text.as_ref(), Some(&exchange.answer_text));
+        }
+    }
+
+    #[test]
+    fn test_read_csv_exchanges() {
+        let raw_csv: &str = "url,fan_name,cc_name,answer_text,url_id\r\nhttps://a,fan_name1,cc_name1,\"Edited\r\nanswer\",url_id1\r\nhttps://b,fan_name2,cc_name1,, url_id9 \r\n";
+        let filetree_exchanges = super::read_csv_exchanges(raw_csv, ',').unwrap();
+        assert_eq!(filetree_exchanges.len(), 2);
+        assert_eq!((filetree_exchanges[0].entry.cc_name.as_str(), filetree_exchanges[0].entry.fan_name.as_str()), ("cc_name1", "fan_name1"));
+        assert_eq!(filetree_exchanges[0].answer_text, Some("Edited\nanswer".to_string()));
+        assert_eq!(filetree_exchanges[0].question_text, None);
+        assert_eq!(filetree_exchanges[1].answer_text, None);
+        assert_eq!(filetree_exchanges[1].url_id, Some("url_id9".to_string()));
+        assert_eq!(filetree_exchanges[1].entry.path, "row 2");
+        assert!(super::read_csv_exchanges("url_id,answer_text\r\nurl_id1,text\r\n", ',').is_err());
+        assert!(super::read_csv_exchanges("cc_name,fan_name\r\ncc_name1\r\n", ',').is_err());
+    }
+
+}
@@ -0,0 +1,337 @@
+//! This module renders the joined archive as an EPUB 3 book, with one chapter per creator.
+//! - get_exchange_anchor: Returns the id by which an exchange can be linked to, wherever it is.
+//! - get_ama_date: Returns the day of the AMA, from the earliest comment in it.
+//! - write_epub: Writes the package document, navigation document, title page and chapters into one zip.
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use crate::ama_config::{self, AmaConfig};
+use crate::ama_export::{self, Exchange};
+use crate::ama_markdown::{self, CreatorStats};
+use crate::ama_site::{escape_html, text_to_html};
+use crate::ama_zip::ZipWriter;
+
+/// Must be the first file in the zip, stored, so that readers can tell what the file is.
+const MIMETYPE: &str = "application/epub+zip";
+const CONTAINER_XML: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">
+<rootfiles>
+<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>
+</rootfiles>
+</container>
+";
+const STYLESHEET: &str = "body { font-family: serif; line-height: 1.4; }
+h1 { font-size: 1.6em; }
+h2 { font-size: 1.2em; margin-top: 1.5em; }
+blockquote { margin: 0.5em 1em; }
+.role { font-weight: bold; margin-bottom: 0; }
+.meta { font-size: 0.85em; }
+";
+/// Written as the modification date of a book whose comments all lack dates.
+const UNKNOWN_DATETIME: &str = "2000-01-01T00:00:00Z";
+
+/// Returns the id of the section of `url_id`, which is the same in every book and on every run.
+/// Ids must not start with a digit in XHTML, and url_ids often do.
+pub fn get_exchange_anchor(url_id: &str) -> String {
+    let url_id: String = url_id.chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    format!("x-{}", url_id)
+}
+
+/// Returns `datetime` in the form that EPUB asks for, e.g. '2019-08-03T17:01:23Z', if it is in UTC.
+fn get_utc_datetime(datetime: &str) -> Option<String> {
+    let utc_datetime: &str = datetime.strip_suffix("+00:00").or(datetime.strip_suffix('Z'))?;
+    match utc_datetime.len() {
+        19 => Some(format!("{}Z", utc_datetime)),
+        _ => None,
+    }
+}
+
+/// Returns the earliest and the latest date at which a question or an answer in `exchanges` was
+/// posted, as UTC date-times. None if no comment has a date.
+fn get_datetime_range(exchanges: &[Exchange]) -> Option<(String, String)> {
+    let mut datetimes: Vec<String> = exchanges.iter()
+        .flat_map(|exchange| [&exchange.question_meta.datetime, &exchange.answer_meta.datetime])
+        .filter_map(|datetime| get_utc_datetime(datetime.as_deref()?))
+        .collect();
+    datetimes.sort();
+    Some((datetimes.first()?.clone(), datetimes.last()?.clone()))
+}
+
+/// Returns the day of the AMA, e.g. '2019-08-03', from the earliest question or answer in `exchanges`.
+pub fn get_ama_date(exchanges: &[Exchange]) -> Option<String> {
+    get_datetime_range(exchanges).map(|(first_datetime, _)| first_datetime[..10].to_string())
+}
+
+/// Wraps `body` in an XHTML content document.
+fn get_xhtml(title: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<!DOCTYPE html>
+<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" lang=\"en\" xml:lang=\"en\">
+<head>
+<meta charset=\"UTF-8\"/>
+<title>{}</title>
+<link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\"/>
+</head>
+<body>
+{}
+</body>
+</html>
+",
+        escape_html(title), body,
+    )
+}
+
+/// Returns the filename of the chapter of each creator, in order: ASCII, and the same on every run.
+/// Names are made ASCII before they are told apart, so that e.g. 'Zoë' and 'Zoé' get two files.
+fn get_chapter_fnames(cc_names: &[&str]) -> Vec<String> {
+    let mut used_anchors: HashSet<String> = HashSet::new();
+    cc_names.iter()
+        .map(|cc_name| {
+            let ascii_name: String = cc_name.chars()
+                .map(|c| match c.is_ascii() {
+                    true => c,
+                    false => '_',
+                })
+                .collect();
+            format!("creator-{}.xhtml", ama_markdown::get_anchor(&ascii_name, &mut used_anchors))
+        })
+        .collect()
+}
+
+/// Writes `exchanges` to `writer` as an EPUB 3 book. The book has a title page with the statistics
+/// of every creator, then one chapter per creator, in the order in which each first appears. The
+/// section of each exchange has the id from `get_exchange_anchor`. Like `write_book`, the output
+/// depends on nothing but the exchanges.
+/// - ama_config: Names the subreddit and thread that the book is about.
+/// - exchanges: Exchanges to write, in compendium order.
+/// - writer: Where to write the book.
+pub fn write_epub(ama_config: &AmaConfig, exchanges: &[Exchange], writer: &mut dyn Write) -> io::Result<usize> {
+    let creator_groups = ama_export::group_by_creator(exchanges);
+    let cc_names: Vec<&str> = creator_groups.iter().map(|(cc_name, _)| *cc_name).collect();
+    let chapter_fnames: Vec<String> = get_chapter_fnames(&cc_names);
+    let title: String = format!("r/{} AMA", ama_config.subreddit);
+    let thread_url: String = ama_config::get_thread_url(ama_config);
+    let datetime_range: Option<(String, String)> = get_datetime_range(exchanges);
+    let ama_date: Option<String> = get_ama_date(exchanges);
+    // Title page.
+    let mut stats_rows: Vec<String> = Vec::new();
+    for ((cc_name, creator_exchanges), chapter_fname) in creator_groups.iter().zip(chapter_fnames.iter()) {
+        let creator_stats = CreatorStats::from_exchanges(creator_exchanges);
+        stats_rows.push(format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>",
+            chapter_fname, escape_html(cc_name), creator_stats.num_exchanges, creator_stats.num_answer_words,
+        ));
+    }
+    let title_body: String = format!(
+        "<section epub:type=\"titlepage\">
+<h1>{title}</h1>
+<p>{num_exchanges} exchanges with {num_creators} creators{date}, archived from <a href=\"{thread_url}\">the AMA thread</a>.</p>
+<table>
+<tr><th>Creator</th><th>Exchanges</th><th>Answer words</th></tr>
+{stats_rows}
+</table>
+</section>",
+        title = escape_html(&title),
+        num_exchanges = exchanges.len(),
+        num_creators = creator_groups.len(),
+        date = ama_date.as_ref().map(|ama_date| format!(" on {}", ama_date)).unwrap_or_default(),
+        thread_url = escape_html(&thread_url),
+        stats_rows = stats_rows.join("\n"),
+    );
+    // A chapter per creator.
+    let mut chapters: Vec<String> = Vec::new();
+    for (cc_name, creator_exchanges) in creator_groups.iter() {
+        let sections: Vec<String> = creator_exchanges.iter()
+            .map(|exchange| format!(
+                "<section id=\"{anchor}\">
+<h2>{fan_name}</h2>
+<p class=\"role\">Question</p>
+<blockquote>
+{question}
+</blockquote>
+<p class=\"role\">Answer</p>
+<blockquote>
+{answer}
+</blockquote>
+<p class=\"meta\"><a href=\"{url}\">View on Reddit</a></p>
+</section>",
+                anchor = get_exchange_anchor(&exchange.url_id),
+                fan_name = escape_html(&exchange.fan_name),
+                question = text_to_html(&exchange.question_text),
+                answer = text_to_html(&exchange.answer_text),
+                url = escape_html(&exchange.url),
+            ))
+            .collect();
+        let chapter_body: String = format!("<section epub:type=\"chapter\">\n<h1>{}</h1>\n{}\n</section>", escape_html(cc_name), sections.join("\n"));
+        chapters.push(get_xhtml(cc_name, &chapter_body));
+    }
+    // Navigation document, with every exchange under its creator.
+    let mut nav_items: Vec<String> = Vec::new();
+    for ((cc_name, creator_exchanges), chapter_fname) in creator_groups.iter().zip(chapter_fnames.iter()) {
+        let exchange_items: Vec<String> = creator_exchanges.iter()
+            .map(|exchange| format!("<li><a href=\"{}#{}\">{}</a></li>", chapter_fname, get_exchange_anchor(&exchange.url_id), escape_html(&exchange.fan_name)))
+            .collect();
+        nav_items.push(format!("<li><a href=\"{}\">{}</a>\n<ol>\n{}\n</ol>\n</li>", chapter_fname, escape_html(cc_name), exchange_items.join("\n")));
+    }
+    let nav_body: String = format!(
+        "<nav epub:type=\"toc\" id=\"toc\">
+<h1>Contents</h1>
+<ol>
+<li><a href=\"title.xhtml\">{}</a></li>
+{}
+</ol>
+</nav>",
+        escape_html(&title), nav_items.join("\n"),
+    );
+    // Package document.
+    let contributors: Vec<String> = cc_names.iter()
+        .map(|cc_name| format!("<dc:contributor>{}</dc:contributor>", escape_html(cc_name)))
+        .collect();
+    let manifest_items: Vec<String> = chapter_fnames.iter().enumerate()
+        .map(|(chapterno, chapter_fname)| format!("<item id=\"chapter-{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>", chapterno + 1, chapter_fname))
+        .collect();
+    let spine_items: Vec<String> = (1..=chapter_fnames.len())
+        .map(|chapterno| format!("<itemref idref=\"chapter-{}\"/>", chapterno))
+        .collect();
+    let content_opf: String = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"en\">
+<metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">
+<dc:identifier id=\"book-id\">{identifier}</dc:identifier>
+<dc:title>{title}</dc:title>
+<dc:language>en</dc:language>
+{date}<dc:subject>r/{subreddit}</dc:subject>
+<dc:source>{thread_url}</dc:source>
+<dc:description>{num_exchanges} questions and answers from the AMA on r/{subreddit}.</dc:description>
+{contributors}
+<meta property=\"dcterms:modified\">{modified}</meta>
+</metadata>
+<manifest>
+<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>
+<item id=\"style\" href=\"style.css\" media-type=\"text/css\"/>
+<item id=\"title\" href=\"title.xhtml\" media-type=\"application/xhtml+xml\"/>
+{manifest_items}
+</manifest>
+<spine>
+<itemref idref=\"title\"/>
+{spine_items}
+</spine>
+</package>
+",
+        identifier = escape_html(&thread_url),
+        title = escape_html(&title),
+        date = ama_date.as_ref().map(|ama_date| format!("<dc:date>{}</dc:date>\n", ama_date)).unwrap_or_default(),
+        subreddit = escape_html(&ama_config.subreddit),
+        thread_url = escape_html(&thread_url),
+        num_exchanges = exchanges.len(),
+        contributors = contributors.join("\n"),
+        modified = datetime_range.map(|(_, last_datetime)| last_datetime).unwrap_or(UNKNOWN_DATETIME.to_string()),
+        manifest_items = manifest_items.join("\n"),
+        spine_items = spine_items.join("\n"),
+    );
+    let mut zip_writer = ZipWriter::new(writer);
+    let () = zip_writer.add_file("mimetype", MIMETYPE.as_bytes())?;
+    let () = zip_writer.add_file("META-INF/container.xml", CONTAINER_XML.as_bytes())?;
+    let () = zip_writer.add_file("OEBPS/content.opf", content_opf.as_bytes())?;
+    let () = zip_writer.add_file("OEBPS/nav.xhtml", get_xhtml("Contents", &nav_body).as_bytes())?;
+    let () = zip_writer.add_file("OEBPS/style.css", STYLESHEET.as_bytes())?;
+    let () = zip_writer.add_file("OEBPS/title.xhtml", get_xhtml(&title, &title_body).as_bytes())?;
+    for (chapter_fname, chapter) in chapter_fnames.iter().zip(chapters.iter()) {
+        let () = zip_writer.add_file(&format!("OEBPS/{}", chapter_fname), chapter.as_bytes())?;
+    }
+    zip_writer.finish()
+}
+
+#[cfg(test)]
+mod ama_epub_tests {
+    use crate::ama_config::AmaConfig;
+    use crate::ama_export::{get_test_exchange, Exchange};
+    use crate::ama_scraper::CommentMeta;
+
+    /// Returns the contents of `name` in a zip written by `ZipWriter`, which stores every file as is.
+    fn get_zipped_file(raw_zip: &[u8], name: &str) -> String {
+        let mut offset: usize = 0;
+        while raw_zip[offset..offset + 4] == [0x50, 0x4b, 0x03, 0x04] {
+            let size: usize = u32::from_le_bytes(raw_zip[offset + 18..offset + 22].try_into().unwrap()) as usize;
+            let name_len: usize = u16::from_le_bytes(raw_zip[offset + 26..offset + 28].try_into().unwrap()) as usize;
+            let contents_offset: usize = offset + 30 + name_len;
+            if &raw_zip[offset + 30..contents_offset] == name.as_bytes() {
+                return String::from_utf8(raw_zip[contents_offset..contents_offset + size].to_vec()).unwrap();
+            }
+            offset = contents_offset + size;
+        }
+        panic!("{} is not in the zip.", name);
+    }
+
+    #[test]
+    fn test_get_exchange_anchor() {
+        assert_eq!(super::get_exchange_anchor("9evw3fne"), "x-9evw3fne");
+        assert_eq!(super::get_exchange_anchor("a b/c"), "x-a_b_c");
+        assert_eq!(super::get_utc_datetime("2019-08-03T17:01:23+00:00"), Some("2019-08-03T17:01:23Z".to_string()));
+        assert_eq!(super::get_utc_datetime("2019-08-03T17:01:23-07:00"), None);
+    }
+
+    #[test]
+    fn test_get_chapter_fnames() {
+        let expected: Vec<&str> = vec!["creator-zo_.xhtml", "creator-zo_-1.xhtml", "creator-daron-nefcy.xhtml"];
+        assert_eq!(super::get_chapter_fnames(&["Zoë", "Zoé", "Daron Nefcy"]), expected);
+        // Each of the two creators gets a chapter of their own.
+        let exchanges: Vec<Exchange> = vec![
+            get_test_exchange("Zoë", "fan_1", "1abc"),
+            get_test_exchange("Zoé", "fan_2", "2def"),
+        ];
+        let mut raw_epub: Vec<u8> = Vec::new();
+        let _ = super::write_epub(&AmaConfig::default(), &exchanges, &mut raw_epub).unwrap();
+        assert!(get_zipped_file(&raw_epub, "OEBPS/creator-zo_.xhtml").contains("<section id=\"x-1abc\">"));
+        assert!(get_zipped_file(&raw_epub, "OEBPS/creator-zo_-1.xhtml").contains("<section id=\"x-2def\">"));
+    }
+
+    #[test]
+    fn test_write_epub() {
+        let exchanges: Vec<Exchange> = vec![
+            Exchange {
+                question_text: "Question & more?".to_string(),
+                answer_text: "First line.\nSecond line.".to_string(),
+                question_meta: CommentMeta { datetime: Some("2019-08-03T18:00:00+00:00".to_string()), ..Default::default() },
+                ..get_test_exchange("Daron Nefcy", "fan_1", "1abc")
+            },
+            Exchange {
+                question_meta: CommentMeta { datetime: Some("2019-08-03T17:00:00+00:00".to_string()), ..Default::default() },
+                ..get_test_exchange("Adam McArthur", "fan_2", "2def")
+            },
+            get_test_exchange("Daron Nefcy", "fan_3", "3ghi"),
+        ];
+        let mut raw_epub: Vec<u8> = Vec::new();
+        let num_files: usize = super::write_epub(&AmaConfig::default(), &exchanges, &mut raw_epub).unwrap();
+        assert_eq!(num_files, 8);
+        // The mimetype comes first, stored, so that readers can recognize the book.
+        assert_eq!(&raw_epub[30..58], b"mimetypeapplication/epub+zip");
+        let container_xml: String = get_zipped_file(&raw_epub, "META-INF/container.xml");
+        assert!(container_xml.contains("full-path=\"OEBPS/content.opf\""));
+        let content_opf: String = get_zipped_file(&raw_epub, "OEBPS/content.opf");
+        assert!(content_opf.contains("<dc:date>2019-08-03</dc:date>"));
+        assert!(content_opf.contains("<meta property=\"dcterms:modified\">2019-08-03T18:00:00Z</meta>"));
+        assert!(content_opf.contains("<dc:contributor>Daron Nefcy</dc:contributor>\n<dc:contributor>Adam McArthur</dc:contributor>"));
+        assert!(content_opf.contains("<dc:subject>r/StarVStheForcesofEvil</dc:subject>"));
+        assert!(content_opf.contains("<itemref idref=\"title\"/>\n<itemref idref=\"chapter-1\"/>\n<itemref idref=\"chapter-2\"/>"));
+        let nav_xhtml: String = get_zipped_file(&raw_epub, "OEBPS/nav.xhtml");
+        assert!(nav_xhtml.contains("<nav epub:type=\"toc\" id=\"toc\">"));
+        assert!(nav_xhtml.contains("<li><a href=\"creator-daron-nefcy.xhtml#x-3ghi\">fan_3</a></li>"));
+        let chapter: String = get_zipped_file(&raw_epub, "OEBPS/creator-daron-nefcy.xhtml");
+        assert!(chapter.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
+        assert!(chapter.contains("<section id=\"x-1abc\">"));
+        assert!(chapter.contains("<p>Question &amp; more?</p>"));
+        assert!(chapter.contains("<p>First line.<br />\nSecond line.</p>"));
+        // The same exchanges make the same book.
+        let mut raw_epub_again: Vec<u8> = Vec::new();
+        let _ = super::write_epub(&AmaConfig::default(), &exchanges, &mut raw_epub_again).unwrap();
+        assert_eq!(raw_epub, raw_epub_again);
+    }
+
+}
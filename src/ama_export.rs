@@ -0,0 +1,265 @@
+//! This module joins the index to the scraped queries, and writes the result in formats that other tools read.
+//! - Exchange: One scraped exchange, with the names and URL from its record in the index.
+//! - ExportFormat: Every format that `export_archive` can write.
+//! - get_exchanges: Joins each record in the index to its scraped query, in index order.
+//! - group_by_creator: Groups exchanges by creator, in the order in which each creator first appears.
+//! - write_json: Writes a single JSON document, with the exchanges grouped by creator.
+//! - write_jsonl: Writes one JSON object per line, one line per exchange.
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::ama_config::AmaConfig;
+use crate::ama_indexer::{self, AmaRecord};
+use crate::ama_scraper::{AmaQuery, CommentMeta};
+
+/// One scraped exchange, joined to its record in the index.
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize)]
+pub struct Exchange {
+    pub cc_name: String,
+    pub fan_name: String,
+    pub url_id: String,
+    pub url: String, // From `ama_indexer::get_url`.
+    pub question_text: String,
+    pub answer_text: String,
+    pub question_markdown: Option<String>, // None if the query was saved before Markdown was kept.
+    pub answer_markdown: Option<String>,
+    pub question_meta: CommentMeta,
+    pub answer_meta: CommentMeta,
+}
+
+/// Every format that `export_archive` can write.
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+pub enum ExportFormat {
+    Json, // One document, grouped by creator.
+    Jsonl, // One exchange per line.
+    Csv, // One row per exchange, with the columns chosen in `CsvOptions`.
+    Tsv, // As Csv, but separated by tabs.
+    Markdown, // A book, with a chapter per creator.
+    Epub, // An e-book, with a chapter per creator.
+}
+
+impl ExportFormat {
+    /// Returns the extension of files written in this format.
+    pub fn get_extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Tsv => "tsv",
+            ExportFormat::Markdown => "md",
+            ExportFormat::Epub => "epub",
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get_extension())
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(format_name: &str) -> std::result::Result<Self, Self::Err> {
+        match format_name.to_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            "csv" => Ok(ExportFormat::Csv),
+            "tsv" => Ok(ExportFormat::Tsv),
+            "md" | "markdown" => Ok(ExportFormat::Markdown),
+            "epub" => Ok(ExportFormat::Epub),
+            _ => Err(format!("'{}' is not one of: json, jsonl, csv, tsv, md, epub", format_name)),
+        }
+    }
+}
+
+/// The document written by `write_json`.
+#[derive(Serialize)]
+struct ArchiveDocument<'a> {
+    subreddit: &'a str,
+    thread_id: &'a str,
+    num_exchanges: usize,
+    creators: Vec<CreatorDocument<'a>>,
+}
+
+#[derive(Serialize)]
+struct CreatorDocument<'a> {
+    cc_name: &'a str,
+    exchanges: Vec<&'a Exchange>,
+}
+
+/// Joins each record in `ama_index` to its query in `ama_queries` by url_id, and keeps the order of
+/// `ama_index`. Returns the joined exchanges, and the records that have no scraped query.
+/// - ama_config: The AMA whose thread the exchanges are in.
+/// - ama_index: Records to join, in the order in which to return them.
+/// - ama_queries: Scraped queries, in any order.
+pub fn get_exchanges(ama_config: &AmaConfig, ama_index: &[AmaRecord], ama_queries: &[AmaQuery]) -> (Vec<Exchange>, Vec<AmaRecord>) {
+    let ama_queries: HashMap<&str, &AmaQuery> = ama_queries.iter()
+        .map(|ama_query| (ama_query.url_id.as_str(), ama_query))
+        .collect();
+    let mut exchanges: Vec<Exchange> = Vec::new();
+    let mut unscraped_records: Vec<AmaRecord> = Vec::new();
+    for ama_record in ama_index.iter() {
+        match ama_queries.get(ama_record.url_id.as_str()) {
+            Some(ama_query) => exchanges.push(
+                Exchange {
+                    cc_name: ama_record.cc_name.clone(),
+                    fan_name: ama_record.fan_name.clone(),
+                    url_id: ama_record.url_id.clone(),
+                    url: ama_indexer::get_url(ama_config, ama_record.url_id.clone()),
+                    question_text: ama_query.question_text.clone().unwrap_or_default(),
+                    answer_text: ama_query.answer_text.clone().unwrap_or_default(),
+                    question_markdown: ama_query.question_markdown.clone(),
+                    answer_markdown: ama_query.answer_markdown.clone(),
+                    question_meta: ama_query.question_meta.clone(),
+                    answer_meta: ama_query.answer_meta.clone(),
+                }
+            ),
+            None => unscraped_records.push(ama_record.clone()),
+        };
+    }
+    (exchanges, unscraped_records)
+}
+
+/// Groups `exchanges` by creator. Creators are listed in the order in which they first appear, and
+/// the exchanges of each keep their order.
+pub fn group_by_creator(exchanges: &[Exchange]) -> Vec<(&str, Vec<&Exchange>)> {
+    let mut creator_groups: Vec<(&str, Vec<&Exchange>)> = Vec::new();
+    for exchange in exchanges.iter() {
+        match creator_groups.iter_mut().find(|(cc_name, _)| *cc_name == exchange.cc_name) {
+            Some((_, creator_exchanges)) => creator_exchanges.push(exchange),
+            None => creator_groups.push((exchange.cc_name.as_str(), vec![exchange])),
+        };
+    }
+    creator_groups
+}
+
+/// Writes `exchanges` to `writer` as a single, pretty-printed JSON document, grouped by creator.
+/// - ama_config: Names the subreddit and thread that the document describes.
+/// - exchanges: Exchanges to write, in order.
+/// - writer: Where to write the document.
+pub fn write_json(ama_config: &AmaConfig, exchanges: &[Exchange], writer: &mut dyn Write) -> io::Result<()> {
+    let archive_document = ArchiveDocument {
+        subreddit: &ama_config.subreddit,
+        thread_id: &ama_config.thread_id,
+        num_exchanges: exchanges.len(),
+        creators: group_by_creator(exchanges).into_iter()
+            .map(|(cc_name, exchanges)| CreatorDocument { cc_name, exchanges })
+            .collect(),
+    };
+    let () = serde_json::to_writer_pretty(&mut *writer, &archive_document)?;
+    writeln!(writer)
+}
+
+/// Writes `exchanges` to `writer` as JSON Lines: one compact object per exchange, one exchange per line.
+/// Each line is written as soon as it is serialized.
+/// - exchanges: Exchanges to write, in order.
+/// - writer: Where to write the lines.
+pub fn write_jsonl(exchanges: &[Exchange], writer: &mut dyn Write) -> io::Result<()> {
+    for exchange in exchanges.iter() {
+        let () = serde_json::to_writer(&mut *writer, exchange)?;
+        let () = writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Returns an exchange between `cc_name` and `fan_name` at `url_id`, with made-up text and no
+/// metadata, for tests to override whichever fields they look at.
+#[cfg(test)]
+pub(crate) fn get_test_exchange(cc_name: &str, fan_name: &str, url_id: &str) -> Exchange {
+    Exchange {
+        cc_name: cc_name.to_string(),
+        fan_name: fan_name.to_string(),
+        url_id: url_id.to_string(),
+        url: format!("https://old.reddit.com/{}", url_id),
+        question_text: format!("Question from {}?", fan_name),
+        answer_text: format!("Answer to {}.", fan_name),
+        question_markdown: None,
+        answer_markdown: None,
+        question_meta: CommentMeta::default(),
+        answer_meta: CommentMeta::default(),
+    }
+}
+
+#[cfg(test)]
+mod ama_export_tests {
+    use crate::ama_config::AmaConfig;
+    use crate::ama_indexer::AmaRecord;
+    use crate::ama_scraper::AmaQuery;
+
+    fn get_ama_index() -> Vec<AmaRecord> {
+        [("url_id1", "cc_name1", "fan_name1"), ("url_id2", "cc_name2", "fan_name2"), ("url_id3", "cc_name1", "fan_name3")].iter()
+            .map(|(url_id, cc_name, fan_name)| AmaRecord { url_id: url_id.to_string(), cc_name: cc_name.to_string(), fan_name: fan_name.to_string() })
+            .collect()
+    }
+
+    fn get_ama_queries() -> Vec<AmaQuery> {
+        ["url_id3", "url_id1", "url_id2"].iter()
+            .map(|url_id| AmaQuery {
+                url_id: url_id.to_string(),
+                question_text: Some(format!("Question for {}?", url_id)),
+                answer_text: Some(format!("Answer for {}.\nWith a \"second\" line.", url_id)),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_get_exchanges() {
+        let ama_config = AmaConfig::default();
+        let ama_queries: Vec<AmaQuery> = get_ama_queries();
+        let (exchanges, unscraped_records) = super::get_exchanges(&ama_config, &get_ama_index(), &ama_queries[..2]);
+        let urlids: Vec<&str> = exchanges.iter().map(|exchange| exchange.url_id.as_str()).collect();
+        assert_eq!(urlids, vec!["url_id1", "url_id3"]);
+        assert_eq!(exchanges[0].url, crate::ama_indexer::get_url(&ama_config, "url_id1".to_string()));
+        assert_eq!(exchanges[1].question_text, "Question for url_id3?");
+        assert_eq!(unscraped_records, vec![get_ama_index()[1].clone()]);
+        let creator_groups = super::group_by_creator(&exchanges);
+        assert_eq!(creator_groups.len(), 1);
+        assert_eq!(creator_groups[0].1.len(), 2);
+    }
+
+    #[test]
+    fn test_write_json() {
+        let ama_config = AmaConfig::default();
+        let (exchanges, _) = super::get_exchanges(&ama_config, &get_ama_index(), &get_ama_queries());
+        let mut raw_json: Vec<u8> = Vec::new();
+        super::write_json(&ama_config, &exchanges, &mut raw_json).unwrap();
+        let archive_document: serde_json::Value = serde_json::from_slice(&raw_json).unwrap();
+        assert_eq!(archive_document["num_exchanges"], 3);
+        let cc_names: Vec<&str> = archive_document["creators"].as_array().unwrap().iter()
+            .map(|creator_document| creator_document["cc_name"].as_str().unwrap())
+            .collect();
+        assert_eq!(cc_names, vec!["cc_name1", "cc_name2"]);
+        assert_eq!(archive_document["creators"][0]["exchanges"][1]["fan_name"], "fan_name3");
+        assert_eq!(archive_document["creators"][0]["exchanges"][0]["answer_meta"]["author"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_write_jsonl() {
+        let ama_config = AmaConfig::default();
+        let (exchanges, _) = super::get_exchanges(&ama_config, &get_ama_index(), &get_ama_queries());
+        let mut raw_jsonl: Vec<u8> = Vec::new();
+        super::write_jsonl(&exchanges, &mut raw_jsonl).unwrap();
+        let raw_jsonl: String = String::from_utf8(raw_jsonl).unwrap();
+        let lines: Vec<serde_json::Value> = raw_jsonl.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), exchanges.len());
+        for (line, exchange) in lines.iter().zip(exchanges.iter()) {
+            assert_eq!(line["url_id"], exchange.url_id.as_str());
+            assert_eq!(line["answer_text"], exchange.answer_text.as_str());
+        }
+    }
+
+}
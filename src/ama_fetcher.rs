@@ -0,0 +1,338 @@
+//! This module defines where fetched pages come from.
+//! - Fetcher: Anything that can return the body of the page at a URL.
+//! - UreqFetcher: Fetches pages from the live site.
+//! - ReplayFetcher: Serves pages from a recorded directory or HAR file, without touching the network.
+//! - RecordingFetcher: Saves every page another Fetcher returns, in the format ReplayFetcher reads.
+//! - get_recording_fname: Returns the filename under which a URL's response is recorded.
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+
+const USER_AGENT: &str = concat!("ama_archiver/", env!("CARGO_PKG_VERSION"));
+
+/// Anything that can return the body of the page at a URL.
+pub trait Fetcher {
+    /// Returns the body of the page at `url` as a str-object.
+    fn fetch(&self, url: &str) -> Result<String>;
+}
+
+/// Fetches pages from the live site with ureq.
+pub struct UreqFetcher {
+    agent: ureq::Agent,
+}
+
+impl UreqFetcher {
+    pub fn new() -> Self {
+        let agent: ureq::Agent = ureq::AgentBuilder::new()
+            .user_agent(USER_AGENT)
+            .timeout_connect(Duration::from_secs(30))
+            .timeout_read(Duration::from_secs(60))
+            .build();
+        UreqFetcher { agent }
+    }
+}
+
+impl Default for UreqFetcher {
+    fn default() -> Self {
+        UreqFetcher::new()
+    }
+}
+
+impl Fetcher for UreqFetcher {
+    fn fetch(&self, url: &str) -> Result<String> {
+        match self.agent.get(url).call() {
+            Ok(resp) => match resp.into_string() {
+                Ok(raw_html) => Ok(raw_html),
+                Err(io_err) => Err(Error::Network { url: url.to_string(), message: io_err.to_string(), retry_after: None }),
+            },
+            Err(reqerr) => Err(Error::network(url, reqerr)),
+        }
+    }
+}
+
+/// Where a ReplayFetcher finds its responses.
+enum ReplaySource {
+    /// One file per URL, named by `get_recording_fname`.
+    Dir(PathBuf),
+    /// Response bodies from a HAR file, keyed by URL.
+    Har(HashMap<String, String>),
+}
+
+/// Serves recorded responses, so that the pipeline can run without touching the network.
+pub struct ReplayFetcher {
+    source: ReplaySource,
+}
+
+// Only the parts of the HAR format that hold the response bodies.
+#[derive(Deserialize)]
+struct Har {
+    log: HarLog,
+}
+
+#[derive(Deserialize)]
+struct HarLog {
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Deserialize)]
+struct HarEntry {
+    request: HarRequest,
+    response: HarResponse,
+}
+
+#[derive(Deserialize)]
+struct HarRequest {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct HarResponse {
+    content: HarContent,
+}
+
+#[derive(Deserialize)]
+struct HarContent {
+    text: Option<String>,
+    encoding: Option<String>,
+}
+
+impl ReplayFetcher {
+    /// Serves responses from a directory written by RecordingFetcher.
+    /// - replay_dir: Directory with one file per recorded URL.
+    pub fn from_dir(replay_dir: impl AsRef<Path>) -> Result<Self> {
+        let replay_dir: &Path = replay_dir.as_ref();
+        if !replay_dir.is_dir() {
+            return Err(Error::Validation(format!("Replay directory {:?} does not exist.", replay_dir)));
+        }
+        Ok(ReplayFetcher { source: ReplaySource::Dir(replay_dir.to_path_buf()) })
+    }
+
+    /// Serves responses from a HAR file, as exported from a browser's network panel.
+    /// Later entries for the same URL replace earlier ones. Bodies saved in base64 are decoded.
+    /// - har_path: Location of the HAR file.
+    pub fn from_har(har_path: impl AsRef<Path>) -> Result<Self> {
+        let har_path: &Path = har_path.as_ref();
+        let raw_har: String = match fs::read_to_string(har_path) {
+            Ok(raw_har) => raw_har,
+            Err(io_err) => return Err(Error::io(har_path, io_err)),
+        };
+        let har: Har = match serde_json::from_str(&raw_har) {
+            Ok(har) => har,
+            Err(json_err) => return Err(Error::Parse(format!("Could not parse HAR file {:?}: {}", har_path, json_err))),
+        };
+        let mut responses: HashMap<String, String> = HashMap::new();
+        for entry in har.log.entries {
+            let text: String = match (entry.response.content.text, entry.response.content.encoding.as_deref()) {
+                (None, _) => continue,
+                (Some(text), None) => text,
+                (Some(text), Some("base64")) => match String::from_utf8(decode_base64(&text)?) {
+                    Ok(text) => text,
+                    Err(_) => return Err(Error::Parse(format!("The response for '{}' in HAR file {:?} is not UTF-8 text.", entry.request.url, har_path))),
+                },
+                (Some(_), Some(encoding)) => return Err(Error::Parse(format!("The response for '{}' in HAR file {:?} has the unsupported encoding '{}'.", entry.request.url, har_path, encoding))),
+            };
+            responses.insert(entry.request.url, text);
+        }
+        Ok(ReplayFetcher { source: ReplaySource::Har(responses) })
+    }
+
+    /// Serves responses from `replay_path`, which is either a recorded directory or a HAR file.
+    pub fn load(replay_path: impl AsRef<Path>) -> Result<Self> {
+        match replay_path.as_ref().is_dir() {
+            true => ReplayFetcher::from_dir(replay_path),
+            false => ReplayFetcher::from_har(replay_path),
+        }
+    }
+}
+
+impl Fetcher for ReplayFetcher {
+    fn fetch(&self, url: &str) -> Result<String> {
+        let not_recorded = || Error::Network {
+            url: url.to_string(),
+            message: "No recorded response.".to_string(),
+            retry_after: None,
+        };
+        match &self.source {
+            ReplaySource::Dir(replay_dir) => {
+                let recording_path: PathBuf = replay_dir.join(get_recording_fname(url));
+                match fs::read_to_string(&recording_path) {
+                    Ok(raw_html) => Ok(raw_html),
+                    Err(io_err) if io_err.kind() == std::io::ErrorKind::NotFound => Err(not_recorded()),
+                    Err(io_err) => Err(Error::io(&recording_path, io_err)),
+                }
+            },
+            ReplaySource::Har(responses) => match responses.get(url) {
+                Some(raw_html) => Ok(raw_html.clone()),
+                None => Err(not_recorded()),
+            },
+        }
+    }
+}
+
+/// Passes every request on to another Fetcher, and saves each response it returns to `record_dir`.
+pub struct RecordingFetcher<F: Fetcher> {
+    inner: F,
+    record_dir: PathBuf,
+}
+
+impl<F: Fetcher> RecordingFetcher<F> {
+    /// - inner: Fetcher whose responses are to be recorded.
+    /// - record_dir: Directory to save them to. Created if it does not exist.
+    pub fn new(inner: F, record_dir: impl AsRef<Path>) -> Result<Self> {
+        let record_dir: &Path = record_dir.as_ref();
+        if let Err(io_err) = fs::create_dir_all(record_dir) {
+            return Err(Error::io(record_dir, io_err));
+        }
+        Ok(RecordingFetcher { inner, record_dir: record_dir.to_path_buf() })
+    }
+}
+
+impl<F: Fetcher> Fetcher for RecordingFetcher<F> {
+    fn fetch(&self, url: &str) -> Result<String> {
+        let raw_html: String = self.inner.fetch(url)?;
+        let recording_path: PathBuf = self.record_dir.join(get_recording_fname(url));
+        match fs::write(&recording_path, &raw_html) {
+            Ok(()) => Ok(raw_html),
+            Err(io_err) => Err(Error::io(&recording_path, io_err)),
+        }
+    }
+}
+
+/// Decodes `encoded` from standard base64, as HAR files save binary bodies. Whitespace is ignored,
+/// and the '=' padding at the end may be left off.
+fn decode_base64(encoded: &str) -> Result<Vec<u8>> {
+    let mut decoded: Vec<u8> = Vec::with_capacity(encoded.len() / 4 * 3);
+    let mut buffer: u32 = 0;
+    let mut num_bits: u32 = 0;
+    let mut num_padding: usize = 0;
+    for c in encoded.chars().filter(|c| !c.is_ascii_whitespace()) {
+        let value: u32 = match c {
+            'A'..='Z' => c as u32 - 'A' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 26,
+            '0'..='9' => c as u32 - '0' as u32 + 52,
+            '+' => 62,
+            '/' => 63,
+            '=' => {
+                num_padding += 1;
+                continue;
+            },
+            c => return Err(Error::Parse(format!("'{}' is not a base64 character.", c))),
+        };
+        if num_padding > 0 {
+            return Err(Error::Parse("Base64 continues after its padding.".to_string()));
+        }
+        // Every character gives 6 bits, and every 8 of them make a byte.
+        buffer = (buffer << 6) | value;
+        num_bits += 6;
+        if num_bits >= 8 {
+            num_bits -= 8;
+            decoded.push((buffer >> num_bits) as u8);
+            buffer &= (1 << num_bits) - 1;
+        }
+    }
+    // A lone character at the end is 6 bits, too few for a byte.
+    if num_bits >= 6 || num_padding > 2 {
+        return Err(Error::Parse("Base64 ends in the middle of a byte.".to_string()));
+    }
+    Ok(decoded)
+}
+
+/// Returns the filename under which the response for `url` is recorded, i.e. the URL without its
+/// scheme, with every character other than letters, digits, '.' and '-' replaced by '_'.
+/// - url: URL whose response is recorded.
+pub fn get_recording_fname(url: &str) -> String {
+    let url_rest: &str = match url.split_once("://") {
+        Some((_, url_rest)) => url_rest,
+        None => url,
+    };
+    url_rest.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod ama_fetcher_tests {
+    use std::fs;
+    use super::Fetcher;
+
+    const REPLAY_DIR: &str = "tests/fixtures/replay";
+
+    #[test]
+    fn test_get_recording_fname() {
+        let url: &str = "https://old.reddit.com/r/testama/comments/abc123/test_ama/c00001/?context=3";
+        let expected: &str = "old.reddit.com_r_testama_comments_abc123_test_ama_c00001__context_3";
+        assert_eq!(super::get_recording_fname(url), expected);
+    }
+
+    #[test]
+    fn test_replay_from_dir() {
+        let fetcher = super::ReplayFetcher::from_dir(REPLAY_DIR).unwrap();
+        let url: &str = "https://old.reddit.com/r/StarVStheForcesofEvil/comments/cll9u5/star_vs_the_forces_of_evil_ask_me_anything/evw3fne/?context=3";
+        let raw_html: String = fetcher.fetch(url).unwrap();
+        assert!(raw_html.contains("VeronicaMewniFan"));
+        match fetcher.fetch("https://old.reddit.com/not/recorded/") {
+            Err(super::Error::Network { .. }) => (),
+            other => panic!("Expected a network error, got: {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_replay_from_har() {
+        let har_path: &str = "output/ama_fetcher-har_test.har";
+        let raw_har: &str = r#"{"log": {"version": "1.2", "entries": [
+            {"request": {"method": "GET", "url": "https://example.com/a"}, "response": {"status": 200, "content": {"mimeType": "text/html", "text": "<p>a</p>"}}},
+            {"request": {"method": "GET", "url": "https://example.com/b"}, "response": {"status": 200, "content": {"mimeType": "text/html", "text": "PHA+YjwvcD4=", "encoding": "base64"}}}
+        ]}}"#;
+        fs::write(har_path, raw_har).unwrap();
+        let fetcher = super::ReplayFetcher::load(har_path).unwrap();
+        let _ = fs::remove_file(har_path);
+        assert_eq!(fetcher.fetch("https://example.com/a").unwrap(), "<p>a</p>");
+        assert_eq!(fetcher.fetch("https://example.com/b").unwrap(), "<p>b</p>");
+        // Encodings that cannot be decoded are refused, rather than skipped.
+        let raw_har: &str = r#"{"log": {"version": "1.2", "entries": [
+            {"request": {"method": "GET", "url": "https://example.com/c"}, "response": {"status": 200, "content": {"mimeType": "text/html", "text": "...", "encoding": "gzip"}}}
+        ]}}"#;
+        fs::write(har_path, raw_har).unwrap();
+        let load_result = super::ReplayFetcher::load(har_path);
+        let _ = fs::remove_file(har_path);
+        assert!(matches!(load_result, Err(super::Error::Parse(_))));
+    }
+
+    #[test]
+    fn test_decode_base64() {
+        let cases: Vec<(&str, &[u8])> = vec![
+            ("", b""),
+            ("YQ==", b"a"),
+            ("YWI=", b"ab"),
+            ("YWJj", b"abc"),
+            ("YWJjZA", b"abcd"),
+            ("PHA+\nYjwvcD4=", b"<p>b</p>"),
+            ("//79", &[0xff, 0xfe, 0xfd]),
+        ];
+        for (encoded, expected) in cases {
+            assert_eq!(super::decode_base64(encoded).unwrap(), expected, "{:?}", encoded);
+        }
+        for encoded in ["Y", "YQ==YQ==", "YQ*=", "Y==="] {
+            assert!(super::decode_base64(encoded).is_err(), "{:?}", encoded);
+        }
+    }
+
+    #[test]
+    fn test_recording_fetcher() {
+        let record_dir: &str = "output/ama_fetcher-record_test";
+        let url: &str = "https://old.reddit.com/r/StarVStheForcesofEvil/comments/cll9u5/star_vs_the_forces_of_evil_ask_me_anything/evw3fne/?context=3";
+        let replay_fetcher = super::ReplayFetcher::from_dir(REPLAY_DIR).unwrap();
+        let recording_fetcher = super::RecordingFetcher::new(replay_fetcher, record_dir).unwrap();
+        let expected: String = recording_fetcher.fetch(url).unwrap();
+        // What was recorded can be replayed.
+        let actual: String = super::ReplayFetcher::from_dir(record_dir).unwrap().fetch(url).unwrap();
+        let _ = fs::remove_dir_all(record_dir);
+        assert_eq!(actual, expected);
+    }
+
+}
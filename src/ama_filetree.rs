@@ -0,0 +1,445 @@
+//! This module names the directories of the exported filetree, and keeps track of which record each was written for.
+//! - sanitize_name: Turns a creator or fan name into a directory name that is safe on every filesystem.
+//! - FiletreeEntry: Where one record was written, and the names it was written for.
+//! - get_filetree_entries: Assigns a distinct directory to every record, resolving collisions.
+//! - save_manifest: Saves the entries of a filetree to its manifest.
+//! - load_manifest: Loads the entries of a filetree from its manifest.
+//! - FiletreeExchange: The text files of one exchange, as read back from the filetree.
+//! - load_filetree: Reads back every exchange in a filetree, with or without a manifest.
+//! - EditedField: A field of an exchange that can be edited by hand.
+//! - FieldEdit: A field that was edited by hand in the filetree.
+//! - find_edits: Compares the filetree against the database, and lists the fields that differ.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ama_indexer::AmaRecord;
+use crate::ama_scraper::AmaQuery;
+use crate::error::{Error, Result};
+
+/// Filename of the manifest, in the root of the filetree.
+pub const MANIFEST_FNAME: &str = "manifest.json";
+/// Longest directory name, in bytes, that `sanitize_name` returns. Leaves room for a collision suffix.
+const MAX_NAME_LEN: usize = 100;
+/// Names that Windows reserves for devices, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Where one record was written, relative to the root of the filetree, and the names it was written for.
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct FiletreeEntry {
+    pub path: String, // '{cc_dirname}/{fan_dirname}', always with '/'.
+    pub cc_name: String,
+    pub fan_name: String,
+    pub url_id: String,
+}
+
+/// Turns `name` into a directory name that is safe on Linux, macOS and Windows:
+/// - Path separators, characters that Windows forbids, and control characters become '_'.
+/// - Leading spaces, and trailing spaces and dots, are dropped, so '.' and '..' cannot escape the tree.
+/// - Names that Windows reserves for devices get a '_' before any extension, e.g. 'Com1.txt' becomes 'Com1_.txt'.
+/// - Names longer than MAX_NAME_LEN bytes are cut short, and empty names become '_'.
+///
+/// The same name always gives the same result, but two names may give the same result too. Those
+/// collisions are resolved by `get_filetree_entries`.
+pub fn sanitize_name(name: &str) -> String {
+    let mut dirname: String = name.chars()
+        .map(|c| match c {
+            '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    dirname = dirname.trim_start_matches(' ').trim_end_matches(['.', ' ']).to_string();
+    if dirname.len() > MAX_NAME_LEN {
+        let mut end: usize = MAX_NAME_LEN;
+        while !dirname.is_char_boundary(end) {
+            end -= 1;
+        }
+        dirname.truncate(end);
+        dirname = dirname.trim_end_matches(['.', ' ']).to_string();
+    }
+    // Windows reserves the name whatever its extension, so the '_' goes before the first dot.
+    let stem: &str = dirname.split('.').next().unwrap_or("");
+    if RESERVED_NAMES.iter().any(|reserved_name| reserved_name.eq_ignore_ascii_case(stem)) {
+        dirname.insert(stem.len(), '_');
+    }
+    if dirname.is_empty() {
+        dirname.push('_');
+    }
+    dirname
+}
+
+/// Assigns each name in `names` a sanitized directory name that no other name in `names` shares,
+/// even on filesystems that ignore case. Names that collide are taken in sorted order: the first
+/// keeps its sanitized name, and the others get '~2', '~3', and so on, skipping any that another
+/// name has already.
+fn get_dirnames<'a>(names: impl Iterator<Item = &'a str>) -> BTreeMap<&'a str, String> {
+    let mut collisions: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for name in names {
+        let colliding_names: &mut Vec<&str> = collisions.entry(sanitize_name(name).to_lowercase()).or_default();
+        if !colliding_names.contains(&name) {
+            colliding_names.push(name);
+        }
+    }
+    let mut dirnames: BTreeMap<&str, String> = BTreeMap::new();
+    for colliding_names in collisions.values_mut() {
+        colliding_names.sort();
+        dirnames.insert(colliding_names[0], sanitize_name(colliding_names[0]));
+    }
+    // A name may already look like a renamed one, e.g. 'x~2', so each rename skips the taken ones.
+    let mut taken_dirnames: HashSet<String> = dirnames.values().map(|dirname| dirname.to_lowercase()).collect();
+    for colliding_names in collisions.values() {
+        let mut copyno: usize = 1;
+        for name in colliding_names.iter().skip(1) {
+            let dirname: String = loop {
+                copyno += 1;
+                let dirname: String = format!("{}~{}", sanitize_name(name), copyno);
+                if taken_dirnames.insert(dirname.to_lowercase()) {
+                    break dirname;
+                }
+            };
+            dirnames.insert(name, dirname);
+        }
+    }
+    dirnames
+}
+
+/// Assigns every record in `ama_index` the directory '{cc_dirname}/{fan_dirname}' that it is to be
+/// written to, in the same order. Creators are given distinct directories, and so are the fans
+/// under each creator. The result depends only on the names in `ama_index`, not on their order.
+/// - ama_index: Records to be written.
+pub fn get_filetree_entries(ama_index: &[AmaRecord]) -> Vec<FiletreeEntry> {
+    let cc_dirnames: BTreeMap<&str, String> = get_dirnames(ama_index.iter().map(|ama_record| ama_record.cc_name.as_str()));
+    let mut fan_dirnames: BTreeMap<&str, BTreeMap<&str, String>> = BTreeMap::new();
+    for cc_name in cc_dirnames.keys() {
+        let fan_names = ama_index.iter()
+            .filter(|ama_record| ama_record.cc_name == *cc_name)
+            .map(|ama_record| ama_record.fan_name.as_str());
+        fan_dirnames.insert(cc_name, get_dirnames(fan_names));
+    }
+    ama_index.iter()
+        .map(|ama_record| FiletreeEntry {
+            path: format!("{}/{}", cc_dirnames[ama_record.cc_name.as_str()], fan_dirnames[ama_record.cc_name.as_str()][ama_record.fan_name.as_str()]),
+            cc_name: ama_record.cc_name.clone(),
+            fan_name: ama_record.fan_name.clone(),
+            url_id: ama_record.url_id.clone(),
+        })
+        .collect()
+}
+
+/// Saves `filetree_entries` to the manifest in `root_path`, so that each directory can be traced
+/// back to the names it was written for.
+/// - root_path: Root of the filetree.
+/// - filetree_entries: Directories that were written.
+pub fn save_manifest(root_path: &Path, filetree_entries: &[FiletreeEntry]) -> Result<()> {
+    let manifest_path = root_path.join(MANIFEST_FNAME);
+    let raw_json: String = match serde_json::to_string_pretty(filetree_entries) {
+        Ok(raw_json) => raw_json,
+        Err(json_err) => return Err(Error::Parse(format!("Could not serialize the manifest: {}", json_err))),
+    };
+    match fs::write(&manifest_path, raw_json) {
+        Ok(()) => Ok(()),
+        Err(io_err) => Err(Error::io(&manifest_path, io_err)),
+    }
+}
+
+/// Loads the entries of the filetree in `root_path` from its manifest.
+/// - root_path: Root of the filetree.
+pub fn load_manifest(root_path: &Path) -> Result<Vec<FiletreeEntry>> {
+    let manifest_path = root_path.join(MANIFEST_FNAME);
+    let raw_json: String = match fs::read_to_string(&manifest_path) {
+        Ok(raw_json) => raw_json,
+        Err(io_err) => return Err(Error::io(&manifest_path, io_err)),
+    };
+    match serde_json::from_str(&raw_json) {
+        Ok(filetree_entries) => Ok(filetree_entries),
+        Err(json_err) => Err(Error::Parse(format!("Could not parse '{}': {}", manifest_path.display(), json_err))),
+    }
+}
+
+/// The text files of one exchange, as read back from the filetree. Files that are missing are None.
+#[derive(PartialEq)]
+#[derive(Debug)]
+pub struct FiletreeExchange {
+    pub entry: FiletreeEntry,
+    pub question_text: Option<String>,
+    pub answer_text: Option<String>,
+    pub url_id: Option<String>,
+}
+
+/// Reads one file of an exchange, if it is there.
+fn read_text_file(text_path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(text_path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(io_err) if io_err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(io_err) => Err(Error::io(text_path, io_err)),
+    }
+}
+
+/// Returns the names of the directories in `dir_path`, sorted.
+fn get_dirnames_in(dir_path: &Path) -> Result<Vec<String>> {
+    let dir_iter = match fs::read_dir(dir_path) {
+        Ok(dir_iter) => dir_iter,
+        Err(io_err) => return Err(Error::io(dir_path, io_err)),
+    };
+    let mut dirnames: Vec<String> = Vec::new();
+    for dir_entry in dir_iter {
+        let dir_entry = match dir_entry {
+            Ok(dir_entry) => dir_entry,
+            Err(io_err) => return Err(Error::io(dir_path, io_err)),
+        };
+        if dir_entry.path().is_dir() {
+            dirnames.push(dir_entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    dirnames.sort();
+    Ok(dirnames)
+}
+
+/// Reads back every exchange in the filetree at `root_path`. The manifest tells which names each
+/// directory was written for. Filetrees written before there was a manifest are walked instead,
+/// and their directories taken to be named '{cc_name}/{fan_name}' verbatim.
+/// - root_path: Root of the filetree.
+pub fn load_filetree(root_path: &Path) -> Result<Vec<FiletreeExchange>> {
+    let filetree_entries: Vec<FiletreeEntry> = match root_path.join(MANIFEST_FNAME).exists() {
+        true => load_manifest(root_path)?,
+        false => {
+            let mut filetree_entries: Vec<FiletreeEntry> = Vec::new();
+            for cc_name in get_dirnames_in(root_path)? {
+                for fan_name in get_dirnames_in(&root_path.join(&cc_name))? {
+                    filetree_entries.push(
+                        FiletreeEntry {
+                            path: format!("{}/{}", cc_name, fan_name),
+                            cc_name: cc_name.clone(),
+                            fan_name,
+                            url_id: String::new(),
+                        }
+                    );
+                }
+            }
+            filetree_entries
+        },
+    };
+    let mut filetree_exchanges: Vec<FiletreeExchange> = Vec::new();
+    for filetree_entry in filetree_entries {
+        let record_path: PathBuf = root_path.join(&filetree_entry.path);
+        filetree_exchanges.push(
+            FiletreeExchange {
+                question_text: read_text_file(&record_path.join("question_text.txt"))?,
+                answer_text: read_text_file(&record_path.join("answer_text.txt"))?,
+                url_id: read_text_file(&record_path.join("url_id.txt"))?.map(|url_id| url_id.trim().to_string()),
+                entry: filetree_entry,
+            }
+        );
+    }
+    Ok(filetree_exchanges)
+}
+
+/// A field of an exchange that can be edited by hand, by the name of its column.
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+pub enum EditedField {
+    QuestionText,
+    AnswerText,
+    UrlId,
+}
+
+impl EditedField {
+    /// Returns the name of the column that this field is saved in.
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            EditedField::QuestionText => "question_text",
+            EditedField::AnswerText => "answer_text",
+            EditedField::UrlId => "url_id",
+        }
+    }
+}
+
+impl fmt::Display for EditedField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.get_name())
+    }
+}
+
+impl FromStr for EditedField {
+    type Err = Error;
+
+    fn from_str(field_name: &str) -> Result<Self> {
+        match field_name {
+            "question_text" => Ok(EditedField::QuestionText),
+            "answer_text" => Ok(EditedField::AnswerText),
+            "url_id" => Ok(EditedField::UrlId),
+            _ => Err(Error::Parse(format!("Unknown field '{}'. Expected 'question_text', 'answer_text' or 'url_id'.", field_name))),
+        }
+    }
+}
+
+/// A field of one exchange that differs between the filetree and the database.
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct FieldEdit {
+    pub cc_name: String,
+    pub fan_name: String,
+    pub url_id: String, // url_id of the record before the edit.
+    pub field: EditedField,
+    pub old_value: Option<String>,
+    pub new_value: String,
+}
+
+impl fmt::Display for FieldEdit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.field {
+            EditedField::UrlId => write!(f, "{} to {}: 'url_id' {} -> {}", self.fan_name, self.cc_name, self.url_id, self.new_value),
+            field => write!(f, "{} to {}: '{}' of 'url_id' {} was edited", self.fan_name, self.cc_name, field, self.url_id),
+        }
+    }
+}
+
+/// Compares every exchange in `filetree_exchanges` against the record with the same names in
+/// `ama_index`, and its query in `ama_queries`. Returns every field that differs, with the edits to
+/// each exchange's text ahead of the edit to its url_id, and the paths of the exchanges that match
+/// no scraped record.
+/// - filetree_exchanges: Exchanges read back from the filetree.
+/// - ama_index: Records in the database.
+/// - ama_queries: Queries in the database.
+pub fn find_edits(filetree_exchanges: &[FiletreeExchange], ama_index: &[AmaRecord], ama_queries: &[AmaQuery]) -> (Vec<FieldEdit>, Vec<String>) {
+    let ama_queries: HashMap<&str, &AmaQuery> = ama_queries.iter().map(|ama_query| (ama_query.url_id.as_str(), ama_query)).collect();
+    let mut field_edits: Vec<FieldEdit> = Vec::new();
+    let mut unmatched_paths: Vec<String> = Vec::new();
+    for filetree_exchange in filetree_exchanges {
+        let filetree_entry: &FiletreeEntry = &filetree_exchange.entry;
+        let matched = ama_index.iter()
+            .find(|ama_record| ama_record.cc_name == filetree_entry.cc_name && ama_record.fan_name == filetree_entry.fan_name)
+            .and_then(|ama_record| Some((ama_record, *ama_queries.get(ama_record.url_id.as_str())?)));
+        let (ama_record, ama_query): (&AmaRecord, &AmaQuery) = match matched {
+            Some(matched) => matched,
+            None => {
+                unmatched_paths.push(filetree_entry.path.clone());
+                continue;
+            },
+        };
+        let get_field_edit = |field: EditedField, old_value: Option<&String>, new_value: &String| FieldEdit {
+            cc_name: ama_record.cc_name.clone(),
+            fan_name: ama_record.fan_name.clone(),
+            url_id: ama_record.url_id.clone(),
+            field,
+            old_value: old_value.cloned(),
+            new_value: new_value.clone(),
+        };
+        for (field, old_value, new_value) in [
+            (EditedField::QuestionText, &ama_query.question_text, &filetree_exchange.question_text),
+            (EditedField::AnswerText, &ama_query.answer_text, &filetree_exchange.answer_text),
+        ] {
+            if let Some(new_value) = new_value {
+                if old_value.as_ref() != Some(new_value) {
+                    field_edits.push(get_field_edit(field, old_value.as_ref(), new_value));
+                }
+            }
+        }
+        if let Some(new_url_id) = &filetree_exchange.url_id {
+            if !new_url_id.is_empty() && *new_url_id != ama_record.url_id {
+                field_edits.push(get_field_edit(EditedField::UrlId, Some(&ama_record.url_id), new_url_id));
+            }
+        }
+    }
+    (field_edits, unmatched_paths)
+}
+
+#[cfg(test)]
+mod ama_filetree_tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::FiletreeEntry;
+    use crate::ama_indexer::AmaRecord;
+
+    fn get_ama_record(cc_name: &str, fan_name: &str, url_id: &str) -> AmaRecord {
+        AmaRecord {
+            cc_name: cc_name.to_string(),
+            fan_name: fan_name.to_string(),
+            url_id: url_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        let cases: Vec<(&str, &str)> = vec![
+            ("Daron Nefcy", "Daron Nefcy"),
+            ("Joe_Zt (again)", "Joe_Zt (again)"),
+            ("..", "_"),
+            (".", "_"),
+            ("", "_"),
+            ("../../etc", ".._.._etc"),
+            ("a/b\\c", "a_b_c"),
+            ("what?: \"no\"", "what__ _no_"),
+            ("trailing dots...", "trailing dots"),
+            ("  spaced  ", "spaced"),
+            ("tab\there", "tab_here"),
+            ("con", "con_"),
+            ("Com1.txt", "Com1_.txt"),
+            ("console", "console"),
+            ("ñandú", "ñandú"),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(super::sanitize_name(name), expected, "{:?}", name);
+        }
+        // Long names are cut on a character boundary.
+        let long_name: String = "é".repeat(80);
+        assert_eq!(super::sanitize_name(&long_name), "é".repeat(50));
+    }
+
+    #[test]
+    fn test_get_filetree_entries() {
+        let ama_index: Vec<AmaRecord> = vec![
+            get_ama_record("cc_name1", "fan?", "url_id1"),
+            get_ama_record("cc_name1", "fan:", "url_id2"),
+            get_ama_record("cc_name1", "FAN_", "url_id3"),
+            get_ama_record("cc_name2", "fan?", "url_id4"),
+            get_ama_record("CC_NAME1", "..", "url_id5"),
+        ];
+        let actual: Vec<String> = super::get_filetree_entries(&ama_index).into_iter().map(|filetree_entry| filetree_entry.path).collect();
+        let expected: Vec<&str> = vec!["cc_name1~2/fan_~3", "cc_name1~2/fan_~2", "cc_name1~2/FAN_", "cc_name2/fan_", "CC_NAME1/_"];
+        assert_eq!(actual, expected);
+        // The order of the records does not change their directories.
+        let mut reversed_index: Vec<AmaRecord> = ama_index.clone();
+        reversed_index.reverse();
+        let mut reversed: Vec<String> = super::get_filetree_entries(&reversed_index).into_iter().map(|filetree_entry| filetree_entry.path).collect();
+        reversed.reverse();
+        assert_eq!(reversed, expected);
+        // A name that looks renamed already keeps its directory.
+        let ama_index: Vec<AmaRecord> = vec![
+            get_ama_record("cc_name1", "x", "url_id1"),
+            get_ama_record("cc_name1", "X", "url_id2"),
+            get_ama_record("cc_name1", "x~2", "url_id3"),
+        ];
+        let actual: Vec<String> = super::get_filetree_entries(&ama_index).into_iter().map(|filetree_entry| filetree_entry.path).collect();
+        assert_eq!(actual, vec!["cc_name1/x~3", "cc_name1/X", "cc_name1/x~2"]);
+    }
+
+    #[test]
+    fn test_manifest() {
+        let root_path: &Path = Path::new("output/ama_filetree-manifest_test");
+        let _ = fs::create_dir_all(root_path);
+        let expected: Vec<FiletreeEntry> = super::get_filetree_entries(&[get_ama_record("cc_name1", "fan/name", "url_id1")]);
+        let () = super::save_manifest(root_path, &expected).unwrap();
+        let actual: Vec<FiletreeEntry> = super::load_manifest(root_path).unwrap();
+        let _ = fs::remove_dir_all(root_path);
+        assert_eq!(actual, expected);
+        assert_eq!(actual[0].path, "cc_name1/fan_name");
+    }
+
+}
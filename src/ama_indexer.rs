@@ -2,7 +2,8 @@ use std::path::Path;
 use std::fs;
 
 /// Tries to remove a file, and reports to stdout the results of the operation.
-fn remove_file(full_path: impl AsRef<Path> + std::fmt::Debug) -> () {
+#[cfg(test)]
+fn remove_file(full_path: impl AsRef<Path> + std::fmt::Debug) {
     match fs::remove_file(&full_path) {
         Ok(()) => {
             println!("File removed: {:?}", &full_path);
@@ -14,28 +15,32 @@ fn remove_file(full_path: impl AsRef<Path> + std::fmt::Debug) -> () {
 }
 
 /// This module defines functions that will help compile and validate an index for the Q&A session exchanges.
-/// - fetch_raw_index: Fetches HTML from the link-compendium URL, and returns it as a str.
+/// - fetch_raw_index: Fetches HTML from the link-compendium URL with a Fetcher, and returns it as a str.
 /// - save_raw_index: Saves the raw index into the specified output file.
 /// - compile_ama_index: Compiles the Q&A index into a list of dict objects.
-/// - create_db: Creates a database file to store all the data in.
-/// - save_ama_index: Saves a Q&A index into a database file. 
-/// - get_urlid: Returns the url ID for a given URL.
+/// - upsert_ama_index: Saves a Q&A index over the one in a database file, and reports what changed.
+/// - get_urlid: Returns the url ID for a given URL in the AMA thread.
 /// - get_url: Returns full URL for the given url_id (i.e. str that completes the url template, and transforms it into a functioning URL)
+/// - identify_duplicates: Finds every url_id that more than one record links to.
+/// - propose_corrections: Guesses which records of a duplicated url_id were linked by mistake.
 //pub mod ama_indexer {
-    use ureq;
+    use std::cmp::Ordering;
+    use std::fmt;
+
     use ego_tree::NodeRef;
     //use std::fs;
     use scraper::{Html, Selector, ElementRef};
-    use rusqlite;
     //use std::path::Path;
 
-    // '/'-split list must be modified:
-    // -2: '' -> {url_id}
-    const URL_TEMPLATE: &str = "https://old.reddit.com/r/StarVStheForcesofEvil/comments/cll9u5/star_vs_the_forces_of_evil_ask_me_anything//?context=3";
+    use crate::ama_config::{self, AmaConfig};
+    use crate::ama_fetcher::Fetcher;
+    use crate::ama_store::Store;
+    use crate::error::{Error, Result};
 
     /// Contains fields to store data parsed from index.
     #[derive(PartialEq)]
     #[derive(Debug)]
+    #[derive(Clone)]
     pub struct AmaRecord {
         pub cc_name: String, // ElementRef::inner_html
         pub fan_name: String, // ElementRef::inner_html
@@ -43,35 +48,34 @@ fn remove_file(full_path: impl AsRef<Path> + std::fmt::Debug) -> () {
     }
 
     /// Fetches HTML from specified URL, and returns it as a str-object.
+    /// - fetcher: Where to get the page from, e.g. the live site or a recording.
     /// - url: Source to get HTML from.
-    pub fn fetch_raw_index(url: &str) -> String {
-        // use ureq to get text of Lc_URL
+    pub fn fetch_raw_index(fetcher: &dyn Fetcher, url: &str) -> Result<String> {
+        // use the fetcher to get text of Lc_URL
         // save text into html file in output
         // ensure output/ exists beforehand
-        let request: ureq::Request = ureq::get(url);
-        let raw_html: String = match request.call() {
-            Ok(resp) => resp.into_string().unwrap(),
-            Err(reqerr) => panic!("Unable to get response from '{}': {:?}", url, reqerr),
-        };
-        raw_html
+        let raw_html: String = fetcher.fetch(url)?;
+        Ok(raw_html)
     }
 
     /// Saves 'raw_index' str to the file './odir_path/ofname'.
     /// - raw_index: Raw HTML as str.
     /// - odir_name: Path of output directory.
     /// - lc_fname: Name of file to save `raw_index` to.
-    pub fn save_raw_index(raw_html: String, odir_name: &str, lc_fname: &str) -> () {
+    pub fn save_raw_index(raw_html: String, odir_name: &str, lc_fname: &str) -> Result<()> {
         // create 'output' directory
         // save 'raw_html' to {oDIR_NAME}/{lC_FNAME}.html
         let () = match fs::create_dir(odir_name) {
             Ok(()) => println!("'{}' directory successfully created.", odir_name),
-            Err(_) => eprintln!("Error creating '{}' directory. Does it exist already?", odir_name),
+            Err(io_err) if io_err.kind() == std::io::ErrorKind::AlreadyExists => (),
+            Err(io_err) => return Err(Error::io(odir_name, io_err)),
         };
         let full_opath: String = format!("{}/{}.html", odir_name, lc_fname);
         let () = match fs::write(&full_opath, raw_html) {
             Ok(()) => println!("Contents of (raw_html, String) written to '{}'", full_opath),
-            Err(_) => eprintln!("Unable to write (raw_html, String) to '{}'", full_opath),
+            Err(io_err) => return Err(Error::io(&full_opath, io_err)),
         };
+        Ok(())
     }
 
     /// Compiles index := {cc_name: [name for name in fan_names]} from HTML of the form: <p><strong>cc_name1</strong></p>
@@ -82,49 +86,46 @@ fn remove_file(full_path: impl AsRef<Path> + std::fmt::Debug) -> () {
     /// <p><strong>cc_name2</strong></p>
     /// - raw_index: Raw HTML as str.
     /// - start_text: The text to search <strong> tags for.
-    pub fn compile_ama_index(raw_html: String, start_text: &str) -> Vec<AmaRecord> {
+    pub fn compile_ama_index(raw_html: String, start_text: &str) -> Result<Vec<AmaRecord>> {
         // locate the starting node
         let parsed_html: Html = Html::parse_document(&raw_html);
-        let strong_selector: Selector = Selector::parse("strong").unwrap();
+        let strong_selector: Selector = Selector::parse("strong").expect("'strong' is a valid selector");
         let mut node_opt: Option<NodeRef<_>> = None; // used to store the 'current_node'
         //let mut current_node: ElementRef;
         for strong in parsed_html.select(&strong_selector) {
-            if strong.inner_html() == start_text.to_string() {
+            if strong.inner_html() == start_text {
                 println!("strong: {:?}", strong.inner_html());
-                node_opt = Some(strong.parent().unwrap());
+                node_opt = strong.parent();
                 break;
             }
         }
         let current_node = match node_opt {
             Some(node) => node,
-            None => panic!("<strong> node that contains '{}' not found. Fatal. Aborting.", start_text),
+            None => return Err(Error::Parse(format!("<strong> node that contains '{}' not found.", start_text))),
         };
         // drop(node_opt); // warned against this.
         // begin to compile records
         let mut ama_index: Vec<AmaRecord> = Vec::new();
-        let mut cc_name: String = start_text.to_string();
-        assert_eq!(
-            match cc_name.pop() {
-                Some(colon) => colon,
-                None => panic!("<strong> tag was empty. Inspect."),
-            },
-        ':');
+        let mut cc_name: String = get_cc_name(start_text)?;
         for p in current_node.next_siblings() {
             if let Some(node) = p.first_child() {
-                let element_ref: ElementRef = ElementRef::wrap(node).unwrap();
+                let element_ref: ElementRef = match ElementRef::wrap(node) {
+                    Some(element_ref) => element_ref,
+                    None => {
+                        eprintln!("Unexpected text found. Neither strong nor a: {:?}", node.value());
+                        break;
+                    },
+                };
                 match element_ref.value().name() {
                     "strong" => {
-                        cc_name = element_ref.inner_html();
-                        assert_eq!(
-                            match cc_name.pop() {
-                                Some(colon) => colon,
-                                None => panic!("<strong> tag was empty. Inspect."),
-                            },
-                        ':');
+                        cc_name = get_cc_name(&element_ref.inner_html())?;
                     },
                     "a" => {
                         let fan_name: String = element_ref.inner_html();
-                        let url_id: String = element_ref.attr("href").unwrap().to_string();
+                        let url_id: String = match element_ref.attr("href") {
+                            Some(href) => href.to_string(),
+                            None => return Err(Error::Parse(format!("Link for '{}' has no 'href'.", fan_name))),
+                        };
                         let ama_record: AmaRecord = AmaRecord {
                             cc_name: cc_name.clone(),
                             fan_name,
@@ -139,113 +140,221 @@ fn remove_file(full_path: impl AsRef<Path> + std::fmt::Debug) -> () {
                 }
             }
         };
-        ama_index
+        Ok(ama_index)
+    }
+
+    /// Strips the trailing colon off the text of a <strong> heading, which must have one.
+    fn get_cc_name(strong_text: &str) -> Result<String> {
+        match strong_text.strip_suffix(':') {
+            Some(cc_name) => Ok(cc_name.to_string()),
+            None => Err(Error::Parse(format!("<strong> tag '{}' does not end with a colon. Inspect.", strong_text))),
+        }
+    }
+
+    /// Contains a url_id that several records link to, although each exchange has a comment of its own.
+    #[derive(PartialEq)]
+    #[derive(Debug)]
+    pub struct DuplicateUrlId {
+        pub url_id: String,
+        pub positions: Vec<usize>, // Where the records that link to it are in the index.
+    }
+
+    /// Contains a record that likely links to the wrong comment, and where its own comment likely is.
+    #[derive(PartialEq)]
+    #[derive(Debug)]
+    pub struct ProposedCorrection {
+        pub ama_record: AmaRecord,
+        pub after_url_id: Option<String>, // url_id of the record before it under the same creator.
+        pub before_url_id: Option<String>, // url_id of the record after it under the same creator.
+        pub reason: String,
     }
 
-    /*
-    fn identify_duplicates(ama_index_ref: &Vec<AmaRecord>) -> () {
-        // Identify duplicate url_id rows:
-        // - SELECT * FROM ama_index WHERE url_id IN (SELECT url_id FROM ama_index GROUP BY url_id HAVING COUNT(url_id) > 1);
-        // Make corrections:
-        // - UPDATE ama_index SET url_id='evw8g9o' WHERE fan_name='Joe_Zt' AND cc_name='Daron Nefcy';
-        // - UPDATE ama_index SET url_id='evwbgza' WHERE fan_name='sloppyjeaux' AND cc_name='Adam McArthur';
-        let mut urlid_list: Vec<String> = Vec::new();
-        let mut dup_list: Vec<AmaRecord> = Vec::new();
-        for ama_record_ref in (*ama_index_ref).iter() {
-            if let true = urlid_list.contains(&((*ama_record_ref).url_id)) {
-                eprintln!("{:?}", *ama_record_refj);
+    impl fmt::Display for ProposedCorrection {
+        /// Writes the proposal as a correction that can be pasted into the AMA definition.
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let window: String = match (&self.after_url_id, &self.before_url_id) {
+                (Some(after_url_id), Some(before_url_id)) => format!("between {} and {}", after_url_id, before_url_id),
+                (Some(after_url_id), None) => format!("after {}", after_url_id),
+                (None, Some(before_url_id)) => format!("before {}", before_url_id),
+                (None, None) => "anywhere".to_string(),
+            };
+            writeln!(f, "[[corrections]]")?;
+            writeln!(f, "match = {{ cc_name = {:?}, fan_name = {:?} }}", self.ama_record.cc_name, self.ama_record.fan_name)?;
+            writeln!(f, "set = {{ url_id = \"?\" }} # Likely {}.", window)?;
+            write!(f, "reason = {:?}", self.reason)
+        }
+    }
+
+    /// Finds every url_id that more than one record in `ama_index` links to, in order of first appearance.
+    /// Done by hand before, with:
+    /// SELECT * FROM ama_index WHERE url_id IN (SELECT url_id FROM ama_index GROUP BY url_id HAVING COUNT(url_id) > 1);
+    pub fn identify_duplicates(ama_index: &[AmaRecord]) -> Vec<DuplicateUrlId> {
+        let mut duplicates: Vec<DuplicateUrlId> = Vec::new();
+        for (position, ama_record) in ama_index.iter().enumerate() {
+            if duplicates.iter().any(|duplicate| duplicate.url_id == ama_record.url_id) {
+                continue;
             }
-        };
+            let positions: Vec<usize> = ama_index.iter()
+                .enumerate()
+                .skip(position)
+                .filter(|(_, other_record)| other_record.url_id == ama_record.url_id)
+                .map(|(other_position, _)| other_position)
+                .collect();
+            if positions.len() > 1 {
+                duplicates.push(DuplicateUrlId { url_id: ama_record.url_id.clone(), positions });
+            }
+        }
+        duplicates
     }
-    */
-
-    /// Creates a database file with the argument as the filename, and initializes the
-    /// index table.
-    pub fn create_db(full_dbpath: &str) -> () {
-        let cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath).unwrap();
-        match cnxn.execute(
-            "CREATE TABLE ama_index (
-                url_id TEXT,
-                cc_name TEXT,
-                fan_name TEXT
-            );",
-            ()
-        ) {
-            Ok(_) => println!("'ama_index' table has been created in '{}'.", full_dbpath),
-            Err(_) => panic!("Table 'ama_index' already exists in '{}'. Aborting.", full_dbpath),
-        };
+
+    /// Orders url_ids as Reddit assigns them, i.e. as base-36 numbers.
+    fn compare_urlids(url_id1: &str, url_id2: &str) -> Ordering {
+        (url_id1.len(), url_id1).cmp(&(url_id2.len(), url_id2))
+    }
+
+    /// Returns the fan's name without the ' (again)' that the compendium adds to their later questions.
+    pub(crate) fn get_base_fan_name(fan_name: &str) -> &str {
+        match fan_name.split_once(" (") {
+            Some((base_fan_name, _)) => base_fan_name,
+            None => fan_name,
+        }
+    }
+
+    /// Returns the records before and after `position` that are under the same creator.
+    fn get_neighbours(ama_index: &[AmaRecord], position: usize) -> (Option<&AmaRecord>, Option<&AmaRecord>) {
+        let cc_name: &str = &ama_index[position].cc_name;
+        let prev_record: Option<&AmaRecord> = ama_index[..position].last().filter(|ama_record| ama_record.cc_name == cc_name);
+        let next_record: Option<&AmaRecord> = ama_index.get(position + 1).filter(|ama_record| ama_record.cc_name == cc_name);
+        (prev_record, next_record)
     }
- 
-    /// Saves ama_index := [{field1: value1, field2: value2, ...}] to full_dbpath in SQL format.
-    /// - ama_index: List of ama_index dict-records.
+
+    /// Guesses which records of `duplicate` were linked to its url_id by mistake. In order of trust:
+    /// 1. The comment at url_id was asked by `question_author`, so records for any other fan are wrong.
+    /// 2. A record that repeats the url_id of the record right above it was copied from it.
+    /// 3. The compendium lists each creator's exchanges in the order they were posted, so a record
+    ///    whose url_id is out of order with its neighbours is wrong.
+    ///
+    /// Returns nothing if none of these tells the records apart.
+    /// - ama_index: Records in the order of the compendium.
+    /// - duplicate: The url_id to look into.
+    /// - question_author: Who asked the question at url_id, if its page could be fetched.
+    pub fn propose_corrections(ama_index: &[AmaRecord], duplicate: &DuplicateUrlId, question_author: Option<&str>) -> Vec<ProposedCorrection> {
+        let mut suspects: Vec<(usize, String)> = Vec::new();
+        if let Some(question_author) = question_author {
+            let is_asker = |position: &usize| get_base_fan_name(&ama_index[*position].fan_name).eq_ignore_ascii_case(question_author);
+            if duplicate.positions.iter().any(is_asker) {
+                for position in duplicate.positions.iter().filter(|position| !is_asker(position)) {
+                    let reason: String = format!("The compendium links {} to {}, which was asked by {}.", ama_index[*position].fan_name, duplicate.url_id, question_author);
+                    suspects.push((*position, reason));
+                }
+            }
+        }
+        if suspects.is_empty() {
+            for position in duplicate.positions.iter() {
+                if let (Some(prev_record), _) = get_neighbours(ama_index, *position) {
+                    if prev_record.url_id == duplicate.url_id {
+                        let reason: String = format!("The compendium links {} to {}, the same comment as {} right above it.", ama_index[*position].fan_name, duplicate.url_id, prev_record.fan_name);
+                        suspects.push((*position, reason));
+                    }
+                }
+            }
+        }
+        if suspects.is_empty() {
+            let is_out_of_order = |position: &usize| -> bool {
+                let (prev_record, next_record) = get_neighbours(ama_index, *position);
+                prev_record.is_some_and(|prev_record| compare_urlids(&prev_record.url_id, &duplicate.url_id) == Ordering::Greater)
+                    || next_record.is_some_and(|next_record| compare_urlids(&duplicate.url_id, &next_record.url_id) == Ordering::Greater)
+            };
+            let out_of_order: Vec<usize> = duplicate.positions.iter().copied().filter(is_out_of_order).collect();
+            if out_of_order.len() < duplicate.positions.len() {
+                for position in out_of_order {
+                    let reason: String = format!("The compendium links {} to {}, which is out of order with the links around it.", ama_index[position].fan_name, duplicate.url_id);
+                    suspects.push((position, reason));
+                }
+            }
+        }
+        suspects.into_iter()
+            .map(|(position, reason)| {
+                let (prev_record, next_record) = get_neighbours(ama_index, position);
+                ProposedCorrection {
+                    ama_record: ama_index[position].clone(),
+                    after_url_id: prev_record.map(|prev_record| prev_record.url_id.clone()),
+                    before_url_id: next_record.map(|next_record| next_record.url_id.clone()),
+                    reason,
+                }
+            })
+            .collect()
+    }
+
+    /// Contains how a newly compiled index differs from the one saved before it.
+    #[derive(PartialEq)]
+    #[derive(Debug)]
+    #[derive(Default)]
+    pub struct IndexChanges {
+        pub added: Vec<AmaRecord>,
+        pub changed: Vec<(AmaRecord, String)>, // Record as saved now, and the url_id it had before.
+        pub removed: Vec<AmaRecord>,
+    }
+
+    impl IndexChanges {
+        /// Returns whether the saved index was left as it was.
+        pub fn is_empty(&self) -> bool {
+            self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+        }
+    }
+
+    impl fmt::Display for IndexChanges {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{} records added, {} changed, {} removed", self.added.len(), self.changed.len(), self.removed.len())
+        }
+    }
+
+    /// Saves `ama_index` over the index in `full_dbpath`, keyed by (cc_name, fan_name), so that it
+    /// can be compiled again without duplicating any record. Records that are no longer in
+    /// `ama_index` are deleted, as are the queries of url_ids that no record links to any more.
+    /// Returns how the saved index changed.
+    /// - ama_index: List of ama_index dict-records, with no url_id shared.
     /// - full_dbpath: Tells function where to save `ama_index`
-    pub fn save_ama_index(ama_index: Vec<AmaRecord>, full_dbpath: &str) -> rusqlite::Result<usize> {
-        let cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath).unwrap();
-        let ama_index_len: usize = ama_index.len();
-        // Begin data dump here.
-        for ama_record in ama_index {
-            cnxn.execute(
-                "INSERT INTO ama_index VALUES (?1, ?2, ?3);",
-                (
-                    ama_record.url_id,
-                    ama_record.cc_name,
-                    ama_record.fan_name,
-                )
-            )?;
-        };
-        Ok(ama_index_len)
+    pub fn upsert_ama_index(ama_index: &[AmaRecord], full_dbpath: &str) -> Result<IndexChanges> {
+        Store::open(full_dbpath)?.upsert_ama_index(ama_index)
     }
 
     /// Loads from `full_dbpath` the table `ama_index` as List[dict] object.
     /// - full_dbpath: Tells function where to find `ama_index`
-    pub fn load_ama_index(full_dbpath: impl AsRef<Path>) -> Vec<AmaRecord> {
-        let mut ama_index: Vec<AmaRecord> = Vec::new();
-        let cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath).unwrap();
-        let mut stmt: rusqlite::Statement = cnxn.prepare(
-            "SELECT url_id, cc_name, fan_name FROM ama_index;"
-            ).unwrap();
-        let ama_record_iter = stmt.query_map(
-            [],
-            |row| {
-                Ok(
-                    AmaRecord {
-                        url_id: row.get(0).unwrap(),
-                        cc_name: row.get(1).unwrap(),
-                        fan_name: row.get(2).unwrap(),
-                    }
-                )
-            }
-        ).unwrap();
-        for ama_record in ama_record_iter {
-            ama_index.push(ama_record.unwrap());
-        };
-        ama_index
+    pub fn load_ama_index(full_dbpath: impl AsRef<Path>) -> Result<Vec<AmaRecord>> {
+        Store::open(full_dbpath)?.load_ama_index()
     }
 
     /// Forms a complete old-Reddit URL from the url_id parameter, and returns it as a str-object.
+    /// - ama_config: The AMA whose thread the url_id belongs to.
     /// - url_id: The part of the URL used to form a complete URL.
-    pub fn get_url(url_id: String) -> String {
-        // url_template = "https://www.reddit.com/r/StarVStheForcesofEvil/comments/cll9u5/star_vs_the_forces_of_evil_ask_me_anything//?context=3"
-        let url_template: String = URL_TEMPLATE.to_string();
-        let mut url_parts: Vec<&str> = url_template.split("/").collect();
-        let urlid_loc: usize = url_parts.len() - 2;
-        url_parts[urlid_loc] = url_id.as_str();
-        // 0: 'https:'
-        // 1: ''
-        // 2: 'www.reddit.com'
-        //url_parts[2] = "old.reddit.com";
-        let url: String = url_parts.join("/");
-        url
-    }
-
-    /// Extracts the URL id from a given URL string.
+    pub fn get_url(ama_config: &AmaConfig, url_id: String) -> String {
+        // url_template = "https://old.reddit.com/r/StarVStheForcesofEvil/comments/cll9u5/star_vs_the_forces_of_evil_ask_me_anything//?context=3"
+        // The url_id goes into the empty part, between the last '//'.
+        let url_template: String = ama_config::get_url_template(ama_config);
+        match url_template.rsplit_once("//") {
+            Some((thread_url, url_query)) => format!("{}/{}/{}", thread_url, url_id, url_query),
+            None => format!("{}/{}", url_template, url_id),
+        }
+    }
+
+    /// Extracts the URL id from a given URL string. Fails with `Error::Parse` if the URL has none,
+    /// e.g. a link to an anchor on the same page.
+    /// - ama_config: The AMA whose thread the URL points into.
     /// - url: URL whose url_id is to be extracted.
-    pub fn get_urlid(url: String) -> String {
+    pub fn get_urlid(ama_config: &AmaConfig, url: String) -> Result<String> {
         // url_template = "https://www.reddit.com/r/StarVStheForcesofEvil/comments/cll9u5/star_vs_the_forces_of_evil_ask_me_anything//?context=3"
-        let url_parts: Vec<&str> = url.split("/").collect();
-        let urlid_loc: usize = url_parts.len() - 2;
-        let url_id: String = url_parts[urlid_loc].to_string();
-        url_id
+        let url_path: &str = url.split("?").next().unwrap_or_default();
+        let url_parts: Vec<&str> = url_path.split("/").collect();
+        // The url_id follows the thread_id and the thread_slug, whatever the slug happens to be.
+        let urlid_loc: Option<usize> = match url_parts.iter().position(|part| *part == ama_config.thread_id) {
+            Some(threadid_loc) if threadid_loc + 2 < url_parts.len() && !url_parts[threadid_loc + 2].is_empty() => Some(threadid_loc + 2),
+            _ => url_parts.len().checked_sub(2),
+        };
+        match urlid_loc.map(|urlid_loc| url_parts[urlid_loc]) {
+            Some(url_id) if !url_id.is_empty() => Ok(url_id.to_string()),
+            _ => Err(Error::Parse(format!("No url_id in the link '{}'.", url))),
+        }
     }
 
 //}
@@ -253,9 +362,10 @@ fn remove_file(full_path: impl AsRef<Path> + std::fmt::Debug) -> () {
 
 #[cfg(test)]
 mod ama_indexer_tests {
-    use super;
     use std::fs;
     use super::remove_file;
+    use crate::ama_config::AmaConfig;
+    use crate::ama_fetcher::ReplayFetcher;
 
     fn get_raw_index() -> String {
         let raw_index: &str = r#"
@@ -276,7 +386,7 @@ mod ama_indexer_tests {
     fn test_get_url() {
         let url_id: String = "nyet".to_string();
         let expected: String = format!("{}/{}/{}", "https://old.reddit.com/r/StarVStheForcesofEvil/comments/cll9u5/star_vs_the_forces_of_evil_ask_me_anything", url_id, "?context=3");
-        let actual: String = super::get_url(url_id);
+        let actual: String = super::get_url(&AmaConfig::default(), url_id);
         assert_eq!(actual, expected);
     }
 
@@ -284,14 +394,41 @@ mod ama_indexer_tests {
     fn test_get_urlid() {
         let expected: String = "nyet".to_string();
         let url: String = format!("{}/{}/{}", "https://www.reddit.com/r/StarVStheForcesofEvil/comments/cll9u5/star_vs_the_forces_of_evil_ask_me_anything", expected, "?context=3");
-        let actual: String = super::get_urlid(url);
+        let actual: String = super::get_urlid(&AmaConfig::default(), url).unwrap();
+        assert_eq!(actual, expected);
+        // Links without a url_id are refused, rather than read out of bounds.
+        for url in ["#", "1", "", "/"] {
+            match super::get_urlid(&AmaConfig::default(), url.to_string()) {
+                Err(crate::Error::Parse(_)) => (),
+                other => panic!("Expected a parse error for {:?}, got: {:?}", url, other),
+            };
+        }
+    }
+
+    #[test]
+    fn test_get_urlid_other_ama() {
+        let ama_config = AmaConfig {
+            subreddit: "subreddit".to_string(),
+            thread_id: "abc123".to_string(),
+            thread_slug: "_".to_string(),
+            compendium_url: String::new(),
+            first_cc_name: "cc_name1:".to_string(),
+            creators: Default::default(),
+            corrections: Vec::new(),
+        };
+        let expected: String = "nyet".to_string();
+        let url: String = format!("https://www.reddit.com/r/subreddit/comments/abc123/some_slug/{}?context=3", expected);
+        let actual: String = super::get_urlid(&ama_config, url).unwrap();
         assert_eq!(actual, expected);
+        let url: String = super::get_url(&ama_config, expected.clone());
+        assert_eq!(url, "https://old.reddit.com/r/subreddit/comments/abc123/_/nyet/?context=3");
     }
 
     #[test]
     fn test_fetch_raw_index() {
         let url: &str = "https://old.reddit.com/r/StarVStheForcesofEvil/comments/clnrdv/link_compendium_of_questions_and_answers_from_the/";
-        let raw_index: String = super::fetch_raw_index(url);
+        let fetcher = ReplayFetcher::from_dir("tests/fixtures/replay").unwrap();
+        let raw_index: String = super::fetch_raw_index(&fetcher, url).unwrap();
         // Few tests to check that it contains some keywords.
         let keywords: Vec<&str> = Vec::from(
             [
@@ -342,36 +479,118 @@ mod ama_indexer_tests {
         let expected: Vec<super::AmaRecord> = get_ama_index();
         let start_text: &str = "cc_name1:";
         let raw_index: &str = &get_raw_index();
-        let actual: Vec<super::AmaRecord> = super::compile_ama_index(raw_index.to_string(), start_text);
+        let actual: Vec<super::AmaRecord> = super::compile_ama_index(raw_index.to_string(), start_text).unwrap();
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_identify_duplicates() {
+        let expected: Vec<super::DuplicateUrlId> = vec![
+            super::DuplicateUrlId {
+                url_id: "1".to_string(),
+                positions: vec![0, 2],
+            },
+        ];
+        assert_eq!(super::identify_duplicates(&get_ama_index()), expected);
+    }
+
+    fn get_duplicated_index() -> Vec<super::AmaRecord> {
+        // As in the link compendium, whose fans are listed in the order they asked.
+        [
+            ("Daron Nefcy", "shroomished", "evw8bie"),
+            ("Daron Nefcy", "Joe_Zt", "evw8mcl"),
+            ("Daron Nefcy", "ilooooove", "evw8n3c"),
+            ("Daron Nefcy", "FourthCause", "evwcz0n"),
+            ("Daron Nefcy", "ShinySaturn", "evw8mcl"),
+            ("Adam McArthur", "SirMarcoDiaz", "evwb9tm"),
+            ("Adam McArthur", "Hiofshao_Quask", "evwbcnk"),
+            ("Adam McArthur", "sloppyjeaux", "evwbcnk"),
+            ("Adam McArthur", "Joe_Zt (again again again)", "evwbk4e"),
+        ].into_iter()
+            .map(|(cc_name, fan_name, url_id)| super::AmaRecord {
+                cc_name: cc_name.to_string(),
+                fan_name: fan_name.to_string(),
+                url_id: url_id.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_propose_corrections() {
+        let ama_index: Vec<super::AmaRecord> = get_duplicated_index();
+        let duplicates: Vec<super::DuplicateUrlId> = super::identify_duplicates(&ama_index);
+        assert_eq!(duplicates.len(), 2);
+        // The question's author tells who was linked by mistake.
+        let proposed_corrections = super::propose_corrections(&ama_index, &duplicates[0], Some("ShinySaturn"));
+        assert_eq!(proposed_corrections.len(), 1);
+        assert_eq!(proposed_corrections[0].ama_record, ama_index[1]);
+        assert_eq!(proposed_corrections[0].after_url_id, Some("evw8bie".to_string()));
+        assert_eq!(proposed_corrections[0].before_url_id, Some("evw8n3c".to_string()));
+        // Without it, the record that is out of order is suspected.
+        let proposed_corrections = super::propose_corrections(&ama_index, &duplicates[0], None);
+        assert_eq!(proposed_corrections.len(), 1);
+        assert_eq!(proposed_corrections[0].ama_record, ama_index[4]);
+        assert_eq!(proposed_corrections[0].before_url_id, None);
+        // A link that repeats the one right above it was copied from it.
+        let proposed_corrections = super::propose_corrections(&ama_index, &duplicates[1], None);
+        assert_eq!(proposed_corrections.len(), 1);
+        assert_eq!(proposed_corrections[0].ama_record, ama_index[7]);
+        assert_eq!(proposed_corrections[0].before_url_id, Some("evwbk4e".to_string()));
+        let ama_index: Vec<super::AmaRecord> = get_ama_index();
+        let proposed_corrections = super::propose_corrections(&ama_index, &super::identify_duplicates(&ama_index)[0], None);
+        assert_eq!(proposed_corrections.len(), 1);
+        assert_eq!(proposed_corrections[0].ama_record, ama_index[2]);
+        // Records without neighbours cannot be told apart.
+        let mut ama_index: Vec<super::AmaRecord> = vec![ama_index[0].clone(), ama_index[3].clone()];
+        ama_index[1].url_id = "1".to_string();
+        let duplicates: Vec<super::DuplicateUrlId> = super::identify_duplicates(&ama_index);
+        assert_eq!(super::propose_corrections(&ama_index, &duplicates[0], None), Vec::new());
+    }
+
+    #[test]
+    fn test_compile_ama_index_missing_start() {
+        let raw_index: &str = &get_raw_index();
+        match super::compile_ama_index(raw_index.to_string(), "cc_name3:") {
+            Err(super::Error::Parse(_)) => (),
+            other => panic!("Expected a parse error, got: {:?}", other),
+        };
+    }
+
     #[test]
     fn test_save_raw_index() {
         let raw_index: &str = &get_raw_index();
         let odir_name: &str = "mock-output";
         let lc_fname: &str = "test_save_raw_index-output";
         // Assert that saved text is the same as the loaded text.
-        let () = super::save_raw_index(raw_index.to_string(), odir_name, lc_fname);
+        let () = super::save_raw_index(raw_index.to_string(), odir_name, lc_fname).unwrap();
         let full_htmlpath: String = format!("{}/{}.html", odir_name, lc_fname);
         let actual: String = fs::read_to_string(&full_htmlpath).unwrap();
         let expected: String = raw_index.to_string();
         assert_eq!(actual, expected);
         // Cleanup
         remove_file(full_htmlpath);
-        fs::remove_dir(odir_name);
+        let _ = fs::remove_dir(odir_name);
+    }
+
+    /// Returns `get_ama_index` without its third record, whose url_id is shared with the first, since
+    /// the index table refuses shared url_ids.
+    fn get_unique_index() -> Vec<super::AmaRecord> {
+        let mut ama_index: Vec<super::AmaRecord> = get_ama_index();
+        ama_index.remove(2);
+        ama_index
     }
 
     #[test]
     fn test_save_ama_index() {
-        let ama_index: Vec<super::AmaRecord> = get_ama_index();
+        let ama_index: Vec<super::AmaRecord> = get_unique_index();
         let full_dbpath: &str = "output/ama_index-save_test.db";
+        let _ = fs::remove_file(full_dbpath);
         // if full_dbpath.exists(): rm full_dbpath
-        let () = super::create_db(full_dbpath);
-        let save_result: Result<usize, _> = super::save_ama_index(ama_index, full_dbpath);
+        let _ = crate::ama_schema::migrate(full_dbpath).unwrap();
+        let save_result: super::Result<super::IndexChanges> = super::upsert_ama_index(&ama_index, full_dbpath);
         match save_result {
-            Ok(numrows) => {
-                println!("{} rows written", numrows);
+            Ok(index_changes) => {
+                println!("{} written", index_changes);
             },
             Err(sql_err) => {
                 panic!("SQL error occurred: {:?}",  sql_err);
@@ -399,40 +618,57 @@ mod ama_indexer_tests {
         for ama_record in ama_record_iter {
             actual.push(ama_record.unwrap());
         };
-        let expected: Vec<super::AmaRecord> = get_ama_index();
+        let expected: Vec<super::AmaRecord> = get_unique_index();
         assert_eq!(actual, expected);
     }
 
-    fn get_db_cnxn(full_dbpath: &str) -> rusqlite::Connection {
-        let cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath).unwrap();
-        match cnxn.execute(
-            "CREATE TABLE IF NOT EXISTS ama_index (
-                url_id TEXT,
-                cc_name TEXT,
-                fan_name TEXT
-            );",
-            ()
-        ) {
-            Ok(_) => {
-                println!("Table created.");
-            },
-            Err(sql_err) => {
-                eprintln!("SQL error: {:?}", sql_err);
-            },
+    #[test]
+    fn test_upsert_ama_index() {
+        let full_dbpath: &str = "output/ama_index-upsert_test.db";
+        let _ = fs::remove_file(full_dbpath);
+        let _ = crate::ama_schema::migrate(full_dbpath).unwrap();
+        let ama_index: Vec<super::AmaRecord> = get_unique_index();
+        assert_eq!(super::upsert_ama_index(&ama_index, full_dbpath).unwrap().added, ama_index);
+        // Saving the same index again changes nothing.
+        assert!(super::upsert_ama_index(&ama_index, full_dbpath).unwrap().is_empty());
+        // fan_name1 and fan_name2 trade url_ids, fan_name5 is dropped, and fan_name6 is new.
+        let mut new_index: Vec<super::AmaRecord> = ama_index.clone();
+        new_index[0].url_id = "2".to_string();
+        new_index[1].url_id = "1".to_string();
+        new_index[3] = super::AmaRecord {
+            cc_name: "cc_name2".to_string(),
+            fan_name: "fan_name6".to_string(),
+            url_id: "5".to_string(),
+        };
+        let index_changes: super::IndexChanges = super::upsert_ama_index(&new_index, full_dbpath).unwrap();
+        let actual: Vec<super::AmaRecord> = super::load_ama_index(full_dbpath).unwrap();
+        remove_file(full_dbpath);
+        let expected = super::IndexChanges {
+            added: vec![new_index[3].clone()],
+            changed: vec![(new_index[0].clone(), "1".to_string()), (new_index[1].clone(), "2".to_string())],
+            removed: vec![ama_index[3].clone()],
         };
-        cnxn
+        assert_eq!(index_changes, expected);
+        // The index is loaded in the order it was compiled in, even though changed records were written again.
+        assert_eq!(actual, new_index);
+    }
+
+    fn get_db_cnxn(full_dbpath: &str) -> rusqlite::Connection {
+        let _ = crate::ama_schema::migrate(full_dbpath).unwrap();
+        rusqlite::Connection::open(full_dbpath).unwrap()
     }
 
     #[test]
     fn test_load_ama_index() {
         let full_dbpath: &str = "output/ama_index-load_test.db";
-        let ama_index: Vec<super::AmaRecord> = get_ama_index();
+        let _ = fs::remove_file(full_dbpath);
+        let ama_index: Vec<super::AmaRecord> = get_unique_index();
         // Insert into table, then test load.
         let cnxn: rusqlite::Connection = get_db_cnxn(full_dbpath);
         // Begin data dump here.
         for ama_record in ama_index {
             cnxn.execute(
-                "INSERT INTO ama_index VALUES (?1, ?2, ?3);",
+                "INSERT INTO ama_index (url_id, cc_name, fan_name) VALUES (?1, ?2, ?3);",
                 (
                     ama_record.url_id,
                     ama_record.cc_name,
@@ -440,8 +676,8 @@ mod ama_indexer_tests {
                 )
             ).unwrap();
         };
-        let actual: Vec<super::AmaRecord> = super::load_ama_index(full_dbpath);
-        let expected: Vec<super::AmaRecord> = get_ama_index();
+        let actual: Vec<super::AmaRecord> = super::load_ama_index(full_dbpath).unwrap();
+        let expected: Vec<super::AmaRecord> = get_unique_index();
         assert_eq!(actual, expected);
         remove_file(full_dbpath);
     }
@@ -0,0 +1,300 @@
+//! This module reads the comment listings that Reddit serves as JSON, which do not change with the
+//! markup of the site, as an alternative to scraping its HTML.
+//! - get_json_url: Returns the URL of the JSON listing for a page.
+//! - get_iso_datetime: Turns a Unix timestamp into the ISO 8601 form that old Reddit's pages show.
+//! - parse_ama_thread: Reconstructs the tree of comments in a listing, with parent/child links.
+//! - parse_ama_query: Extracts Q&A data from a listing that has already been fetched.
+//! - get_compendium_html: Returns the body of the post in a listing, as HTML.
+//!
+//! Listings are asked for with 'raw_json=1', so that their text is not HTML-escaped a second time.
+use scraper::{ElementRef, Html, Selector};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+
+use crate::ama_config::{self, AmaConfig};
+use crate::ama_scraper::{self, AmaComment, AmaQuery, CommentMeta};
+use crate::error::{Error, Result};
+
+/// What Reddit shows in place of the name of a deleted account.
+const DELETED_AUTHOR: &str = "[deleted]";
+
+// Only the parts of the listing format that the archive keeps.
+#[derive(Deserialize)]
+struct Listing {
+    data: ListingData,
+}
+
+#[derive(Deserialize)]
+struct ListingData {
+    children: Vec<Thing>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", content = "data")]
+enum Thing {
+    #[serde(rename = "t1")]
+    Comment(Box<CommentData>),
+    #[serde(rename = "t3")]
+    Post(PostData),
+    #[serde(rename = "more")]
+    More(IgnoredAny), // Replies that were left out of the listing, to be loaded on demand.
+}
+
+#[derive(Deserialize)]
+struct CommentData {
+    id: String,
+    author: Option<String>,
+    body: Option<String>, // Markdown, as it was written.
+    body_html: Option<String>,
+    created_utc: Option<f64>,
+    score: Option<i64>,
+    #[serde(default)]
+    score_hidden: bool,
+    edited: Option<Edited>,
+    permalink: Option<String>,
+    replies: Option<Replies>,
+}
+
+#[derive(Deserialize)]
+struct PostData {
+    selftext_html: Option<String>,
+}
+
+/// 'edited' is the time of the last edit, or false for a comment that never was.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Edited {
+    Timestamp(f64),
+    Never(IgnoredAny),
+}
+
+/// 'replies' is a listing, or an empty string for a comment that has none.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Replies {
+    Listing(Listing),
+    Empty(IgnoredAny),
+}
+
+/// Returns the URL of the JSON listing for the page at `url`, which keeps its query string.
+/// e.g. '.../c00001/?context=3' -> '.../c00001/.json?context=3&raw_json=1'
+pub fn get_json_url(url: &str) -> String {
+    let (url_path, url_query): (&str, Option<&str>) = match url.split_once('?') {
+        Some((url_path, url_query)) => (url_path, Some(url_query)),
+        None => (url, None),
+    };
+    let url_path: String = match url_path.ends_with('/') {
+        true => format!("{}.json", url_path),
+        false => format!("{}/.json", url_path),
+    };
+    match url_query {
+        Some(url_query) if !url_query.is_empty() => format!("{}?{}&raw_json=1", url_path, url_query),
+        _ => format!("{}?raw_json=1", url_path),
+    }
+}
+
+/// Returns the Unix timestamp `timestamp` as an ISO 8601 date-time in UTC, e.g.
+/// '2019-08-03T17:01:23+00:00', which is the form that old Reddit's pages show.
+pub fn get_iso_datetime(timestamp: f64) -> String {
+    let seconds: i64 = timestamp.floor() as i64;
+    let (days, day_seconds): (i64, i64) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // Converts days since 1970-01-01 into a date of the proleptic Gregorian calendar.
+    let shifted_days: i64 = days + 719468;
+    let era: i64 = shifted_days.div_euclid(146097);
+    let day_of_era: i64 = shifted_days.rem_euclid(146097);
+    let year_of_era: i64 = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year: i64 = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month: i64 = (5 * day_of_year + 2) / 153;
+    let day: i64 = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month: i64 = match shifted_month < 10 {
+        true => shifted_month + 3,
+        false => shifted_month - 9,
+    };
+    let year: i64 = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}+00:00",
+        year, month, day, day_seconds / 3600, day_seconds % 3600 / 60, day_seconds % 60,
+    )
+}
+
+/// Returns the listings in `raw_json`, i.e. the post's, and then its comments'.
+fn get_listings(raw_json: &str) -> Result<Vec<Listing>> {
+    match serde_json::from_str(raw_json) {
+        Ok(listings) => Ok(listings),
+        Err(json_err) => Err(Error::Parse(format!("Could not parse comment listing: {}", json_err))),
+    }
+}
+
+/// Returns the plain text of `body_html`, as `ama_scraper::get_html_text` finds it on the page.
+fn get_body_text(body_html: &str) -> Option<String> {
+    let parsed_html: Html = Html::parse_fragment(body_html);
+    let md_selector: Selector = Selector::parse(".md").expect("'.md' is a valid selector");
+    let md_node: ElementRef = parsed_html.select(&md_selector).next()?;
+    // The page puts a line break after the body, which is kept so that both backends agree.
+    ama_scraper::get_html_text(md_node).map(|body_text| format!("{}\n", body_text))
+}
+
+/// Adds `comment_data`, and every reply to it, to `thread` in the order in which they appear.
+fn add_comments(comment_data: CommentData, parent_id: Option<&str>, depth: u32, thread: &mut Vec<AmaComment>) {
+    let meta = CommentMeta {
+        comment_id: Some(comment_data.id.clone()),
+        author: comment_data.author.filter(|author| author != DELETED_AUTHOR),
+        datetime: comment_data.created_utc.map(get_iso_datetime),
+        score: comment_data.score.filter(|_| !comment_data.score_hidden),
+        edited: match comment_data.edited {
+            Some(Edited::Timestamp(timestamp)) => Some(get_iso_datetime(timestamp)),
+            Some(Edited::Never(_)) | None => None,
+        },
+        permalink: comment_data.permalink.map(|permalink| format!("{}{}", ama_config::REDDIT_HOST, permalink)),
+    };
+    let comment_id: String = comment_data.id;
+    thread.push(
+        AmaComment {
+            comment_id: comment_id.clone(),
+            parent_id: parent_id.map(|parent_id| parent_id.to_string()),
+            depth,
+            body_text: comment_data.body_html.as_deref().and_then(get_body_text),
+            body_markdown: comment_data.body,
+            meta,
+        }
+    );
+    if let Some(Replies::Listing(replies)) = comment_data.replies {
+        for reply in replies.data.children {
+            if let Thing::Comment(reply_data) = reply {
+                add_comments(*reply_data, Some(&comment_id), depth + 1, thread);
+            }
+        }
+    }
+}
+
+/// Reconstructs the tree of comments in a comment listing, in the order in which they appear on the
+/// page. Replies that the listing left out, as 'more', are skipped.
+/// [{"kind": "Listing", ...post...}, {"kind": "Listing", "data": {"children": [{"kind": "t1", "data": {..., "replies": ...}}]}}]
+/// - raw_json: Listing as it was fetched.
+pub fn parse_ama_thread(raw_json: &str) -> Result<Vec<AmaComment>> {
+    let comment_listing: Listing = match get_listings(raw_json)?.into_iter().nth(1) {
+        Some(comment_listing) => comment_listing,
+        None => return Err(Error::Parse("Comment listing has no comments.".to_string())),
+    };
+    let mut thread: Vec<AmaComment> = Vec::new();
+    for thing in comment_listing.data.children {
+        if let Thing::Comment(comment_data) = thing {
+            add_comments(*comment_data, None, 0, &mut thread);
+        }
+    }
+    Ok(thread)
+}
+
+/// Extracts `question_text` and `answer_text` values from a comment listing, just as
+/// `ama_scraper::parse_ama_query` does from a page. The Markdown is kept as it was written.
+/// - ama_config: Names the creators, whose comments are the answers.
+/// - raw_json: Listing as it was fetched.
+/// - ama_query: dict to store parsed data. Initialize outside function.
+pub fn parse_ama_query(ama_config: &AmaConfig, raw_json: &str, ama_query: &mut AmaQuery) -> Result<()> {
+    let thread: Vec<AmaComment> = parse_ama_thread(raw_json)?;
+    let () = ama_scraper::fill_ama_query(ama_config, thread, ama_query);
+    Ok(())
+}
+
+/// Returns the body of the post in a listing as HTML, in the same markup that the post's page has,
+/// so that a link compendium can be indexed from either.
+/// - raw_json: Listing as it was fetched.
+pub fn get_compendium_html(raw_json: &str) -> Result<String> {
+    let selftext_html: Option<String> = get_listings(raw_json)?.into_iter()
+        .next()
+        .and_then(|post_listing| post_listing.data.children.into_iter().next())
+        .and_then(|thing| match thing {
+            Thing::Post(post_data) => post_data.selftext_html,
+            _ => None,
+        });
+    match selftext_html {
+        Some(selftext_html) => Ok(selftext_html),
+        None => Err(Error::Parse("Listing has no post with a body.".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod ama_json_tests {
+    use std::fs;
+    use crate::ama_config::AmaConfig;
+    use crate::ama_scraper::{self, AmaComment, AmaQuery};
+
+    const REPLAY_DIR: &str = "tests/fixtures/replay";
+
+    fn read_fixture(url_id: &str, backend_suffix: &str) -> String {
+        fs::read_to_string(format!("{}/old.reddit.com_r_testama_comments_abc123_test_ama_{}_{}", REPLAY_DIR, url_id, backend_suffix)).unwrap()
+    }
+
+    #[test]
+    fn test_get_json_url() {
+        assert_eq!(
+            super::get_json_url("https://old.reddit.com/r/testama/comments/abc123/test_ama/c00001/?context=3"),
+            "https://old.reddit.com/r/testama/comments/abc123/test_ama/c00001/.json?context=3&raw_json=1",
+        );
+        assert_eq!(
+            super::get_json_url("https://old.reddit.com/r/testama/comments/xyz789/link_compendium"),
+            "https://old.reddit.com/r/testama/comments/xyz789/link_compendium/.json?raw_json=1",
+        );
+    }
+
+    #[test]
+    fn test_get_iso_datetime() {
+        assert_eq!(super::get_iso_datetime(0.0), "1970-01-01T00:00:00+00:00");
+        assert_eq!(super::get_iso_datetime(1564851683.0), "2019-08-03T17:01:23+00:00");
+        assert_eq!(super::get_iso_datetime(951782400.5), "2000-02-29T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_parse_ama_thread() {
+        // Both backends find the same comments, in the same order, with the same metadata and text.
+        for url_id in ["c00001", "c00003", "c00005", "c00012"] {
+            let json_thread: Vec<AmaComment> = super::parse_ama_thread(&read_fixture(url_id, ".json_context_3_raw_json_1")).unwrap();
+            let html_thread: Vec<AmaComment> = ama_scraper::parse_ama_thread(&read_fixture(url_id, "_context_3")).unwrap();
+            assert_eq!(json_thread.len(), html_thread.len());
+            for (json_comment, html_comment) in json_thread.iter().zip(html_thread.iter()) {
+                assert_eq!((&json_comment.comment_id, &json_comment.parent_id, json_comment.depth), (&html_comment.comment_id, &html_comment.parent_id, html_comment.depth));
+                assert_eq!(json_comment.meta, html_comment.meta);
+                assert_eq!(json_comment.body_text, html_comment.body_text);
+            }
+        }
+        // The Markdown is the body as it was written, rather than as it was rendered.
+        let thread: Vec<AmaComment> = super::parse_ama_thread(&read_fixture("c00001", ".json_context_3_raw_json_1")).unwrap();
+        assert_eq!(thread[1].body_markdown, Some("Mostly *my childhood*. See [this interview](https://example.com/interview).\n\n- Cartoons\n- Comics".to_string()));
+        // Deleted accounts and hidden scores are unknown, and replies left out as 'more' are skipped.
+        let raw_json: &str = r#"[{"kind": "Listing", "data": {"children": []}}, {"kind": "Listing", "data": {"children": [
+            {"kind": "t1", "data": {"id": "c1", "author": "[deleted]", "body": "[removed]", "score": 1, "score_hidden": true, "edited": false, "replies": {"kind": "Listing", "data": {"children": [
+                {"kind": "more", "data": {"count": 2, "children": ["c2", "c3"]}}
+            ]}}}}
+        ]}}]"#;
+        let thread: Vec<AmaComment> = super::parse_ama_thread(raw_json).unwrap();
+        assert_eq!(thread.len(), 1);
+        assert_eq!((thread[0].meta.author.as_deref(), thread[0].meta.score, thread[0].body_text.as_deref()), (None, None, None));
+        assert!(super::parse_ama_thread("<html></html>").is_err());
+    }
+
+    #[test]
+    fn test_parse_ama_query() {
+        let mut ama_query = AmaQuery {
+            url_id: "c00003".to_string(),
+            ..Default::default()
+        };
+        let ama_config: AmaConfig = crate::load_ama_config("tests/fixtures/test_ama.toml").unwrap();
+        let () = super::parse_ama_query(&ama_config, &read_fixture("c00003", ".json_context_3_raw_json_1"), &mut ama_query).unwrap();
+        assert_eq!(ama_query.question_text, Some("Will there be a sequel?\n\n".to_string()));
+        assert_eq!(ama_query.answer_text, Some("Not at this time.\n\nWell, never say never!\n\n".to_string()));
+        assert_eq!(ama_query.answer_markdown, Some("Not at this time.\n\nWell, never say never!".to_string()));
+        assert_eq!(ama_query.answer_meta.edited, Some("2019-08-03T18:02:10+00:00".to_string()));
+    }
+
+    #[test]
+    fn test_get_compendium_html() {
+        let raw_json: String = fs::read_to_string(format!("{}/old.reddit.com_r_testama_comments_xyz789_link_compendium_.json_raw_json_1", REPLAY_DIR)).unwrap();
+        let raw_html: String = super::get_compendium_html(&raw_json).unwrap();
+        let ama_index = crate::ama_indexer::compile_ama_index(raw_html, "Alice Author:").unwrap();
+        let fan_names: Vec<&str> = ama_index.iter().map(|ama_record| ama_record.fan_name.as_str()).collect();
+        assert_eq!(fan_names, vec!["fan_one", "fan_two", "fan_one (again)"]);
+        assert!(super::get_compendium_html(&read_fixture("c00001", ".json_context_3_raw_json_1")).is_ok());
+        assert!(super::get_compendium_html("[]").is_err());
+    }
+
+}
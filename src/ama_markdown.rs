@@ -0,0 +1,412 @@
+//! This module renders the joined archive as a Markdown book, with one chapter per creator.
+//! - escape_markdown: Escapes the characters of plain text that Markdown would read as formatting.
+//! - get_anchor: Turns a heading into the anchor that Markdown renderers link it by.
+//! - CreatorStats: How many exchanges a creator answered, and how much they wrote.
+//! - write_book: Writes the table of contents, the statistics, and a chapter per creator.
+//! - html_to_markdown: Turns the rendered HTML of a comment back into the Markdown it was written in.
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+use ego_tree::NodeRef;
+use scraper::{ElementRef, Node};
+
+use crate::ama_config::{self, AmaConfig};
+use crate::ama_export::{self, Exchange};
+
+/// Escapes every character of `text` that Markdown would otherwise read as formatting, or that would
+/// end a table cell, so that it renders as written.
+/// - text: Plain text, e.g. a name or a scraped answer.
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped_text: String = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '~') {
+            escaped_text.push('\\');
+        }
+        escaped_text.push(c);
+    }
+    escaped_text
+}
+
+/// Returns the anchor that GitHub-flavored renderers give to the heading `heading`: lowercased,
+/// with spaces as hyphens, and with every other punctuation mark left out. Anchors already in
+/// `used_anchors` are suffixed with '-1', '-2' and so on, as the renderers do.
+/// - heading: Text of the heading, before it is escaped.
+/// - used_anchors: Anchors of the headings before it, to which the new anchor is added.
+pub fn get_anchor(heading: &str, used_anchors: &mut HashSet<String>) -> String {
+    let base_anchor: String = heading.trim().to_lowercase().chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            c if c.is_alphanumeric() || c == '-' || c == '_' => Some(c),
+            _ => None,
+        })
+        .collect();
+    let mut anchor: String = base_anchor.clone();
+    let mut suffix: usize = 0;
+    while used_anchors.contains(&anchor) {
+        suffix += 1;
+        anchor = format!("{}-{}", base_anchor, suffix);
+    }
+    used_anchors.insert(anchor.clone());
+    anchor
+}
+
+/// How many exchanges a creator answered, and how much they wrote.
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+pub struct CreatorStats {
+    pub num_exchanges: usize,
+    pub num_answer_words: usize,
+    pub max_answer_words: usize,
+    pub total_answer_score: Option<i64>, // None if no answer's score is known.
+}
+
+impl CreatorStats {
+    /// Counts up the answers in `exchanges`.
+    pub fn from_exchanges(exchanges: &[&Exchange]) -> Self {
+        let mut creator_stats = CreatorStats::default();
+        for exchange in exchanges.iter() {
+            let num_words: usize = exchange.answer_text.split_whitespace().count();
+            creator_stats.num_exchanges += 1;
+            creator_stats.num_answer_words += num_words;
+            creator_stats.max_answer_words = creator_stats.max_answer_words.max(num_words);
+            if let Some(score) = exchange.answer_meta.score {
+                creator_stats.total_answer_score = Some(creator_stats.total_answer_score.unwrap_or_default() + score);
+            }
+        }
+        creator_stats
+    }
+
+    /// Returns the mean number of words per answer, rounded down.
+    pub fn get_mean_answer_words(&self) -> usize {
+        self.num_answer_words.checked_div(self.num_exchanges).unwrap_or_default()
+    }
+}
+
+/// Writes `text` as a block quote, one quoted line per line of text, with blank lines kept. Lines
+/// that are followed by another end in a hard break, so that they are not run together.
+fn write_quote(text: &str, writer: &mut dyn Write) -> io::Result<()> {
+    let lines: Vec<&str> = text.trim().lines().map(|line| line.trim_end()).collect();
+    for (lineno, line) in lines.iter().enumerate() {
+        let next_line: &str = lines.get(lineno + 1).copied().unwrap_or_default();
+        match (*line, next_line) {
+            ("", _) => writeln!(writer, ">")?,
+            (line, "") => writeln!(writer, "> {}", escape_markdown(line))?,
+            (line, _) => writeln!(writer, "> {}\\", escape_markdown(line))?,
+        };
+    }
+    Ok(())
+}
+
+/// Writes the body of a comment as a block quote. Its Markdown is quoted as it is, if it was
+/// scraped, and its plain text is escaped and quoted by `write_quote` otherwise.
+fn write_body_quote(text: &str, markdown: Option<&str>, writer: &mut dyn Write) -> io::Result<()> {
+    let markdown: &str = match markdown {
+        Some(markdown) if !markdown.trim().is_empty() => markdown,
+        _ => return write_quote(text, writer),
+    };
+    for line in markdown.trim().lines() {
+        match line.trim_end() {
+            "" => writeln!(writer, ">")?,
+            line => writeln!(writer, "> {}", line)?,
+        };
+    }
+    Ok(())
+}
+
+/// Writes `exchanges` to `writer` as a Markdown book: a title, a table of contents, a table of
+/// statistics, and then a chapter per creator, in the order in which each creator first appears.
+/// Nothing but the exchanges goes into the book, so the same archive is always written the same way.
+/// - ama_config: Names the subreddit and thread that the book is about.
+/// - exchanges: Exchanges to write, in compendium order.
+/// - writer: Where to write the book.
+pub fn write_book(ama_config: &AmaConfig, exchanges: &[Exchange], writer: &mut dyn Write) -> io::Result<()> {
+    let creator_groups = ama_export::group_by_creator(exchanges);
+    let mut used_anchors: HashSet<String> = HashSet::new();
+    let title: String = format!("r/{} AMA", ama_config.subreddit);
+    let _ = get_anchor(&title, &mut used_anchors);
+    let _ = get_anchor("Contents", &mut used_anchors);
+    let _ = get_anchor("Statistics", &mut used_anchors);
+    let chapter_anchors: Vec<String> = creator_groups.iter()
+        .map(|(cc_name, _)| get_anchor(cc_name, &mut used_anchors))
+        .collect();
+    let thread_url: String = ama_config::get_thread_url(ama_config);
+    writeln!(writer, "# {}", escape_markdown(&title))?;
+    writeln!(writer)?;
+    writeln!(writer, "{} exchanges with {} creators, archived from [the AMA thread]({}).", exchanges.len(), creator_groups.len(), thread_url)?;
+    writeln!(writer)?;
+    writeln!(writer, "## Contents")?;
+    writeln!(writer)?;
+    for (chapterno, ((cc_name, creator_exchanges), anchor)) in creator_groups.iter().zip(chapter_anchors.iter()).enumerate() {
+        writeln!(writer, "{}. [{}](#{}) ({} exchanges)", chapterno + 1, escape_markdown(cc_name), anchor, creator_exchanges.len())?;
+    }
+    writeln!(writer)?;
+    writeln!(writer, "## Statistics")?;
+    writeln!(writer)?;
+    writeln!(writer, "| Creator | Exchanges | Answer words | Mean words per answer | Longest answer, in words | Total answer score |")?;
+    writeln!(writer, "| --- | ---: | ---: | ---: | ---: | ---: |")?;
+    let all_stats: Vec<CreatorStats> = creator_groups.iter()
+        .map(|(_, creator_exchanges)| CreatorStats::from_exchanges(creator_exchanges))
+        .collect();
+    for ((cc_name, _), creator_stats) in creator_groups.iter().zip(all_stats.iter()) {
+        writeln!(
+            writer, "| {} | {} | {} | {} | {} | {} |",
+            escape_markdown(cc_name), creator_stats.num_exchanges, creator_stats.num_answer_words, creator_stats.get_mean_answer_words(),
+            creator_stats.max_answer_words, creator_stats.total_answer_score.map(|score| score.to_string()).unwrap_or("unknown".to_string()),
+        )?;
+    }
+    for ((cc_name, creator_exchanges), creator_stats) in creator_groups.iter().zip(all_stats.iter()) {
+        writeln!(writer)?;
+        writeln!(writer, "## {}", escape_markdown(cc_name))?;
+        writeln!(writer)?;
+        writeln!(writer, "{} answered {} questions, in {} words.", escape_markdown(cc_name), creator_stats.num_exchanges, creator_stats.num_answer_words)?;
+        for (exchangeno, exchange) in creator_exchanges.iter().enumerate() {
+            writeln!(writer)?;
+            writeln!(writer, "### {}. {}", exchangeno + 1, escape_markdown(&exchange.fan_name))?;
+            writeln!(writer)?;
+            writeln!(writer, "**Question:**")?;
+            writeln!(writer)?;
+            write_body_quote(&exchange.question_text, exchange.question_markdown.as_deref(), writer)?;
+            writeln!(writer)?;
+            writeln!(writer, "**Answer:**")?;
+            writeln!(writer)?;
+            write_body_quote(&exchange.answer_text, exchange.answer_markdown.as_deref(), writer)?;
+            writeln!(writer)?;
+            writeln!(writer, "[View on Reddit]({})", exchange.url)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns `text` with every run of whitespace as one space, as a browser would render it.
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed_text: String = String::with_capacity(text.len());
+    let mut was_space: bool = false;
+    for c in text.chars() {
+        match (c.is_whitespace(), was_space) {
+            (true, true) => (),
+            (true, false) => collapsed_text.push(' '),
+            (false, _) => collapsed_text.push(c),
+        };
+        was_space = c.is_whitespace();
+    }
+    collapsed_text
+}
+
+/// Returns `markdown` with its first line as is, and every line after it indented by `indent`.
+fn indent_lines(markdown: &str, indent: &str) -> String {
+    let lines: Vec<String> = markdown.lines().enumerate()
+        .map(|(lineno, line)| match (lineno, line.is_empty()) {
+            (0, _) | (_, true) => line.to_string(),
+            _ => format!("{}{}", indent, line),
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// Returns the Markdown of every child of `node`, run together. Spaces at the start of a line are
+/// dropped, as a browser would after a line break.
+fn children_to_markdown(node: NodeRef<Node>) -> String {
+    let mut markdown: String = String::new();
+    for child in node.children() {
+        let child_markdown: String = node_to_markdown(child);
+        match markdown.is_empty() || markdown.ends_with('\n') {
+            true => markdown.push_str(child_markdown.trim_start_matches(' ')),
+            false => markdown.push_str(&child_markdown),
+        };
+    }
+    markdown
+}
+
+/// Returns the text of every descendant of `node`, as is, e.g. for code.
+fn get_raw_text(node: NodeRef<Node>) -> String {
+    node.descendants()
+        .filter_map(|descendant| match descendant.value() {
+            Node::Text(text) => Some(text.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns `markdown` as a block of its own, apart from whatever comes before and after it.
+fn get_block(markdown: &str) -> String {
+    format!("\n\n{}\n\n", markdown.trim())
+}
+
+/// Returns the Markdown for `node`, and everything in it. Blocks are set apart by blank lines, which
+/// `html_to_markdown` trims back down to one.
+fn node_to_markdown(node: NodeRef<Node>) -> String {
+    let element = match node.value() {
+        // Whitespace with a line break in it only lays out the HTML, and is not part of the text.
+        Node::Text(text) if text.trim().is_empty() && text.contains('\n') => return String::new(),
+        Node::Text(text) => return escape_markdown(&collapse_whitespace(text)),
+        Node::Element(element) => element,
+        _ => return String::new(),
+    };
+    match element.name() {
+        "p" | "div" => get_block(&children_to_markdown(node)),
+        "br" => "\\\n".to_string(),
+        "hr" => get_block("* * *"),
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level: usize = element.name()[1..].parse().unwrap_or(1);
+            get_block(&format!("{} {}", "#".repeat(level), children_to_markdown(node).trim()))
+        },
+        "em" | "i" => format!("*{}*", children_to_markdown(node)),
+        "strong" | "b" => format!("**{}**", children_to_markdown(node)),
+        "del" | "s" | "strike" => format!("~~{}~~", children_to_markdown(node)),
+        "sup" => format!("^({})", children_to_markdown(node)),
+        "span" if element.has_class("md-spoiler-text", scraper::CaseSensitivity::CaseSensitive) => format!(">!{}!<", children_to_markdown(node)),
+        "code" => {
+            let code: String = get_raw_text(node);
+            match code.contains('`') {
+                true => format!("`` {} ``", code),
+                false => format!("`{}`", code),
+            }
+        },
+        "pre" => get_block(&format!("```\n{}\n```", get_raw_text(node).trim_end_matches('\n'))),
+        "a" => {
+            let link_text: String = children_to_markdown(node);
+            match element.attr("href") {
+                Some(href) => format!("[{}]({})", link_text, href.replace(' ', "%20").replace('(', "%28").replace(')', "%29")),
+                None => link_text,
+            }
+        },
+        "blockquote" => {
+            let quoted_lines: Vec<String> = html_to_markdown_of(node).lines()
+                .map(|line| match line.is_empty() {
+                    true => ">".to_string(),
+                    false => format!("> {}", line),
+                })
+                .collect();
+            get_block(&quoted_lines.join("\n"))
+        },
+        "ul" | "ol" => {
+            let item_nodes = node.children().filter(|child| matches!(child.value(), Node::Element(child_element) if child_element.name() == "li"));
+            let start: usize = element.attr("start").and_then(|start| start.parse().ok()).unwrap_or(1);
+            let items: Vec<String> = item_nodes.enumerate()
+                .map(|(itemno, item_node)| {
+                    let marker: String = match element.name() {
+                        "ol" => format!("{}.", start + itemno),
+                        _ => "-".to_string(),
+                    };
+                    let indent: String = " ".repeat(marker.len() + 1);
+                    format!("{} {}", marker, indent_lines(&html_to_markdown_of(item_node), &indent))
+                })
+                .collect();
+            get_block(&items.join("\n"))
+        },
+        "table" => {
+            let row_nodes: Vec<NodeRef<Node>> = node.descendants()
+                .filter(|descendant| matches!(descendant.value(), Node::Element(row_element) if row_element.name() == "tr"))
+                .collect();
+            let mut rows: Vec<String> = Vec::new();
+            for (rowno, row_node) in row_nodes.iter().enumerate() {
+                let cells: Vec<String> = row_node.children()
+                    .filter(|child| matches!(child.value(), Node::Element(cell_element) if cell_element.name() == "td" || cell_element.name() == "th"))
+                    .map(|cell_node| children_to_markdown(cell_node).trim().to_string())
+                    .collect();
+                rows.push(format!("| {} |", cells.join(" | ")));
+                if rowno == 0 {
+                    rows.push(format!("|{}", " --- |".repeat(cells.len())));
+                }
+            }
+            get_block(&rows.join("\n"))
+        },
+        "script" | "style" => String::new(),
+        _ => children_to_markdown(node),
+    }
+}
+
+/// Returns the Markdown for everything in `node`, with blocks one blank line apart, and no blank
+/// lines around it.
+fn html_to_markdown_of(node: NodeRef<Node>) -> String {
+    let markdown: String = children_to_markdown(node);
+    let mut lines: Vec<&str> = Vec::new();
+    for line in markdown.trim().lines().map(|line| line.trim_end_matches(' ')) {
+        if !(line.is_empty() && lines.last().is_some_and(|last_line| last_line.is_empty())) {
+            lines.push(line);
+        }
+    }
+    lines.join("\n")
+}
+
+/// Turns the rendered HTML of a comment body, i.e. its '.usertext-body .md' element, back into the
+/// Markdown it was written in: paragraphs, line breaks, emphasis, links, lists, block quotes, code,
+/// headings, tables and spoilers are kept. Plain text is escaped, so that it renders as written.
+/// - md_node: The '.md' element of the comment.
+pub fn html_to_markdown(md_node: ElementRef) -> String {
+    html_to_markdown_of(*md_node)
+}
+
+#[cfg(test)]
+mod ama_markdown_tests {
+    use std::collections::HashSet;
+
+    use scraper::{Html, Selector};
+
+    use crate::ama_config::AmaConfig;
+    use crate::ama_export::{get_test_exchange, Exchange};
+    use crate::ama_scraper::CommentMeta;
+
+    #[test]
+    fn test_get_anchor() {
+        let mut used_anchors: HashSet<String> = HashSet::new();
+        assert_eq!(super::get_anchor("Daron Nefcy", &mut used_anchors), "daron-nefcy");
+        assert_eq!(super::get_anchor("Q&A: Part_2!", &mut used_anchors), "qa-part_2");
+        assert_eq!(super::get_anchor("Daron Nefcy", &mut used_anchors), "daron-nefcy-1");
+        assert_eq!(super::escape_markdown("Joe_Zt *said* [this]"), "Joe\\_Zt \\*said\\* \\[this\\]");
+    }
+
+    #[test]
+    fn test_write_book() {
+        let ama_config = AmaConfig::default();
+        let exchanges: Vec<Exchange> = vec![
+            Exchange {
+                answer_text: "Yes!\n\nDefinitely.".to_string(),
+                answer_meta: CommentMeta { score: Some(10), ..Default::default() },
+                ..get_test_exchange("Daron Nefcy", "fan_1", "id_fan_1")
+            },
+            Exchange { answer_text: "No.".to_string(), ..get_test_exchange("Adam McArthur", "fan_2", "id_fan_2") },
+            Exchange {
+                answer_text: "Maybe\nso".to_string(),
+                answer_meta: CommentMeta { score: Some(5), ..Default::default() },
+                ..get_test_exchange("Daron Nefcy", "fan_3", "id_fan_3")
+            },
+        ];
+        let mut raw_book: Vec<u8> = Vec::new();
+        super::write_book(&ama_config, &exchanges, &mut raw_book).unwrap();
+        let raw_book: String = String::from_utf8(raw_book).unwrap();
+        // Chapters keep the order in which their creators first appear.
+        assert!(raw_book.contains("1. [Daron Nefcy](#daron-nefcy) (2 exchanges)\n2. [Adam McArthur](#adam-mcarthur) (1 exchanges)\n"));
+        assert!(raw_book.contains("| Daron Nefcy | 2 | 4 | 2 | 2 | 15 |\n| Adam McArthur | 1 | 1 | 1 | 1 | unknown |\n"));
+        assert!(raw_book.find("## Daron Nefcy").unwrap() < raw_book.find("## Adam McArthur").unwrap());
+        assert!(raw_book.contains("### 2. fan\\_3\n"));
+        assert!(raw_book.contains("> Maybe\\\n> so\n"));
+        assert!(raw_book.contains("**Answer:**\n\n> Yes!\n>\n> Definitely.\n\n[View on Reddit](https://old.reddit.com/id_fan_1)\n"));
+        // The same exchanges make the same book.
+        let mut raw_book_again: Vec<u8> = Vec::new();
+        super::write_book(&ama_config, &exchanges, &mut raw_book_again).unwrap();
+        assert_eq!(raw_book.as_bytes(), raw_book_again.as_slice());
+    }
+
+    fn get_markdown(md_html: &str) -> String {
+        let parsed_html: Html = Html::parse_fragment(&format!("<div class=\"md\">{}</div>", md_html));
+        let md_selector: Selector = Selector::parse(".md").unwrap();
+        super::html_to_markdown(parsed_html.select(&md_selector).next().unwrap())
+    }
+
+    #[test]
+    fn test_html_to_markdown() {
+        assert_eq!(get_markdown("<p>First <em>one</em>, then <strong>two</strong>.</p>\n\n<p>Line 1<br/>\nLine 2</p>\n"), "First *one*, then **two**.\n\nLine 1\\\nLine 2");
+        assert_eq!(get_markdown("<p>See <a href=\"https://example.com/a_(b)\">this_link</a> and <code>x*y</code>.</p>"), "See [this\\_link](https://example.com/a_%28b%29) and `x*y`.");
+        assert_eq!(
+            get_markdown("<ol>\n<li>One</li>\n<li>Two\n<ul>\n<li>Nested</li>\n</ul></li>\n</ol>\n<blockquote>\n<p>Quoted</p>\n<p>twice</p>\n</blockquote>"),
+            "1. One\n2. Two\n\n   - Nested\n\n> Quoted\n>\n> twice",
+        );
+        assert_eq!(get_markdown("<p>Spoiler: <span class=\"md-spoiler-text\">Star wins</span> <del>not</del></p>"), "Spoiler: >!Star wins!< ~~not~~");
+        assert_eq!(get_markdown("<h2>Title</h2><pre><code>let x = 1;\n</code></pre><hr/>"), "## Title\n\n```\nlet x = 1;\n```\n\n* * *");
+        assert_eq!(get_markdown("<table><thead><tr><th>A</th><th>B</th></tr></thead><tbody><tr><td>1</td><td>2</td></tr></tbody></table>"), "| A | B |\n| --- | --- |\n| 1 | 2 |");
+    }
+
+}
@@ -0,0 +1,414 @@
+//! This module defines the tables of the archive database, and upgrades older databases in place.
+//! - migrate: Brings a database up to the latest schema version, creating it if need be.
+//! - open_cnxn: Opens a migrated database with its foreign keys enforced.
+//! - delete_unlinked_rows: Deletes the queries, comments and failures that no record links to.
+//! - get_schema_version: Returns the schema version of a database.
+//! - SCHEMA_VERSION: The version that `migrate` brings every database up to.
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+/// One step of the schema. Each step runs in a transaction of its own, and is recorded in
+/// `schema_version` once it succeeds.
+struct Migration {
+    version: u32,
+    description: &'static str,
+    apply: fn(&rusqlite::Transaction) -> Result<()>,
+}
+
+/// Every step of the schema, in the order they are applied. Databases made before `schema_version`
+/// existed are at version 0, so every step has to cope with tables that an older build made already.
+const MIGRATIONS: [Migration; 9] = [
+    Migration {
+        version: 1,
+        description: "Create the index and query tables.",
+        apply: create_base_tables,
+    },
+    Migration {
+        version: 2,
+        description: "Create the table of queries that could not be fetched.",
+        apply: create_failures_table,
+    },
+    Migration {
+        version: 3,
+        description: "Add comment metadata to the query table.",
+        apply: add_meta_columns,
+    },
+    Migration {
+        version: 4,
+        description: "Create the table of comments on each query's page.",
+        apply: create_thread_table,
+    },
+    Migration {
+        version: 5,
+        description: "Add keys, foreign keys and indexes.",
+        apply: add_keys,
+    },
+    Migration {
+        version: 6,
+        description: "Create the table of hand edits imported from the filetree.",
+        apply: create_edits_table,
+    },
+    Migration {
+        version: 7,
+        description: "Index the text of every query for full-text search.",
+        apply: create_search_table,
+    },
+    Migration {
+        version: 8,
+        description: "Record the position of each record in the compendium.",
+        apply: add_index_position,
+    },
+    Migration {
+        version: 9,
+        description: "Add the Markdown of each query and comment.",
+        apply: add_markdown_columns,
+    },
+];
+
+/// The version that `migrate` brings every database up to.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+/// Columns stored for both the question and the answer, as '{question,answer}_{name}'.
+const META_COLUMNS: [(&str, &str); 6] = [
+    ("comment_id", "TEXT"),
+    ("author", "TEXT"),
+    ("datetime", "TEXT"),
+    ("score", "INTEGER"),
+    ("edited", "TEXT"),
+    ("permalink", "TEXT"),
+];
+
+fn create_base_tables(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ama_index (
+            url_id TEXT,
+            cc_name TEXT,
+            fan_name TEXT
+        );
+        CREATE TABLE IF NOT EXISTS ama_queries (
+            url_id TEXT PRIMARY KEY,
+            question_text TEXT NOT NULL,
+            answer_text TEXT NOT NULL
+        );"
+    )?;
+    Ok(())
+}
+
+fn create_failures_table(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ama_failures (
+            url_id TEXT PRIMARY KEY,
+            num_attempts INTEGER NOT NULL,
+            last_error TEXT NOT NULL
+        );"
+    )?;
+    Ok(())
+}
+
+fn add_meta_columns(tx: &rusqlite::Transaction) -> Result<()> {
+    let column_names: Vec<String> = get_column_names(tx, "ama_queries")?;
+    for role in ["question", "answer"] {
+        for (name, sql_type) in META_COLUMNS {
+            let column_name: String = format!("{}_{}", role, name);
+            if !column_names.contains(&column_name) {
+                tx.execute(&format!("ALTER TABLE ama_queries ADD COLUMN {} {};", column_name, sql_type), ())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn create_thread_table(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ama_comments (
+            url_id TEXT NOT NULL,
+            comment_id TEXT NOT NULL,
+            parent_id TEXT,
+            depth INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            body_text TEXT,
+            author TEXT,
+            datetime TEXT,
+            score INTEGER,
+            edited TEXT,
+            permalink TEXT,
+            PRIMARY KEY (url_id, comment_id)
+        );"
+    )?;
+    Ok(())
+}
+
+/// SQLite cannot add keys to a table, so every table is rebuilt with them, and its rows copied over.
+/// Each record of `ama_index` is an exchange of its own, so no two of them may share a url_id. Rows
+/// that no record links to any more are dropped, since the foreign keys would refuse them.
+fn add_keys(tx: &rusqlite::Transaction) -> Result<()> {
+    let duplicate_urlids: Vec<String> = {
+        let mut stmt: rusqlite::Statement = tx.prepare("SELECT url_id FROM ama_index GROUP BY url_id HAVING COUNT(url_id) > 1;")?;
+        let urlid_iter = stmt.query_map([], |row| row.get(0))?;
+        urlid_iter.collect::<rusqlite::Result<Vec<String>>>()?
+    };
+    if !duplicate_urlids.is_empty() {
+        return Err(Error::Validation(format!("Several records of 'ama_index' share the url_ids {:?}. Add corrections for them, and run 'fix' before upgrading.", duplicate_urlids)));
+    }
+    let meta_columns: Vec<String> = ["question", "answer"].iter()
+        .flat_map(|role| META_COLUMNS.iter().map(move |(name, _)| format!("{}_{}", role, name)))
+        .collect();
+    let meta_column_defs: Vec<String> = ["question", "answer"].iter()
+        .flat_map(|role| META_COLUMNS.iter().map(move |(name, sql_type)| format!("{}_{} {}", role, name, sql_type)))
+        .collect();
+    tx.execute_batch(&format!(
+        "CREATE TABLE new_ama_index (
+            url_id TEXT NOT NULL UNIQUE,
+            cc_name TEXT NOT NULL,
+            fan_name TEXT NOT NULL,
+            PRIMARY KEY (cc_name, fan_name)
+        );
+        INSERT INTO new_ama_index (url_id, cc_name, fan_name) SELECT url_id, cc_name, fan_name FROM ama_index;
+        DROP TABLE ama_index;
+        ALTER TABLE new_ama_index RENAME TO ama_index;
+        CREATE INDEX ama_index_cc_name ON ama_index (cc_name);
+
+        CREATE TABLE new_ama_queries (
+            url_id TEXT PRIMARY KEY REFERENCES ama_index (url_id) ON DELETE CASCADE,
+            question_text TEXT NOT NULL,
+            answer_text TEXT NOT NULL,
+            {meta_column_defs}
+        );
+        INSERT INTO new_ama_queries (url_id, question_text, answer_text, {meta_columns})
+            SELECT url_id, question_text, answer_text, {meta_columns} FROM ama_queries;
+        DROP TABLE ama_queries;
+        ALTER TABLE new_ama_queries RENAME TO ama_queries;
+        CREATE INDEX ama_queries_answer_author ON ama_queries (answer_author);
+
+        CREATE TABLE new_ama_failures (
+            url_id TEXT PRIMARY KEY REFERENCES ama_index (url_id) ON DELETE CASCADE,
+            num_attempts INTEGER NOT NULL,
+            last_error TEXT NOT NULL
+        );
+        INSERT INTO new_ama_failures SELECT url_id, num_attempts, last_error FROM ama_failures;
+        DROP TABLE ama_failures;
+        ALTER TABLE new_ama_failures RENAME TO ama_failures;
+
+        CREATE TABLE new_ama_comments (
+            url_id TEXT NOT NULL REFERENCES ama_queries (url_id) ON DELETE CASCADE,
+            comment_id TEXT NOT NULL,
+            parent_id TEXT,
+            depth INTEGER NOT NULL,
+            position INTEGER NOT NULL,
+            body_text TEXT,
+            author TEXT,
+            datetime TEXT,
+            score INTEGER,
+            edited TEXT,
+            permalink TEXT,
+            PRIMARY KEY (url_id, comment_id)
+        );
+        INSERT INTO new_ama_comments SELECT url_id, comment_id, parent_id, depth, position, body_text, author, datetime, score, edited, permalink FROM ama_comments;
+        DROP TABLE ama_comments;
+        ALTER TABLE new_ama_comments RENAME TO ama_comments;
+        CREATE INDEX ama_comments_author ON ama_comments (author);",
+        meta_column_defs = meta_column_defs.join(",\n            "),
+        meta_columns = meta_columns.join(", "),
+    ))?;
+    let () = delete_unlinked_rows(tx)?;
+    Ok(())
+}
+
+/// Deletes the queries, comments and failures whose url_id no record in `ama_index` has. Older
+/// builds left these behind when a record moved or went away, before foreign keys were enforced.
+pub(crate) fn delete_unlinked_rows(tx: &rusqlite::Transaction) -> Result<()> {
+    let _ = tx.execute("DELETE FROM ama_queries WHERE url_id NOT IN (SELECT url_id FROM ama_index);", ())?;
+    let _ = tx.execute("DELETE FROM ama_comments WHERE url_id NOT IN (SELECT url_id FROM ama_queries);", ())?;
+    let _ = tx.execute("DELETE FROM ama_failures WHERE url_id NOT IN (SELECT url_id FROM ama_index);", ())?;
+    Ok(())
+}
+
+fn create_edits_table(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE ama_edits (
+            cc_name TEXT NOT NULL,
+            fan_name TEXT NOT NULL,
+            url_id TEXT NOT NULL,
+            field TEXT NOT NULL,
+            old_value TEXT,
+            new_value TEXT NOT NULL,
+            edited_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE INDEX ama_edits_url_id ON ama_edits (url_id);"
+    )?;
+    Ok(())
+}
+
+/// The search table keeps a copy of the text of each query, rather than pointing at 'ama_queries'
+/// by rowid, since VACUUM may renumber the rowids of a table whose key is not an INTEGER.
+fn create_search_table(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE VIRTUAL TABLE ama_search USING fts5 (
+            url_id UNINDEXED,
+            question_text,
+            answer_text,
+            tokenize = 'porter unicode61 remove_diacritics 2'
+        );
+        INSERT INTO ama_search (url_id, question_text, answer_text) SELECT url_id, question_text, answer_text FROM ama_queries;
+        CREATE TRIGGER ama_search_insert AFTER INSERT ON ama_queries BEGIN
+            INSERT INTO ama_search (url_id, question_text, answer_text) VALUES (new.url_id, new.question_text, new.answer_text);
+        END;
+        CREATE TRIGGER ama_search_delete AFTER DELETE ON ama_queries BEGIN
+            DELETE FROM ama_search WHERE url_id = old.url_id;
+        END;
+        CREATE TRIGGER ama_search_update AFTER UPDATE OF url_id, question_text, answer_text ON ama_queries BEGIN
+            DELETE FROM ama_search WHERE url_id = old.url_id;
+            INSERT INTO ama_search (url_id, question_text, answer_text) VALUES (new.url_id, new.question_text, new.answer_text);
+        END;"
+    )?;
+    Ok(())
+}
+
+/// Records saved before this version are taken to be in the order in which they were inserted.
+fn add_index_position(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE ama_index ADD COLUMN position INTEGER;
+        UPDATE ama_index SET position = rowid - (SELECT MIN(rowid) FROM ama_index);"
+    )?;
+    Ok(())
+}
+
+/// Records saved before this version keep only their plain text, until they are fetched again.
+fn add_markdown_columns(tx: &rusqlite::Transaction) -> Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE ama_queries ADD COLUMN question_markdown TEXT;
+        ALTER TABLE ama_queries ADD COLUMN answer_markdown TEXT;
+        ALTER TABLE ama_comments ADD COLUMN body_markdown TEXT;"
+    )?;
+    Ok(())
+}
+
+/// Returns the names of the columns of `table_name`.
+fn get_column_names(cnxn: &rusqlite::Connection, table_name: &str) -> Result<Vec<String>> {
+    let mut stmt: rusqlite::Statement = cnxn.prepare("SELECT name FROM pragma_table_info(?1);")?;
+    let column_iter = stmt.query_map([table_name], |row| row.get(0))?;
+    let mut column_names: Vec<String> = Vec::new();
+    for column_name in column_iter {
+        column_names.push(column_name?);
+    }
+    Ok(column_names)
+}
+
+/// Returns the schema version of the database that `cnxn` is open to, i.e. that of the last
+/// migration applied to it. Databases made before versioning are at version 0.
+pub fn get_schema_version(cnxn: &rusqlite::Connection) -> Result<u32> {
+    cnxn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL
+        );",
+        ()
+    )?;
+    let version: Option<u32> = cnxn.query_row("SELECT MAX(version) FROM schema_version;", [], |row| row.get(0))?;
+    Ok(version.unwrap_or(0))
+}
+
+/// Applies every migration that `full_dbpath` has not had yet, in order, and returns the schema
+/// version it ends up at. The database is created if it does not exist.
+/// - full_dbpath: tells the function where the database file is.
+pub fn migrate(full_dbpath: impl AsRef<Path>) -> Result<u32> {
+    let mut cnxn: rusqlite::Connection = rusqlite::Connection::open(&full_dbpath)?;
+    migrate_cnxn(&mut cnxn, full_dbpath.as_ref())
+}
+
+/// Opens `full_dbpath`, migrates it, and turns on foreign keys, which SQLite leaves off for every
+/// new connection. Everything that writes to the database should go through here.
+/// - full_dbpath: tells the function where the database file is.
+pub(crate) fn open_cnxn(full_dbpath: impl AsRef<Path>) -> Result<rusqlite::Connection> {
+    let mut cnxn: rusqlite::Connection = rusqlite::Connection::open(&full_dbpath)?;
+    let _ = migrate_cnxn(&mut cnxn, full_dbpath.as_ref())?;
+    cnxn.pragma_update(None, "foreign_keys", true)?;
+    Ok(cnxn)
+}
+
+/// Does what `migrate` does, over a connection that is already open to `full_dbpath`.
+/// Foreign keys are off while it runs, since some steps rebuild tables that others refer to.
+pub(crate) fn migrate_cnxn(cnxn: &mut rusqlite::Connection, full_dbpath: &Path) -> Result<u32> {
+    cnxn.pragma_update(None, "foreign_keys", false)?;
+    let old_version: u32 = get_schema_version(cnxn)?;
+    if old_version > SCHEMA_VERSION {
+        return Err(Error::Validation(format!("'{}' is at schema version {}, which is newer than this build supports ({}).", full_dbpath.display(), old_version, SCHEMA_VERSION)));
+    }
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version > old_version) {
+        let tx: rusqlite::Transaction = cnxn.transaction()?;
+        let () = (migration.apply)(&tx)?;
+        tx.execute("INSERT INTO schema_version VALUES (?1, ?2);", (migration.version, migration.description))?;
+        tx.commit()?;
+    }
+    if old_version < SCHEMA_VERSION {
+        println!("'{}' upgraded from schema version {} to {}.", full_dbpath.display(), old_version, SCHEMA_VERSION);
+    }
+    Ok(SCHEMA_VERSION)
+}
+
+#[cfg(test)]
+mod ama_schema_tests {
+    use std::fs;
+
+    #[test]
+    fn test_migrate_new_db() {
+        let full_dbpath: &str = "output/ama_schema-new_test.db";
+        let _ = fs::remove_file(full_dbpath);
+        assert_eq!(super::migrate(full_dbpath).unwrap(), super::SCHEMA_VERSION);
+        // Migrating again changes nothing.
+        assert_eq!(super::migrate(full_dbpath).unwrap(), super::SCHEMA_VERSION);
+        let cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath).unwrap();
+        let num_versions: u32 = cnxn.query_row("SELECT COUNT(*) FROM schema_version;", [], |row| row.get(0)).unwrap();
+        let ama_queries_sql: String = cnxn.query_row("SELECT sql FROM sqlite_master WHERE name = 'ama_queries';", [], |row| row.get(0)).unwrap();
+        let _ = fs::remove_file(full_dbpath);
+        assert_eq!(num_versions, super::SCHEMA_VERSION);
+        assert!(ama_queries_sql.contains("REFERENCES ama_index (url_id)"));
+    }
+
+    #[test]
+    fn test_migrate_legacy_db() {
+        // As made by the first build, which had no 'schema_version'.
+        let full_dbpath: &str = "output/ama_schema-legacy_test.db";
+        let _ = fs::remove_file(full_dbpath);
+        let cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath).unwrap();
+        cnxn.execute_batch(
+            "CREATE TABLE ama_index (url_id TEXT, cc_name TEXT, fan_name TEXT);
+            CREATE TABLE ama_queries (url_id TEXT PRIMARY KEY, question_text TEXT NOT NULL, answer_text TEXT NOT NULL);
+            INSERT INTO ama_index VALUES ('url_id1', 'cc_name1', 'fan_name1'), ('url_id2', 'cc_name1', 'fan_name2');
+            INSERT INTO ama_queries VALUES ('url_id1', 'question_text1', 'answer_text1');"
+        ).unwrap();
+        assert_eq!(super::get_schema_version(&cnxn).unwrap(), 0);
+        assert_eq!(super::migrate(full_dbpath).unwrap(), super::SCHEMA_VERSION);
+        let ama_index = crate::ama_indexer::load_ama_index(full_dbpath).unwrap();
+        let ama_queries = crate::ama_scraper::load_ama_queries_from_db(full_dbpath).unwrap();
+        // Records that share a url_id are now refused.
+        let insert_result = cnxn.execute("INSERT INTO ama_index (url_id, cc_name, fan_name) VALUES ('url_id1', 'cc_name2', 'fan_name3');", ());
+        let _ = fs::remove_file(full_dbpath);
+        assert_eq!(ama_index.len(), 2);
+        assert_eq!(ama_queries.len(), 1);
+        assert_eq!(ama_queries[0].answer_text, Some("answer_text1".to_string()));
+        assert!(insert_result.is_err());
+    }
+
+    #[test]
+    fn test_migrate_duplicated_db() {
+        let full_dbpath: &str = "output/ama_schema-duplicated_test.db";
+        let _ = fs::remove_file(full_dbpath);
+        let cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath).unwrap();
+        cnxn.execute_batch(
+            "CREATE TABLE ama_index (url_id TEXT, cc_name TEXT, fan_name TEXT);
+            INSERT INTO ama_index VALUES ('url_id1', 'cc_name1', 'fan_name1'), ('url_id1', 'cc_name1', 'fan_name2');"
+        ).unwrap();
+        let migrate_result = super::migrate(full_dbpath);
+        // The steps before the one that failed are kept.
+        let version: u32 = super::get_schema_version(&cnxn).unwrap();
+        let _ = fs::remove_file(full_dbpath);
+        match migrate_result {
+            Err(super::Error::Validation(_)) => (),
+            other => panic!("Expected a validation error, got: {:?}", other),
+        };
+        assert_eq!(version, 4);
+    }
+
+}
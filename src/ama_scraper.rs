@@ -1,7 +1,14 @@
-use std::path::Path;
+use std::fmt;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+use std::time::Duration;
 
-fn remove_file(full_path: impl AsRef<Path> + std::fmt::Debug) -> () {
+#[cfg(test)]
+fn remove_file(full_path: impl AsRef<Path> + std::fmt::Debug) {
     match fs::remove_file(&full_path) {
         Ok(()) => {
             println!("File removed: {:?}", &full_path);
@@ -17,19 +24,220 @@ fn remove_file(full_path: impl AsRef<Path> + std::fmt::Debug) -> () {
 /// - fetch_ama_query: Fetches text Q&A data from Reddit as text, and returns it as a dict[str, str].
 /// - fetch_ama_queries: Iterates over index, and fetches Q&A data for each entry in the index.
 /// - save_ama_query: Saves a given ama_query, provided it's got the right fields.
+/// - fetch_ama_query_with_retry: Re-fetches a query with exponential backoff until it has an answer.
+/// - save_failed_query: Marks a query whose retries ran out, so that it can be skipped.
+/// - parse_ama_query: Extracts Q&A data from a page that has already been fetched.
+/// - fill_ama_query: Picks the question and answer out of the comments on a page.
+/// - Backend: Whether pages are scraped as HTML, or read as JSON listings.
+/// - save_raw_query: Caches the raw HTML of a fetched page by its url_id.
+/// - load_raw_query: Loads a cached page by its url_id, if it was cached.
+/// - get_html_markdown: Converts the body of a comment back into the Markdown it was written in.
+/// - get_comment_meta: Extracts the author, timestamps, score and permalink of a comment.
+/// - parse_ama_thread: Reconstructs the tree of comments on a page, with parent/child links.
+/// - save_ama_thread_to_db: Saves the comment tree of a query to the 'ama_comments' table.
 //pub mod ama_scraper {
     use scraper::{Html, Selector};
+    use serde::Serialize;
     //use std::path::Path;
-    use rusqlite;
     use scraper::ElementRef;
 
+    use crate::ama_config::{self, AmaConfig};
+    use crate::ama_fetcher::Fetcher;
+    use crate::ama_json;
+    use crate::ama_markdown;
+    use crate::ama_store::Store;
+    use crate::error::{Error, Result};
+
+    /// Contains the metadata that old Reddit shows in the tagline of a comment.
+    #[derive(PartialEq)]
+    #[derive(Debug)]
+    #[derive(Clone)]
+    #[derive(Default)]
+    #[derive(Serialize)]
+    pub struct CommentMeta {
+        pub comment_id: Option<String>, // Reddit's id of the comment, without its 't1_' prefix.
+        pub author: Option<String>, // None if the account was deleted.
+        pub datetime: Option<String>, // ISO 8601, e.g. '2019-08-03T17:01:23+00:00'
+        pub score: Option<i64>, // None if the score was hidden.
+        pub edited: Option<String>, // When the comment was last edited, or None if it never was.
+        pub permalink: Option<String>,
+    }
+
     /// Contains results of fetching from source URLs
     #[derive(PartialEq)]
     #[derive(Debug)]
+    #[derive(Clone)]
+    #[derive(Default)]
     pub struct AmaQuery {
         pub url_id: String,
         pub question_text: Option<String>,
         pub answer_text: Option<String>,
+        pub question_markdown: Option<String>, // Body of the question as it was written, with its formatting.
+        pub answer_markdown: Option<String>,
+        pub question_meta: CommentMeta,
+        pub answer_meta: CommentMeta,
+        pub thread: Vec<AmaComment>, // Every comment on the page. Not loaded by `load_ama_queries_from_db`.
+    }
+
+    /// Contains one comment of the chain shown on a comment page.
+    #[derive(PartialEq)]
+    #[derive(Debug)]
+    #[derive(Clone)]
+    #[derive(Default)]
+    pub struct AmaComment {
+        pub comment_id: String,
+        pub parent_id: Option<String>, // None for the topmost comment on the page, even if it is a reply.
+        pub depth: u32, // Nesting level on the page, starting from 0.
+        pub body_text: Option<String>,
+        pub body_markdown: Option<String>, // Body as it was written, with its formatting.
+        pub meta: CommentMeta,
+    }
+
+    /// Where the comments on a page are read from. Both give the same comments, but JSON listings
+    /// do not break when the markup of the site changes, and keep the Markdown as it was written.
+    #[derive(PartialEq)]
+    #[derive(Debug)]
+    #[derive(Clone, Copy)]
+    #[derive(Default)]
+    pub enum Backend {
+        #[default]
+        Html, // Old Reddit's pages, scraped with the '.usertext-body' selector.
+        Json, // Reddit's '.json' comment listings.
+    }
+
+    impl Backend {
+        /// Returns the extension of the pages that this backend fetches, e.g. for caching them.
+        pub fn get_extension(&self) -> &'static str {
+            match self {
+                Backend::Html => "html",
+                Backend::Json => "json",
+            }
+        }
+
+        /// Returns the URL that this backend fetches for the page at `url`.
+        pub fn get_page_url(&self, url: &str) -> String {
+            match self {
+                Backend::Html => url.to_string(),
+                Backend::Json => ama_json::get_json_url(url),
+            }
+        }
+
+        /// Extracts Q&A data from a page that this backend fetched.
+        /// - ama_config: Names the creators, whose comments are the answers.
+        /// - raw_page: Page as it was fetched.
+        /// - ama_query: dict to store parsed data. Initialize outside function.
+        pub fn parse_ama_query(&self, ama_config: &AmaConfig, raw_page: &str, ama_query: &mut AmaQuery) -> Result<()> {
+            match self {
+                Backend::Html => parse_ama_query(ama_config, raw_page, ama_query),
+                Backend::Json => ama_json::parse_ama_query(ama_config, raw_page, ama_query),
+            }
+        }
+
+        /// Returns the body of the post on a page that this backend fetched, as HTML.
+        pub fn get_post_html(&self, raw_page: &str) -> Result<String> {
+            match self {
+                Backend::Html => Ok(raw_page.to_string()),
+                Backend::Json => ama_json::get_compendium_html(raw_page),
+            }
+        }
+    }
+
+    impl fmt::Display for Backend {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", self.get_extension())
+        }
+    }
+
+    impl FromStr for Backend {
+        type Err = String;
+
+        fn from_str(backend_name: &str) -> std::result::Result<Self, Self::Err> {
+            match backend_name.to_lowercase().as_str() {
+                "html" => Ok(Backend::Html),
+                "json" => Ok(Backend::Json),
+                _ => Err(format!("Unknown backend '{}'. Expected 'html' or 'json'.", backend_name)),
+            }
+        }
+    }
+
+    /// Governs how many times, and how patiently, a query is re-fetched.
+    #[derive(PartialEq)]
+    #[derive(Debug)]
+    #[derive(Clone)]
+    pub struct RetryPolicy {
+        pub max_attempts: u32, // Attempts, including the first, before the query is marked failed.
+        pub base_delay: Duration, // Wait after the first failed attempt; doubles after each one after.
+        pub max_delay: Duration, // Cap on the doubled wait. A 'Retry-After' from the server is not capped.
+        pub jitter: bool, // Waits a random amount between half and all of the wait, so retries don't line up.
+    }
+
+    impl Default for RetryPolicy {
+        fn default() -> Self {
+            RetryPolicy {
+                max_attempts: 5,
+                base_delay: Duration::from_secs(2),
+                max_delay: Duration::from_secs(60),
+                jitter: true,
+            }
+        }
+    }
+
+    /// Returns how long to wait after the given failed attempt.
+    /// - retry_policy: Backoff settings.
+    /// - num_attempts: Number of attempts made so far, starting from 1.
+    /// - retry_after: Wait requested by the server, which is honored if it is longer than the backoff.
+    pub fn get_retry_delay(retry_policy: &RetryPolicy, num_attempts: u32, retry_after: Option<Duration>) -> Duration {
+        let exponent: u32 = num_attempts.saturating_sub(1).min(31);
+        let mut delay: Duration = retry_policy.base_delay
+            .saturating_mul(2u32.pow(exponent))
+            .min(retry_policy.max_delay);
+        if retry_policy.jitter {
+            // RandomState is seeded differently every time, which is random enough to spread retries out.
+            let random: u64 = RandomState::new().build_hasher().finish();
+            let half_delay: Duration = delay / 2;
+            delay = half_delay + half_delay.mul_f64((random % 1000) as f64 / 1000.0);
+        }
+        match retry_after {
+            Some(retry_after) if retry_after > delay => retry_after,
+            _ => delay,
+        }
+    }
+
+    /// Fetches `ama_query` until it has an answer, or until `retry_policy` runs out of attempts.
+    /// Returns the number of attempts made, or the error of the last attempt.
+    /// - fetcher: Where to get the page from, e.g. the live site or a recording.
+    /// - backend: Whether to scrape the page, or read its JSON listing.
+    /// - ama_config: Names the creators, whose comments are the answers.
+    /// - url: source whence data is to be fetched.
+    /// - ama_query: dict to store fetched data. Initialize outside function.
+    /// - retry_policy: How many times to try, and how long to wait in between.
+    /// - pages_dir: Where to cache every page that loads, whether or not it has an answer.
+    pub fn fetch_ama_query_with_retry(fetcher: &dyn Fetcher, backend: Backend, ama_config: &AmaConfig, url: &str, ama_query: &mut AmaQuery, retry_policy: &RetryPolicy, pages_dir: Option<&Path>) -> Result<u32> {
+        let mut num_attempts: u32 = 0;
+        loop {
+            num_attempts += 1;
+            println!("Fetching record... Attempt: {}/{}", num_attempts, retry_policy.max_attempts);
+            let fetch_result: Result<String> = fetch_ama_query(fetcher, backend, ama_config, url, ama_query);
+            if let (Ok(raw_page), Some(pages_dir)) = (&fetch_result, pages_dir) {
+                let () = save_raw_query(raw_page, pages_dir, &ama_query.url_id, backend)?;
+            }
+            let fetch_err: Error = match fetch_result {
+                Ok(_) if ama_query.answer_text.is_some() => return Ok(num_attempts),
+                // A deleted answer looks just like a page that failed to load, so it gets retried too.
+                Ok(_) => Error::Validation(format!("No answer found for 'url_id' {}.", ama_query.url_id)),
+                Err(fetch_err) => fetch_err,
+            };
+            if num_attempts >= retry_policy.max_attempts {
+                return Err(fetch_err);
+            }
+            let retry_after: Option<Duration> = match &fetch_err {
+                Error::Network { retry_after, .. } => *retry_after,
+                _ => None,
+            };
+            let delay: Duration = get_retry_delay(retry_policy, num_attempts, retry_after);
+            eprintln!("{} Retrying in {:?}.", fetch_err, delay);
+            thread::sleep(delay);
+        }
     }
 
     /// Helper function to get desired text off page.
@@ -41,95 +249,279 @@ fn remove_file(full_path: impl AsRef<Path> + std::fmt::Debug) -> () {
         Some(buffer)
     }
 
-    /// Fetches `question_text` and `answer_text` values for a given URL.
+    /// Converts the body of a comment back into Markdown, keeping the paragraphs, links, emphasis,
+    /// lists, quotes and spoilers that `get_html_text` drops.
+    /// <div class="usertext-body"><div class="md">...</div></div>
+    pub fn get_html_markdown(usertext_node: ElementRef) -> Option<String> {
+        let md_selector: Selector = Selector::parse(".md").expect("'.md' is a valid selector");
+        usertext_node.select(&md_selector).next()
+            .map(ama_markdown::html_to_markdown)
+    }
+
+    /// Fetches `question_text` and `answer_text` values for a given URL, and returns the raw page
+    /// they were parsed from.
+    /// - fetcher: Where to get the page from, e.g. the live site or a recording.
+    /// - backend: Whether to scrape the page, or read its JSON listing.
+    /// - ama_config: Names the creators, whose comments are the answers.
     /// - url: source whence data is to be fetched.
     /// - ama_query: dict to store fetched data. Initialize outside function.
-    pub fn fetch_ama_query(url: &str, ama_query: &mut AmaQuery) -> () {
-        let request: ureq::Request = ureq::get(url);
-        let raw_html: String = match request.call() {
-            Ok(resp) => resp.into_string().unwrap(),
-            Err(reqerr) => panic!("Unable to get response from '{}': {:?}", url, reqerr),
+    pub fn fetch_ama_query(fetcher: &dyn Fetcher, backend: Backend, ama_config: &AmaConfig, url: &str, ama_query: &mut AmaQuery) -> Result<String> {
+        let raw_page: String = fetcher.fetch(&backend.get_page_url(url))?;
+        let () = backend.parse_ama_query(ama_config, &raw_page, ama_query)?;
+        Ok(raw_page)
+    }
+
+    /// Extracts `question_text` and `answer_text` values from the raw HTML of a comment page.
+    /// The comment whose id is `url_id` is the one the link points to. It is the answer to the
+    /// comment above it, if that is by someone else, and either it is by a creator or none of its
+    /// replies are. Otherwise, it is the question, and the first reply by someone else is the answer,
+    /// if there is one. Replies that the answerer makes to their own answer are appended to it.
+    /// - ama_config: Names the creators, whose comments are the answers.
+    /// - raw_html: Page as it was fetched.
+    /// - ama_query: dict to store parsed data. Initialize outside function.
+    pub fn parse_ama_query(ama_config: &AmaConfig, raw_html: &str, ama_query: &mut AmaQuery) -> Result<()> {
+        let thread: Vec<AmaComment> = parse_ama_thread(raw_html)?;
+        let () = fill_ama_query(ama_config, thread, ama_query);
+        Ok(())
+    }
+
+    /// Picks the question and answer for `ama_query` out of `thread`, as `parse_ama_query` describes,
+    /// and keeps `thread` in it.
+    /// - ama_config: Names the creators, whose comments are the answers.
+    /// - thread: Comments on the page, in the order in which they appear.
+    /// - ama_query: dict to store parsed data. Initialize outside function.
+    pub fn fill_ama_query(ama_config: &AmaConfig, thread: Vec<AmaComment>, ama_query: &mut AmaQuery) {
+        let linked_comment: &AmaComment = match thread.iter().find(|ama_comment| ama_comment.comment_id == ama_query.url_id) {
+            Some(linked_comment) => linked_comment,
+            None => {
+                eprintln!("Linked comment not found for url_id: {:?}.", &ama_query.url_id);
+                ama_query.thread = thread;
+                return;
+            },
         };
-        let parsed_html: Html = Html::parse_document(&raw_html);
-        let usertextbody_selector: Selector = Selector::parse(".usertext-body").unwrap();
-        for (commentno, usertext_node) in parsed_html.select(&usertextbody_selector).enumerate() {
-            match commentno {
-                0 => continue,
-                1 => ama_query.question_text = get_html_text(usertext_node),
-                2 => ama_query.answer_text = get_html_text(usertext_node),
-                _ => eprintln!("Extraneous node found for url_id: {:?}.", &ama_query.url_id),
+        let get_replies = |parent: &AmaComment| -> Vec<&AmaComment> {
+            thread.iter().filter(|ama_comment| ama_comment.parent_id.as_ref() == Some(&parent.comment_id)).collect()
+        };
+        let linked_replies: Vec<&AmaComment> = get_replies(linked_comment);
+        let linked_parent: Option<&AmaComment> = match &linked_comment.parent_id {
+            Some(parent_id) => thread.iter().find(|ama_comment| &ama_comment.comment_id == parent_id),
+            None => None,
+        };
+        let is_by_creator = |ama_comment: &AmaComment| -> bool {
+            ama_comment.meta.author.as_deref().is_some_and(|author| ama_config::is_creator(ama_config, author))
+        };
+        // A fan's "Thanks!" below a linked answer is not an answer to it.
+        let is_linked_answer: bool = is_by_creator(linked_comment) || !linked_replies.iter().any(|reply| is_by_creator(reply));
+        // The answer is never by whoever asked, e.g. a fan's unanswered follow-up to their own question.
+        let linked_answer: Option<&AmaComment> = linked_replies.iter().copied().find(|reply| reply.meta.author != linked_comment.meta.author);
+        let (question, answer): (&AmaComment, Option<&AmaComment>) = match (linked_parent, linked_answer) {
+            (Some(linked_parent), _) if linked_parent.meta.author != linked_comment.meta.author && is_linked_answer => (linked_parent, Some(linked_comment)),
+            (_, Some(linked_answer)) => (linked_comment, Some(linked_answer)),
+            _ => (linked_comment, None),
+        };
+        ama_query.question_text = question.body_text.clone();
+        ama_query.question_markdown = question.body_markdown.clone();
+        ama_query.question_meta = question.meta.clone();
+        if let Some(answer) = answer {
+            let mut answer_text: String = answer.body_text.clone().unwrap_or_default();
+            let mut answer_markdown: Option<String> = answer.body_markdown.clone();
+            let mut answer_part: &AmaComment = answer;
+            while let Some(next_part) = get_replies(answer_part).into_iter().find(|reply| reply.meta.author.is_some() && reply.meta.author == answer.meta.author) {
+                answer_text.push_str(next_part.body_text.as_deref().unwrap_or_default());
+                // Each part is a comment of its own, so it starts a new paragraph.
+                if let (Some(answer_markdown), Some(next_markdown)) = (answer_markdown.as_mut(), &next_part.body_markdown) {
+                    answer_markdown.push_str("\n\n");
+                    answer_markdown.push_str(next_markdown);
+                }
+                answer_part = next_part;
             }
+            ama_query.answer_text = Some(answer_text);
+            ama_query.answer_markdown = answer_markdown;
+            ama_query.answer_meta = answer.meta.clone();
         }
+        ama_query.thread = thread;
     }
 
-    /// Creates database file with specified filename, and initializes queries table.
-    pub fn create_db(full_dbpath: &str) -> () {
-        let cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath).unwrap();
-        match cnxn.execute(
-            "CREATE TABLE ama_queries (
-                url_id TEXT PRIMARY KEY,
-                question_text TEXT NOT NULL,
-                answer_text TEXT NOT NULL
-            );",
-            ()
-        ) {
-            Ok(_) => println!("ama_queries table created in '{}'.", full_dbpath),
-            Err(_) => panic!("The table 'ama_queries' already exists in '{}'. Aborting.", full_dbpath),
+    /// Reconstructs the tree of comments on a comment page, in the order in which they appear.
+    /// <div class="thing comment" data-fullname="t1_{comment_id}">
+    ///   <div class="entry">...<div class="usertext-body">...</div></div>
+    ///   <div class="child"><div class="thing comment">...</div></div>
+    /// - raw_html: Page as it was fetched.
+    pub fn parse_ama_thread(raw_html: &str) -> Result<Vec<AmaComment>> {
+        let parsed_html: Html = Html::parse_document(raw_html);
+        let comment_selector: Selector = Selector::parse(".thing.comment").expect("'.thing.comment' is a valid selector");
+        let usertextbody_selector: Selector = Selector::parse(":scope > .entry .usertext-body").expect("':scope > .entry .usertext-body' is a valid selector");
+        let is_comment_node = |element_ref: &ElementRef| -> bool {
+            let element = element_ref.value();
+            element.has_class("thing", scraper::CaseSensitivity::CaseSensitive) && element.has_class("comment", scraper::CaseSensitivity::CaseSensitive)
         };
+        let mut thread: Vec<AmaComment> = Vec::new();
+        for comment_node in parsed_html.select(&comment_selector) {
+            let meta: CommentMeta = get_comment_meta(comment_node);
+            let comment_id: String = match &meta.comment_id {
+                Some(comment_id) => comment_id.clone(),
+                None => return Err(Error::Parse("Found a comment without a 'data-fullname'.".to_string())),
+            };
+            let ancestor_nodes: Vec<ElementRef> = comment_node.ancestors()
+                .filter_map(ElementRef::wrap)
+                .filter(is_comment_node)
+                .collect();
+            let parent_id: Option<String> = ancestor_nodes.first()
+                .and_then(|parent_node| get_comment_meta(*parent_node).comment_id);
+            let usertext_node: Option<ElementRef> = comment_node.select(&usertextbody_selector).next();
+            let body_text: Option<String> = usertext_node.and_then(get_html_text);
+            let body_markdown: Option<String> = usertext_node.and_then(get_html_markdown);
+            thread.push(
+                AmaComment {
+                    comment_id,
+                    parent_id,
+                    depth: ancestor_nodes.len() as u32,
+                    body_text,
+                    body_markdown,
+                    meta,
+                }
+            );
+        }
+        Ok(thread)
     }
 
-    /// Creates 'ama_queries' table in `full_dbpath`, and saves `ama_query` into the table.
+    /// Extracts the metadata of the comment or post in `thing_node`. Fields that the markup does
+    /// not have are left as None.
+    /// <div class="thing comment" data-fullname=... data-author=... data-permalink=...>
+    ///   <div class="entry"><p class="tagline">...<span class="score unvoted" title=...>
+    ///   <time class="live-timestamp" datetime=...><time class="edited-timestamp" datetime=...></p>
+    pub fn get_comment_meta(thing_node: ElementRef) -> CommentMeta {
+        let mut comment_meta = CommentMeta {
+            comment_id: thing_node.attr("data-fullname")
+                .map(|fullname| match fullname.split_once('_') {
+                    Some((_, comment_id)) => comment_id.to_string(),
+                    None => fullname.to_string(),
+                }),
+            author: thing_node.attr("data-author").map(|author| author.to_string()),
+            permalink: thing_node.attr("data-permalink")
+                .map(|permalink| format!("{}{}", ama_config::REDDIT_HOST, permalink)),
+            ..Default::default()
+        };
+        // The tagline sits in the comment's own entry, apart from the replies beneath it.
+        let tagline_selector: Selector = Selector::parse(":scope > .entry > .tagline").expect("':scope > .entry > .tagline' is a valid selector");
+        let tagline_node: ElementRef = match thing_node.select(&tagline_selector).next() {
+            Some(tagline_node) => tagline_node,
+            None => return comment_meta,
+        };
+        let score_selector: Selector = Selector::parse(".score.unvoted").expect("'.score.unvoted' is a valid selector");
+        comment_meta.score = tagline_node.select(&score_selector).next()
+            .and_then(|score_node| score_node.attr("title"))
+            .and_then(|score| score.parse::<i64>().ok());
+        let datetime_selector: Selector = Selector::parse("time.live-timestamp").expect("'time.live-timestamp' is a valid selector");
+        comment_meta.datetime = tagline_node.select(&datetime_selector).next()
+            .and_then(|time_node| time_node.attr("datetime"))
+            .map(|datetime| datetime.to_string());
+        let edited_selector: Selector = Selector::parse("time.edited-timestamp").expect("'time.edited-timestamp' is a valid selector");
+        comment_meta.edited = tagline_node.select(&edited_selector).next()
+            .and_then(|time_node| time_node.attr("datetime"))
+            .map(|datetime| datetime.to_string());
+        comment_meta
+    }
+
+    /// Returns where the page for `url_id` is cached, i.e. './pages_dir/{url_id}.html', or
+    /// './pages_dir/{url_id}.json' for a JSON listing.
+    pub fn get_raw_query_path(pages_dir: &Path, url_id: &str, backend: Backend) -> PathBuf {
+        pages_dir.join(format!("{}.{}", url_id, backend.get_extension()))
+    }
+
+    /// Caches the raw page for `url_id` in `pages_dir`, replacing any earlier copy.
+    /// - raw_page: Page as it was fetched.
+    /// - pages_dir: Directory of cached pages. Created if it does not exist.
+    /// - url_id: Query the page belongs to.
+    /// - backend: Backend that fetched the page.
+    pub fn save_raw_query(raw_page: &str, pages_dir: &Path, url_id: &str, backend: Backend) -> Result<()> {
+        if let Err(io_err) = fs::create_dir_all(pages_dir) {
+            return Err(Error::io(pages_dir, io_err));
+        }
+        let raw_query_path: PathBuf = get_raw_query_path(pages_dir, url_id, backend);
+        match fs::write(&raw_query_path, raw_page) {
+            Ok(()) => Ok(()),
+            Err(io_err) => Err(Error::io(&raw_query_path, io_err)),
+        }
+    }
+
+    /// Loads the page for `url_id` that `backend` cached in `pages_dir`, or None if it never did.
+    pub fn load_raw_query(pages_dir: &Path, url_id: &str, backend: Backend) -> Result<Option<String>> {
+        let raw_query_path: PathBuf = get_raw_query_path(pages_dir, url_id, backend);
+        match fs::read_to_string(&raw_query_path) {
+            Ok(raw_page) => Ok(Some(raw_page)),
+            Err(io_err) if io_err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(io_err) => Err(Error::io(&raw_query_path, io_err)),
+        }
+    }
+
+    /// Saves `thread` as the comments on the page for `url_id`, replacing any saved earlier.
+    /// Returns the number of comments saved.
+    /// - url_id: Query the page belongs to.
+    /// - thread: Comments in the order in which they appear on the page.
+    /// - full_dbpath: tells the function where the database file is.
+    pub fn save_ama_thread_to_db(url_id: &str, thread: &[AmaComment], full_dbpath: impl AsRef<Path>) -> Result<usize> {
+        Store::open(full_dbpath)?.save_ama_thread(url_id, thread)
+    }
+
+    /// Loads the comments on the page for `url_id`, in the order in which they appeared.
+    pub fn load_ama_thread_from_db(url_id: &str, full_dbpath: impl AsRef<Path>) -> Result<Vec<AmaComment>> {
+        Store::open(full_dbpath)?.load_ama_thread(url_id)
+    }
+
+    /// Saves `ama_query` into the 'ama_queries' table in `full_dbpath`, together with its thread.
+    /// Returns the number of rows written, the query's and its comments'.
     /// - ama_query: populated dict to be loaded into the database.
     /// - full_dbpath: tells the function where the database file is.
-    pub fn save_ama_query_to_db(ama_query: AmaQuery, full_dbpath: impl AsRef<Path>) -> rusqlite::Result<usize> {
-        let cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath).unwrap();
-        // Begin data dump here.
-        cnxn.execute(
-            "INSERT INTO ama_queries VALUES (?1, ?2, ?3);",
-            (
-                ama_query.url_id,
-                ama_query.question_text.unwrap(),
-                ama_query.answer_text.unwrap(),
-            )
-        )?;
-        // Learn how to get length of INSERT result.
-        Ok(0)
+    pub fn save_ama_query_to_db(ama_query: AmaQuery, full_dbpath: impl AsRef<Path>) -> Result<usize> {
+        Store::open(full_dbpath)?.save_ama_query(&ama_query)
+    }
+
+    /// Deletes the saved query for `url_id` and its thread, so that it can be saved again.
+    pub fn delete_ama_query_from_db(url_id: &str, full_dbpath: impl AsRef<Path>) -> Result<usize> {
+        Store::open(full_dbpath)?.delete_ama_query(url_id)
+    }
+
+    /// Marks the query for `url_id` as failed, replacing any earlier failure.
+    /// - url_id: Query whose retries ran out.
+    /// - num_attempts: How many times it was tried.
+    /// - last_error: What went wrong on the last attempt.
+    /// - full_dbpath: tells the function where the database file is.
+    pub fn save_failed_query(url_id: &str, num_attempts: u32, last_error: &Error, full_dbpath: &str) -> Result<usize> {
+        Store::open(full_dbpath)?.save_failed_query(url_id, num_attempts, last_error)
+    }
+
+    /// Clears the failure mark for `url_id`, once it has been fetched after all.
+    pub fn clear_failed_query(url_id: &str, full_dbpath: &str) -> Result<usize> {
+        Store::open(full_dbpath)?.clear_failed_query(url_id)
+    }
+
+    /// Loads the url_ids of every query that is marked failed.
+    pub fn load_failed_urlids(full_dbpath: &str) -> Result<Vec<String>> {
+        Store::open(full_dbpath)?.load_failed_urlids()
     }
 
     /// Loads 'ama_queries' table from `full_dbpath` into List[dict].
     /// - full_dbpath: Tells function where to find `ama_queries`
-    pub fn load_ama_queries_from_db(full_dbpath: impl AsRef<Path>) -> Vec<AmaQuery> {
-        let cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath).unwrap();
-        let mut stmt: rusqlite::Statement = cnxn.prepare(
-            "SELECT url_id, question_text, answer_text FROM ama_queries;"
-            ).unwrap();
-        let ama_query_iter = stmt.query_map(
-            [],
-            |row| {
-                Ok(
-                    AmaQuery {
-                        url_id: row.get(0).unwrap(),
-                        question_text: Some(row.get(1).unwrap()),
-                        answer_text: Some(row.get(2).unwrap()),
-                    }
-                )
-            }
-        ).unwrap();
-        let mut ama_queries: Vec<AmaQuery> = Vec::new();
-        for ama_query in ama_query_iter {
-            ama_queries.push(ama_query.unwrap());
-        }
-        ama_queries
+    pub fn load_ama_queries_from_db(full_dbpath: impl AsRef<Path>) -> Result<Vec<AmaQuery>> {
+        Store::open(full_dbpath)?.load_ama_queries()
     }
 
 //}
 
 #[cfg(test)]
 mod ama_scraper_tests {
-    use super;
     use super::remove_file;
-    use rusqlite;
     use scraper::{Html, Selector};
+    use std::fs;
+    use std::path::Path;
+    use std::time::Duration;
+    use crate::ama_config::AmaConfig;
+    use crate::ama_fetcher::ReplayFetcher;
+
+    fn get_test_config() -> AmaConfig {
+        crate::load_ama_config("tests/fixtures/test_ama.toml").unwrap()
+    }
 
     #[test]
     fn test_get_html_text() {
@@ -155,16 +547,38 @@ mod ama_scraper_tests {
             url_id: "evw3fne".to_string(),
             question_text: None,
             answer_text: None,
+            ..Default::default()
+        };
+        let fetcher = ReplayFetcher::from_dir("tests/fixtures/replay").unwrap();
+        let _ = super::fetch_ama_query(&fetcher, super::Backend::Html, &AmaConfig::default(), url, &mut ama_query).unwrap();
+        assert!(ama_query.question_text.is_some(), "ama_query.question_text is unexpectedly None. Inspect!");
+        assert!(ama_query.answer_text.is_some(), "ama_query.answer_text is unexpectedly None. Inspect!");
+    }
+
+    #[test]
+    fn test_fetch_ama_query_with_retry() {
+        let fetcher = ReplayFetcher::from_dir("tests/fixtures/replay").unwrap();
+        let retry_policy = super::RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            jitter: false,
+        };
+        let mut ama_query = super::AmaQuery {
+            url_id: "c00001".to_string(),
+            question_text: None,
+            answer_text: None,
+            ..Default::default()
+        };
+        let url: &str = "https://old.reddit.com/r/testama/comments/abc123/test_ama/c00001/?context=3";
+        assert_eq!(super::fetch_ama_query_with_retry(&fetcher, super::Backend::Html, &get_test_config(), url, &mut ama_query, &retry_policy, None).unwrap(), 1);
+        assert_eq!(ama_query.question_text, Some("What inspired the show?\n\n".to_string()));
+        // Pages that never load give up after the last attempt.
+        let url: &str = "https://old.reddit.com/r/testama/comments/abc123/test_ama/c99999/?context=3";
+        match super::fetch_ama_query_with_retry(&fetcher, super::Backend::Html, &get_test_config(), url, &mut ama_query, &retry_policy, None) {
+            Err(super::Error::Network { .. }) => (),
+            other => panic!("Expected a network error, got: {:?}", other),
         };
-        while let None = ama_query.answer_text {
-            let () = super::fetch_ama_query(url, &mut ama_query);
-        }
-        if let None = ama_query.question_text {
-            panic!("ama_query.question_text is unexpectedly None. Inspect!");
-        }
-        if let None = ama_query.answer_text {
-            panic!("ama_query.answer_text is unexpectedly None. Inspect!");
-        }
     }
 
     #[test]
@@ -173,12 +587,13 @@ mod ama_scraper_tests {
             url_id: "url_id".to_string(),
             question_text: Some("question_text".to_string()),
             answer_text: Some("answer_text".to_string()),
+            ..Default::default()
         };
         let outdir: &str = "output";
         let filename: &str = "ama_query-save_test.db";
         let full_dbpath: String = format!("{}/{}", outdir, filename);
         // remove_file(&full_dbpath);
-        let () = super::create_db(&full_dbpath);
+        let () = index_urlids(&full_dbpath, &["url_id"]);
         match super::save_ama_query_to_db(ama_query, &full_dbpath) {
             Ok(_) => println!("AmaQuery successfully saved to database."),
             Err(sql_save_err) => panic!("Problem saving to database: {:?}", sql_save_err),
@@ -196,6 +611,7 @@ mod ama_scraper_tests {
                         url_id: row.get(0).unwrap(),
                         question_text: Some(row.get(1).unwrap()),
                         answer_text: Some(row.get(2).unwrap()),
+                        ..Default::default()
                     }
                 )
             }
@@ -204,11 +620,13 @@ mod ama_scraper_tests {
             url_id: "url_id".to_string(),
             question_text: Some("question_text".to_string()),
             answer_text: Some("answer_text".to_string()),
+            ..Default::default()
         };
         let mut actual = super::AmaQuery {
             url_id: String::new(),
             question_text: None,
             answer_text: None,
+            ..Default::default()
         };
         for ama_query in ama_query_iter {
             actual = ama_query.unwrap();
@@ -217,24 +635,265 @@ mod ama_scraper_tests {
         remove_file(full_dbpath);
     }
 
-    fn get_db_cnxn(full_dbpath: &str) -> rusqlite::Connection {
-        let cnxn: rusqlite::Connection = rusqlite::Connection::open(full_dbpath).unwrap();
-        match cnxn.execute(
-            "CREATE TABLE IF NOT EXISTS ama_queries (
-                url_id TEXT PRIMARY KEY,
-                question_text TEXT NOT NULL,
-                answer_text TEXT NOT NULL
-            );",
-            ()
-        ) {
-            Ok(_) => {
-                println!("Table created.");
+    #[test]
+    fn test_save_incomplete_ama_query_to_db() {
+        let ama_query = super::AmaQuery {
+            url_id: "url_id".to_string(),
+            question_text: Some("question_text".to_string()),
+            answer_text: None,
+            ..Default::default()
+        };
+        match super::save_ama_query_to_db(ama_query, "output/ama_query-incomplete_test.db") {
+            Err(super::Error::Validation(_)) => (),
+            other => panic!("Expected a validation error, got: {:?}", other),
+        };
+        remove_file("output/ama_query-incomplete_test.db");
+    }
+
+    #[test]
+    fn test_raw_query_cache() {
+        let pages_dir: &Path = Path::new("output/ama_query-cache_test");
+        let raw_html: String = fs::read_to_string("tests/fixtures/replay/old.reddit.com_r_testama_comments_abc123_test_ama_c00005__context_3").unwrap();
+        assert_eq!(super::load_raw_query(pages_dir, "c00005", super::Backend::Html).unwrap(), None);
+        let () = super::save_raw_query(&raw_html, pages_dir, "c00005", super::Backend::Html).unwrap();
+        let cached_html: Option<String> = super::load_raw_query(pages_dir, "c00005", super::Backend::Html).unwrap();
+        // Each backend caches its pages apart from the other's.
+        assert_eq!(super::load_raw_query(pages_dir, "c00005", super::Backend::Json).unwrap(), None);
+        let _ = fs::remove_dir_all(pages_dir);
+        assert_eq!(cached_html.as_ref(), Some(&raw_html));
+        // The cached page parses just like the fetched one.
+        let mut ama_query = super::AmaQuery {
+            url_id: "c00005".to_string(),
+            question_text: None,
+            answer_text: None,
+            ..Default::default()
+        };
+        let () = super::parse_ama_query(&get_test_config(), &cached_html.unwrap(), &mut ama_query).unwrap();
+        assert_eq!(ama_query.question_text, Some("Bob, what is your favorite episode?\n\n".to_string()));
+        // The Markdown keeps the quote, the emphasis and the spoiler that the plain text drops.
+        assert_eq!(ama_query.question_markdown, Some("Bob, what is your favorite episode?".to_string()));
+        assert_eq!(ama_query.answer_markdown, Some("> favorite episode\n\nDefinitely **the finale**, because >!everyone comes home!<.".to_string()));
+    }
+
+    #[test]
+    fn test_get_comment_meta() {
+        let raw_html: String = fs::read_to_string("tests/fixtures/replay/old.reddit.com_r_testama_comments_abc123_test_ama_c00001__context_3").unwrap();
+        let mut ama_query = super::AmaQuery {
+            url_id: "c00001".to_string(),
+            ..Default::default()
+        };
+        let () = super::parse_ama_query(&get_test_config(), &raw_html, &mut ama_query).unwrap();
+        let expected = super::CommentMeta {
+            comment_id: Some("c00001".to_string()),
+            author: Some("fan_one".to_string()),
+            datetime: Some("2019-08-03T17:01:23+00:00".to_string()),
+            score: Some(12),
+            edited: None,
+            permalink: Some("https://old.reddit.com/r/testama/comments/abc123/test_ama/c00001/".to_string()),
+        };
+        assert_eq!(ama_query.question_meta, expected);
+        assert_eq!(ama_query.answer_meta.author, Some("alice_author".to_string()));
+        assert_eq!(ama_query.answer_meta.score, Some(30));
+        // An edited answer keeps both when it was posted, and when it was last edited.
+        let raw_html: String = fs::read_to_string("tests/fixtures/replay/old.reddit.com_r_testama_comments_abc123_test_ama_c00003__context_3").unwrap();
+        let mut ama_query = super::AmaQuery {
+            url_id: "c00003".to_string(),
+            ..Default::default()
+        };
+        let () = super::parse_ama_query(&get_test_config(), &raw_html, &mut ama_query).unwrap();
+        let expected = super::CommentMeta {
+            comment_id: Some("c00004".to_string()),
+            author: Some("alice_author".to_string()),
+            datetime: Some("2019-08-03T17:30:00+00:00".to_string()),
+            score: Some(44),
+            edited: Some("2019-08-03T18:02:10+00:00".to_string()),
+            permalink: Some("https://old.reddit.com/r/testama/comments/abc123/test_ama/c00004/".to_string()),
+        };
+        assert_eq!(ama_query.answer_meta, expected);
+    }
+
+    #[test]
+    fn test_parse_ama_query_multipart() {
+        // The answerer's reply to their own answer is part of it; the lurker's reply is not.
+        let raw_html: String = fs::read_to_string("tests/fixtures/replay/old.reddit.com_r_testama_comments_abc123_test_ama_c00003__context_3").unwrap();
+        let mut ama_query = super::AmaQuery {
+            url_id: "c00003".to_string(),
+            ..Default::default()
+        };
+        let () = super::parse_ama_query(&get_test_config(), &raw_html, &mut ama_query).unwrap();
+        assert_eq!(ama_query.question_text, Some("Will there be a sequel?\n\n".to_string()));
+        assert_eq!(ama_query.answer_text, Some("Not at this time.\n\nWell, never say never!\n\n".to_string()));
+    }
+
+    #[test]
+    fn test_parse_ama_query_in_context() {
+        // The linked follow-up sits beneath two comments that are shown only for context.
+        let raw_html: String = fs::read_to_string("tests/fixtures/replay/old.reddit.com_r_testama_comments_abc123_test_ama_c00012__context_3").unwrap();
+        let mut ama_query = super::AmaQuery {
+            url_id: "c00012".to_string(),
+            ..Default::default()
+        };
+        let () = super::parse_ama_query(&get_test_config(), &raw_html, &mut ama_query).unwrap();
+        assert_eq!(ama_query.question_text, Some("One more: what are you working on next?\n\n".to_string()));
+        assert_eq!(ama_query.answer_text, Some("A new pilot,\n\nwhich I cannot talk about yet.\n\n".to_string()));
+        assert_eq!(ama_query.question_meta.comment_id, Some("c00012".to_string()));
+        assert_eq!(ama_query.answer_meta.comment_id, Some("c00013".to_string()));
+        // Linking to the answer instead finds the same exchange.
+        let mut ama_query = super::AmaQuery {
+            url_id: "c00013".to_string(),
+            ..Default::default()
+        };
+        let () = super::parse_ama_query(&get_test_config(), &raw_html, &mut ama_query).unwrap();
+        assert_eq!(ama_query.question_meta.comment_id, Some("c00012".to_string()));
+        assert_eq!(ama_query.answer_text, Some("A new pilot,\n\nwhich I cannot talk about yet.\n\n".to_string()));
+        // A comment below one by the same author answers nothing.
+        let mut ama_query = super::AmaQuery {
+            url_id: "c00014".to_string(),
+            ..Default::default()
+        };
+        let () = super::parse_ama_query(&get_test_config(), &raw_html, &mut ama_query).unwrap();
+        assert_eq!(ama_query.question_meta.comment_id, Some("c00014".to_string()));
+        assert_eq!(ama_query.answer_text, None);
+    }
+
+    #[test]
+    fn test_parse_ama_query_thanked() {
+        // The link points at the creator's answer, which the fan thanked in a reply of their own.
+        let raw_html: String = fs::read_to_string("tests/fixtures/replay/old.reddit.com_r_testama_comments_abc123_test_ama_c00016__context_3").unwrap();
+        let mut ama_query = super::AmaQuery {
+            url_id: "c00016".to_string(),
+            ..Default::default()
+        };
+        let () = super::parse_ama_query(&get_test_config(), &raw_html, &mut ama_query).unwrap();
+        assert_eq!(ama_query.question_text, Some("Where do your ideas come from?\n\n".to_string()));
+        assert_eq!(ama_query.answer_text, Some("The ocean, mostly.\n\n".to_string()));
+        assert_eq!(ama_query.answer_meta.author, Some("alice_author".to_string()));
+    }
+
+    #[test]
+    fn test_parse_ama_thread() {
+        let raw_html: String = fs::read_to_string("tests/fixtures/replay/old.reddit.com_r_testama_comments_abc123_test_ama_c00012__context_3").unwrap();
+        let thread: Vec<super::AmaComment> = super::parse_ama_thread(&raw_html).unwrap();
+        let actual: Vec<(&str, Option<&str>, u32)> = thread.iter()
+            .map(|ama_comment| (ama_comment.comment_id.as_str(), ama_comment.parent_id.as_deref(), ama_comment.depth))
+            .collect();
+        let expected: Vec<(&str, Option<&str>, u32)> = vec![
+            ("c00010", None, 0),
+            ("c00011", Some("c00010"), 1),
+            ("c00012", Some("c00011"), 2),
+            ("c00013", Some("c00012"), 3),
+            ("c00014", Some("c00013"), 4),
+        ];
+        assert_eq!(actual, expected);
+        assert_eq!(thread[1].body_text, Some("Thank you!\n\n".to_string()));
+        assert_eq!(thread[1].meta.author, Some("alice_author".to_string()));
+        // The thread is saved and loaded as it was parsed.
+        let full_dbpath: &str = "output/ama_query-thread_test.db";
+        let () = index_urlids(full_dbpath, &["c00012"]);
+        let ama_query = super::AmaQuery {
+            url_id: "c00012".to_string(),
+            question_text: Some("question_text".to_string()),
+            answer_text: Some("answer_text".to_string()),
+            ..Default::default()
+        };
+        let _ = super::save_ama_query_to_db(ama_query, full_dbpath).unwrap();
+        assert_eq!(super::save_ama_thread_to_db("c00012", &thread, full_dbpath).unwrap(), 5);
+        // Saving again replaces the earlier copy.
+        assert_eq!(super::save_ama_thread_to_db("c00012", &thread, full_dbpath).unwrap(), 5);
+        let loaded_thread: Vec<super::AmaComment> = super::load_ama_thread_from_db("c00012", full_dbpath).unwrap();
+        remove_file(full_dbpath);
+        assert_eq!(loaded_thread, thread);
+    }
+
+    #[test]
+    fn test_save_comment_meta_to_db() {
+        let full_dbpath: &str = "output/ama_query-meta_test.db";
+        let expected = super::AmaQuery {
+            url_id: "url_id".to_string(),
+            question_text: Some("question_text".to_string()),
+            answer_text: Some("answer_text".to_string()),
+            question_markdown: Some("question\\_text".to_string()),
+            answer_markdown: Some("*answer* text".to_string()),
+            question_meta: super::CommentMeta {
+                comment_id: Some("c00001".to_string()),
+                author: Some("fan_one".to_string()),
+                datetime: Some("2019-08-03T17:01:23+00:00".to_string()),
+                score: Some(12),
+                edited: None,
+                permalink: None,
             },
-            Err(sql_err) => {
-                eprintln!("SQL error: {:?}", sql_err);
+            answer_meta: super::CommentMeta {
+                comment_id: None,
+                author: None,
+                datetime: None,
+                score: Some(-3),
+                edited: Some("2019-08-03T18:02:10+00:00".to_string()),
+                permalink: Some("https://old.reddit.com/r/testama/comments/abc123/test_ama/c00004/".to_string()),
             },
+            thread: Vec::new(),
+        };
+        let () = index_urlids(full_dbpath, &["url_id"]);
+        let _ = super::save_ama_query_to_db(expected.clone(), full_dbpath).unwrap();
+        let actual: Vec<super::AmaQuery> = super::load_ama_queries_from_db(full_dbpath).unwrap();
+        remove_file(full_dbpath);
+        assert_eq!(actual, vec![expected]);
+    }
+
+    #[test]
+    fn test_get_retry_delay() {
+        let retry_policy = super::RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            jitter: false,
+        };
+        let expected: Vec<Duration> = [1, 2, 4, 5, 5].into_iter().map(Duration::from_secs).collect();
+        let actual: Vec<Duration> = (1..=5).map(|num_attempts| super::get_retry_delay(&retry_policy, num_attempts, None)).collect();
+        assert_eq!(actual, expected);
+        // The server's wait is honored only when it is the longer one.
+        assert_eq!(super::get_retry_delay(&retry_policy, 1, Some(Duration::from_secs(30))), Duration::from_secs(30));
+        assert_eq!(super::get_retry_delay(&retry_policy, 3, Some(Duration::from_secs(3))), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_get_retry_delay_jitter() {
+        let retry_policy = super::RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(4),
+            max_delay: Duration::from_secs(60),
+            jitter: true,
         };
-        cnxn
+        for _ in 0..20 {
+            let delay: Duration = super::get_retry_delay(&retry_policy, 2, None);
+            assert!(delay >= Duration::from_secs(4) && delay <= Duration::from_secs(8), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn test_save_failed_query() {
+        let full_dbpath: &str = "output/ama_query-failed_test.db";
+        let () = index_urlids(full_dbpath, &["url_id", "url_id2"]);
+        let last_error = super::Error::Validation("No answer found.".to_string());
+        assert_eq!(super::save_failed_query("url_id", 5, &last_error, full_dbpath).unwrap(), 1);
+        assert_eq!(super::save_failed_query("url_id", 3, &last_error, full_dbpath).unwrap(), 1);
+        assert_eq!(super::save_failed_query("url_id2", 5, &last_error, full_dbpath).unwrap(), 1);
+        assert_eq!(super::load_failed_urlids(full_dbpath).unwrap(), vec!["url_id".to_string(), "url_id2".to_string()]);
+        assert_eq!(super::clear_failed_query("url_id", full_dbpath).unwrap(), 1);
+        assert_eq!(super::load_failed_urlids(full_dbpath).unwrap(), vec!["url_id2".to_string()]);
+        remove_file(full_dbpath);
+    }
+
+    fn get_db_cnxn(full_dbpath: &str) -> rusqlite::Connection {
+        let _ = crate::ama_schema::migrate(full_dbpath).unwrap();
+        rusqlite::Connection::open(full_dbpath).unwrap()
+    }
+
+    /// Gives each of `url_ids` a record in `full_dbpath`, which its query and failure must refer to.
+    fn index_urlids(full_dbpath: &str, url_ids: &[&str]) {
+        let cnxn: rusqlite::Connection = get_db_cnxn(full_dbpath);
+        for url_id in url_ids {
+            let _ = cnxn.execute("INSERT OR IGNORE INTO ama_index (url_id, cc_name, fan_name) VALUES (?1, 'cc_name', ?1);", [url_id]).unwrap();
+        }
     }
 
     #[test]
@@ -246,11 +905,13 @@ mod ama_scraper_tests {
             url_id: "url_id".to_string(),
             question_text: Some("question_text".to_string()),
             answer_text: Some("answer_text".to_string()),
+            ..Default::default()
         };
         let ama_query2 = super::AmaQuery {
             url_id: "url_id2".to_string(),
             question_text: Some("question_text2".to_string()),
             answer_text: Some("answer_text2".to_string()),
+            ..Default::default()
         };
         let expected: Vec<super::AmaQuery> = Vec::from(
             [
@@ -260,16 +921,16 @@ mod ama_scraper_tests {
         );
         for ama_query in &expected {
             let _ = cnxn.execute(
-                "INSERT INTO ama_queries VALUES (?1, ?2, ?3);",
+                "INSERT INTO ama_queries (url_id, question_text, answer_text) VALUES (?1, ?2, ?3);",
                 (
                     // E0507
-                    (*ama_query).url_id.clone(),
-                    (*ama_query).question_text.clone().unwrap(),
-                    (*ama_query).answer_text.clone().unwrap(),
+                    ama_query.url_id.clone(),
+                    ama_query.question_text.clone().unwrap(),
+                    ama_query.answer_text.clone().unwrap(),
                 )
             ).unwrap();
         }
-        let actual = super::load_ama_queries_from_db(full_dbpath);
+        let actual = super::load_ama_queries_from_db(full_dbpath).unwrap();
         assert_eq!(actual, expected);
         remove_file(full_dbpath);
     }
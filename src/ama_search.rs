@@ -0,0 +1,93 @@
+//! This module searches the text of every scraped exchange.
+//! - SearchResult: One exchange that matches a search, with the matching text highlighted.
+//! - get_match_query: Turns plain search terms into an FTS5 query that cannot fail to parse.
+use std::fmt;
+
+/// Marks the start of a highlighted match in `SearchResult::snippet`.
+pub const HIGHLIGHT_START: &str = "**";
+/// Marks the end of a highlighted match in `SearchResult::snippet`.
+pub const HIGHLIGHT_END: &str = "**";
+
+/// One exchange that matches a search.
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct SearchResult {
+    pub url_id: String,
+    pub cc_name: String,
+    pub fan_name: String,
+    pub snippet: String, // Text around the best match in the question or answer, highlighted.
+    pub rank: f64, // BM25 score, where lower is better.
+}
+
+impl fmt::Display for SearchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} to {} ('url_id' {}): {}", self.fan_name, self.cc_name, self.url_id, self.snippet.replace('\n', " ").trim())
+    }
+}
+
+/// Turns plain search terms into an FTS5 query that matches every term, in any order. Each term is
+/// quoted, so that punctuation such as apostrophes or hyphens cannot break the query syntax.
+/// - terms: Words to look for, separated by whitespace.
+pub fn get_match_query(terms: &str) -> String {
+    let quoted_terms: Vec<String> = terms.split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+    quoted_terms.join(" ")
+}
+
+#[cfg(test)]
+mod ama_search_tests {
+    use std::fs;
+
+    use crate::ama_indexer::AmaRecord;
+    use crate::ama_scraper::AmaQuery;
+    use crate::ama_store::Store;
+
+    #[test]
+    fn test_get_match_query() {
+        assert_eq!(super::get_match_query("Marco's  hoodie"), "\"Marco's\" \"hoodie\"");
+        assert_eq!(super::get_match_query("say \"hi\""), "\"say\" \"\"\"hi\"\"\"");
+    }
+
+    fn get_ama_query(url_id: &str, question_text: &str, answer_text: &str) -> AmaQuery {
+        AmaQuery {
+            url_id: url_id.to_string(),
+            question_text: Some(question_text.to_string()),
+            answer_text: Some(answer_text.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let full_dbpath: &str = "output/ama_search-search_test.db";
+        let _ = fs::remove_file(full_dbpath);
+        let mut store: Store = Store::open(full_dbpath).unwrap();
+        let ama_index: Vec<AmaRecord> = [("url_id1", "cc_name1", "fan_name1"), ("url_id2", "cc_name1", "fan_name2"), ("url_id3", "cc_name2", "fan_name3")].iter()
+            .map(|(url_id, cc_name, fan_name)| AmaRecord { url_id: url_id.to_string(), cc_name: cc_name.to_string(), fan_name: fan_name.to_string() })
+            .collect();
+        let _ = store.upsert_ama_index(&ama_index).unwrap();
+        let _ = store.save_ama_query(&get_ama_query("url_id1", "What inspired the wand?", "The wand was inspired by a toy I had.")).unwrap();
+        let _ = store.save_ama_query(&get_ama_query("url_id2", "Favorite episode?", "Probably the one with the wand.")).unwrap();
+        let _ = store.save_ama_query(&get_ama_query("url_id3", "Any wands in season 4?", "No comment!")).unwrap();
+        // Stemming matches 'wands' too, and the exchange that mentions it most ranks first.
+        let results = store.search(&super::get_match_query("wand"), None, 10).unwrap();
+        let urlids: Vec<&str> = results.iter().map(|result| result.url_id.as_str()).collect();
+        assert_eq!(urlids.len(), 3);
+        assert_eq!(urlids[0], "url_id1");
+        assert!(results[0].snippet.contains("**wand**"));
+        // Filtered by creator, without regard to case.
+        let results = store.search(&super::get_match_query("wand"), Some("CC_NAME2"), 10).unwrap();
+        assert_eq!(results.iter().map(|result| result.fan_name.as_str()).collect::<Vec<&str>>(), vec!["fan_name3"]);
+        // The search table follows every change to the queries.
+        let _ = store.replace_ama_queries(&[get_ama_query("url_id2", "Favorite episode?", "Probably the one with the dragon.")]).unwrap();
+        let _ = store.delete_ama_query("url_id3").unwrap();
+        let wand_results = store.search(&super::get_match_query("wand"), None, 10).unwrap();
+        let dragon_results = store.search(&super::get_match_query("dragon"), None, 10).unwrap();
+        let _ = fs::remove_file(full_dbpath);
+        assert_eq!(wand_results.len(), 1);
+        assert_eq!(dragon_results[0].url_id, "url_id2");
+    }
+
+}
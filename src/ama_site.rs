@@ -0,0 +1,365 @@
+//! This module renders the joined archive as a static website that needs nothing but a browser.
+//! - escape_html: Escapes the characters of plain text that HTML would read as markup.
+//! - text_to_html: Turns plain text into paragraphs, keeping its line breaks.
+//! - SearchEntry: One exchange, as listed in the search index of the site.
+//! - write_site: Writes the index page, a page per creator, a page per exchange, and the search index.
+//!
+//! Every link is relative, and the stylesheet and scripts are written next to the pages, so that
+//! the site can be browsed from a zip file or a static host alike.
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::ama_config::{self, AmaConfig};
+use crate::ama_export::{self, Exchange};
+use crate::ama_filetree;
+use crate::ama_markdown::{self, CreatorStats};
+use crate::error::{Error, Result};
+
+/// Directory of the creator pages, relative to the root of the site.
+const CREATORS_DNAME: &str = "creators";
+/// Directory of the exchange pages, relative to the root of the site.
+const EXCHANGES_DNAME: &str = "exchanges";
+/// Script that defines the search index, relative to the root of the site. A script, rather than
+/// JSON, since browsers refuse to fetch files from a page opened from disk.
+const SEARCH_INDEX_FNAME: &str = "search-index.js";
+const SEARCH_SCRIPT_FNAME: &str = "search.js";
+const STYLESHEET_FNAME: &str = "style.css";
+
+const STYLESHEET: &str = "body { font-family: sans-serif; line-height: 1.5; max-width: 48em; margin: 0 auto; padding: 0 1em; color: #222; }
+header { border-bottom: 1px solid #ccc; padding: 0.5em 0; }
+footer { border-top: 1px solid #ccc; margin-top: 2em; padding: 0.5em 0; font-size: 0.9em; color: #555; }
+article { border-bottom: 1px solid #eee; padding-bottom: 1em; }
+blockquote { margin: 0.5em 0; padding: 0 1em; border-left: 3px solid #ccc; }
+.answer { border-left-color: #36c; }
+.meta { font-size: 0.9em; color: #555; }
+#search-terms { width: 100%; font-size: 1.1em; padding: 0.3em; box-sizing: border-box; }
+";
+
+const SEARCH_SCRIPT: &str = "(function () {
+    var input = document.getElementById('search-terms');
+    var results = document.getElementById('search-results');
+    var status = document.getElementById('search-status');
+    var maxResults = 50;
+    function matches(entry, terms) {
+        var text = (entry.cc_name + ' ' + entry.fan_name + ' ' + entry.question_text + ' ' + entry.answer_text).toLowerCase();
+        return terms.every(function (term) { return text.indexOf(term) !== -1; });
+    }
+    function getSnippet(entry, terms) {
+        var text = entry.answer_text.toLowerCase().indexOf(terms[0]) !== -1 ? entry.answer_text : entry.question_text;
+        var start = Math.max(0, text.toLowerCase().indexOf(terms[0]) - 60);
+        return (start > 0 ? '...' : '') + text.substr(start, 200) + (start + 200 < text.length ? '...' : '');
+    }
+    function search() {
+        var terms = input.value.toLowerCase().split(/\\s+/).filter(function (term) { return term.length > 0; });
+        results.textContent = '';
+        if (terms.length === 0) {
+            status.textContent = '';
+            return;
+        }
+        var found = AMA_SEARCH_INDEX.filter(function (entry) { return matches(entry, terms); });
+        status.textContent = found.length + ' exchanges match' + (found.length > maxResults ? ', of which the first ' + maxResults + ' are shown.' : '.');
+        found.slice(0, maxResults).forEach(function (entry) {
+            var item = document.createElement('li');
+            var link = document.createElement('a');
+            link.href = entry.page;
+            link.textContent = entry.fan_name + ' to ' + entry.cc_name;
+            var snippet = document.createElement('div');
+            snippet.className = 'meta';
+            snippet.textContent = getSnippet(entry, terms);
+            item.appendChild(link);
+            item.appendChild(snippet);
+            results.appendChild(item);
+        });
+    }
+    input.addEventListener('input', search);
+    search();
+})();
+";
+
+/// Escapes every character of `text` that HTML would otherwise read as markup, so that it can go
+/// into an element or a double-quoted attribute, of HTML or of XHTML. Control characters other than
+/// whitespace are left out, since XML does not allow them.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped_text: String = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped_text.push_str("&amp;"),
+            '<' => escaped_text.push_str("&lt;"),
+            '>' => escaped_text.push_str("&gt;"),
+            '"' => escaped_text.push_str("&quot;"),
+            '\'' => escaped_text.push_str("&#39;"),
+            '\t' | '\n' | '\r' => escaped_text.push(c),
+            c if c.is_control() => (),
+            c => escaped_text.push(c),
+        };
+    }
+    escaped_text
+}
+
+/// Turns `text` into HTML paragraphs: blank lines separate paragraphs, and other line breaks are kept.
+/// The result is valid XHTML as well.
+pub fn text_to_html(text: &str) -> String {
+    let paragraphs: Vec<String> = text.trim().split("\n\n")
+        .map(|paragraph| paragraph.trim())
+        .filter(|paragraph| !paragraph.is_empty())
+        .map(|paragraph| {
+            let lines: Vec<String> = paragraph.lines().map(|line| escape_html(line.trim_end())).collect();
+            format!("<p>{}</p>", lines.join("<br />\n"))
+        })
+        .collect();
+    paragraphs.join("\n")
+}
+
+/// One exchange, as listed in the search index of the site.
+#[derive(PartialEq)]
+#[derive(Debug)]
+#[derive(Serialize)]
+pub struct SearchEntry<'a> {
+    pub url_id: &'a str,
+    pub cc_name: &'a str,
+    pub fan_name: &'a str,
+    pub question_text: &'a str,
+    pub answer_text: &'a str,
+    pub page: String, // Page of the exchange, relative to the root of the site.
+}
+
+/// Wraps `body` in a complete page, whose links to the rest of the site start with `root_prefix`.
+fn get_page(ama_config: &AmaConfig, title: &str, root_prefix: &str, body: &str, scripts: &[&str]) -> String {
+    let script_tags: Vec<String> = scripts.iter()
+        .map(|script_fname| format!("<script src=\"{}{}\"></script>", root_prefix, script_fname))
+        .collect();
+    format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+<title>{title}</title>
+<link rel=\"stylesheet\" href=\"{root_prefix}{stylesheet}\">
+</head>
+<body>
+<header><a href=\"{root_prefix}index.html\">r/{subreddit} AMA archive</a></header>
+<main>
+{body}
+</main>
+<footer>Archived from <a href=\"{thread_url}\">the AMA thread on r/{subreddit}</a>.</footer>
+{scripts}
+</body>
+</html>
+",
+        title = escape_html(title),
+        root_prefix = root_prefix,
+        stylesheet = STYLESHEET_FNAME,
+        subreddit = escape_html(&ama_config.subreddit),
+        body = body,
+        thread_url = escape_html(&ama_config::get_thread_url(ama_config)),
+        scripts = script_tags.join("\n"),
+    )
+}
+
+/// Renders one exchange as an article, whose links to the rest of the site start with `root_prefix`.
+fn get_exchange_article(exchange: &Exchange, root_prefix: &str, creator_page: &str, exchange_page: &str) -> String {
+    let author_line = |author: &Option<String>, datetime: &Option<String>| match (author, datetime) {
+        (Some(author), Some(datetime)) => format!("<div class=\"meta\">u/{} at {}</div>\n", escape_html(author), escape_html(datetime)),
+        (Some(author), None) => format!("<div class=\"meta\">u/{}</div>\n", escape_html(author)),
+        _ => String::new(),
+    };
+    format!(
+        "<article id=\"{url_id}\">
+<h3><a href=\"{root_prefix}{exchange_page}\">{fan_name}</a> to <a href=\"{root_prefix}{creator_page}\">{cc_name}</a></h3>
+{question_meta}<blockquote class=\"question\">
+{question}
+</blockquote>
+{answer_meta}<blockquote class=\"answer\">
+{answer}
+</blockquote>
+<div class=\"meta\"><a href=\"{url}\">View on Reddit</a></div>
+</article>",
+        url_id = escape_html(&exchange.url_id),
+        root_prefix = root_prefix,
+        exchange_page = escape_html(exchange_page),
+        fan_name = escape_html(&exchange.fan_name),
+        creator_page = escape_html(creator_page),
+        cc_name = escape_html(&exchange.cc_name),
+        question_meta = author_line(&exchange.question_meta.author, &exchange.question_meta.datetime),
+        question = text_to_html(&exchange.question_text),
+        answer_meta = author_line(&exchange.answer_meta.author, &exchange.answer_meta.datetime),
+        answer = text_to_html(&exchange.answer_text),
+        url = escape_html(&exchange.url),
+    )
+}
+
+/// Writes `contents` to `file_path`, creating its directory if need be.
+fn write_file(file_path: &Path, contents: &str) -> Result<()> {
+    if let Some(dir_path) = file_path.parent() {
+        if let Err(io_err) = fs::create_dir_all(dir_path) {
+            return Err(Error::io(dir_path, io_err));
+        }
+    }
+    match fs::write(file_path, contents) {
+        Ok(()) => Ok(()),
+        Err(io_err) => Err(Error::io(file_path, io_err)),
+    }
+}
+
+/// Writes `exchanges` to `site_path` as a static website: 'index.html', which lists the creators
+/// and searches every exchange; 'creators/{name}.html', with every exchange a creator answered; and
+/// 'exchanges/{url_id}.html', a permalink for each exchange. Files already in `site_path` are
+/// written over, and nothing else is removed. Returns the number of pages written.
+/// - ama_config: Names the subreddit and thread that the site is about.
+/// - exchanges: Exchanges to write, in compendium order.
+/// - site_path: Root directory of the site.
+pub fn write_site(ama_config: &AmaConfig, exchanges: &[Exchange], site_path: &Path) -> Result<usize> {
+    let creator_groups = ama_export::group_by_creator(exchanges);
+    let mut used_names: HashSet<String> = HashSet::new();
+    let creator_pages: Vec<String> = creator_groups.iter()
+        .map(|(cc_name, _)| format!("{}/{}.html", CREATORS_DNAME, ama_filetree::sanitize_name(&ama_markdown::get_anchor(cc_name, &mut used_names))))
+        .collect();
+    let get_exchange_page = |exchange: &Exchange| format!("{}/{}.html", EXCHANGES_DNAME, ama_filetree::sanitize_name(&exchange.url_id));
+    let mut num_pages: usize = 0;
+    // Index page.
+    let mut creator_items: Vec<String> = Vec::new();
+    for ((cc_name, creator_exchanges), creator_page) in creator_groups.iter().zip(creator_pages.iter()) {
+        let creator_stats = CreatorStats::from_exchanges(creator_exchanges);
+        creator_items.push(format!(
+            "<li><a href=\"{}\">{}</a> <span class=\"meta\">{} exchanges, {} words answered</span></li>",
+            escape_html(creator_page), escape_html(cc_name), creator_stats.num_exchanges, creator_stats.num_answer_words,
+        ));
+    }
+    let index_body: String = format!(
+        "<h1>r/{subreddit} AMA</h1>
+<p>{num_exchanges} exchanges with {num_creators} creators.</p>
+<h2>Creators</h2>
+<ul>
+{creator_items}
+</ul>
+<h2>Search</h2>
+<input id=\"search-terms\" type=\"search\" placeholder=\"Search every question and answer\" aria-label=\"Search\">
+<p id=\"search-status\" class=\"meta\"></p>
+<ol id=\"search-results\"></ol>",
+        subreddit = escape_html(&ama_config.subreddit),
+        num_exchanges = exchanges.len(),
+        num_creators = creator_groups.len(),
+        creator_items = creator_items.join("\n"),
+    );
+    let index_title: String = format!("r/{} AMA", ama_config.subreddit);
+    let () = write_file(&site_path.join("index.html"), &get_page(ama_config, &index_title, "", &index_body, &[SEARCH_INDEX_FNAME, SEARCH_SCRIPT_FNAME]))?;
+    num_pages += 1;
+    // A page per creator, and a page per exchange.
+    for ((cc_name, creator_exchanges), creator_page) in creator_groups.iter().zip(creator_pages.iter()) {
+        let articles: Vec<String> = creator_exchanges.iter()
+            .map(|exchange| get_exchange_article(exchange, "../", creator_page, &get_exchange_page(exchange)))
+            .collect();
+        let creator_body: String = format!("<h1>{}</h1>\n<p>{} exchanges.</p>\n{}", escape_html(cc_name), creator_exchanges.len(), articles.join("\n"));
+        let () = write_file(&site_path.join(creator_page), &get_page(ama_config, cc_name, "../", &creator_body, &[]))?;
+        num_pages += 1;
+        for exchange in creator_exchanges.iter() {
+            let exchange_page: String = get_exchange_page(exchange);
+            let exchange_title: String = format!("{} to {}", exchange.fan_name, exchange.cc_name);
+            let exchange_body: String = get_exchange_article(exchange, "../", creator_page, &exchange_page);
+            let () = write_file(&site_path.join(&exchange_page), &get_page(ama_config, &exchange_title, "../", &exchange_body, &[]))?;
+            num_pages += 1;
+        }
+    }
+    // Search index, stylesheet and script.
+    let search_entries: Vec<SearchEntry> = exchanges.iter()
+        .map(|exchange| SearchEntry {
+            url_id: &exchange.url_id,
+            cc_name: &exchange.cc_name,
+            fan_name: &exchange.fan_name,
+            question_text: &exchange.question_text,
+            answer_text: &exchange.answer_text,
+            page: get_exchange_page(exchange),
+        })
+        .collect();
+    let raw_search_index: String = match serde_json::to_string(&search_entries) {
+        Ok(raw_json) => raw_json,
+        Err(json_err) => return Err(Error::Parse(format!("Could not serialize the search index: {}", json_err))),
+    };
+    let () = write_file(&site_path.join(SEARCH_INDEX_FNAME), &format!("var AMA_SEARCH_INDEX = {};\n", raw_search_index))?;
+    let () = write_file(&site_path.join(SEARCH_SCRIPT_FNAME), SEARCH_SCRIPT)?;
+    let () = write_file(&site_path.join(STYLESHEET_FNAME), STYLESHEET)?;
+    Ok(num_pages)
+}
+
+#[cfg(test)]
+mod ama_site_tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use crate::ama_config::AmaConfig;
+    use crate::ama_export::{get_test_exchange, Exchange};
+    use crate::ama_scraper::CommentMeta;
+
+    /// Returns the path of every file in the site at `site_path`, relative to it, sorted.
+    fn get_site_files(site_path: &Path) -> Vec<PathBuf> {
+        let mut site_files: Vec<PathBuf> = Vec::new();
+        let mut dir_paths: Vec<PathBuf> = vec![site_path.to_path_buf()];
+        while let Some(dir_path) = dir_paths.pop() {
+            for dir_entry in fs::read_dir(&dir_path).unwrap() {
+                let entry_path: PathBuf = dir_entry.unwrap().path();
+                match entry_path.is_dir() {
+                    true => dir_paths.push(entry_path),
+                    false => site_files.push(entry_path.strip_prefix(site_path).unwrap().to_path_buf()),
+                };
+            }
+        }
+        site_files.sort();
+        site_files
+    }
+
+    #[test]
+    fn test_text_to_html() {
+        assert_eq!(super::escape_html("<a href=\"x\">Tom & Jerry's</a>\u{0}"), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;");
+        assert_eq!(super::text_to_html("Line 1\nLine <2>\n\n\n\nNew paragraph.\n"), "<p>Line 1<br />\nLine &lt;2&gt;</p>\n<p>New paragraph.</p>");
+    }
+
+    #[test]
+    fn test_write_site() {
+        let site_path: &Path = Path::new("output/ama_site-write_test");
+        let _ = fs::remove_dir_all(site_path);
+        let exchanges: Vec<Exchange> = vec![
+            get_test_exchange("Daron Nefcy", "fan_1", "url_id1"),
+            Exchange {
+                question_text: "Is <b>this</b> escaped?".to_string(),
+                answer_text: "Yes & no.\nMostly yes.\n\nThe end.".to_string(),
+                answer_meta: CommentMeta { author: Some("creator_1".to_string()), ..Default::default() },
+                ..get_test_exchange("Adam McArthur", "fan_2", "url_id2")
+            },
+            get_test_exchange("Daron Nefcy", "fan_3", "url_id3"),
+        ];
+        let num_pages: usize = super::write_site(&AmaConfig::default(), &exchanges, site_path).unwrap();
+        let site_files: Vec<PathBuf> = get_site_files(site_path);
+        let index_page: String = fs::read_to_string(site_path.join("index.html")).unwrap();
+        let creator_page: String = fs::read_to_string(site_path.join("creators/daron-nefcy.html")).unwrap();
+        let exchange_page: String = fs::read_to_string(site_path.join("exchanges/url_id2.html")).unwrap();
+        let raw_search_index: String = fs::read_to_string(site_path.join("search-index.js")).unwrap();
+        let _ = fs::remove_dir_all(site_path);
+        assert_eq!(num_pages, 6);
+        let expected_files: Vec<&str> = vec![
+            "creators/adam-mcarthur.html", "creators/daron-nefcy.html",
+            "exchanges/url_id1.html", "exchanges/url_id2.html", "exchanges/url_id3.html",
+            "index.html", "search-index.js", "search.js", "style.css",
+        ];
+        assert_eq!(site_files, expected_files.iter().map(PathBuf::from).collect::<Vec<PathBuf>>());
+        assert!(index_page.contains("<a href=\"creators/daron-nefcy.html\">Daron Nefcy</a>"));
+        assert!(index_page.find("Daron Nefcy").unwrap() < index_page.find("Adam McArthur").unwrap());
+        assert!(creator_page.contains("<article id=\"url_id3\">"));
+        assert!(creator_page.contains("<link rel=\"stylesheet\" href=\"../style.css\">"));
+        assert!(exchange_page.contains("Is &lt;b&gt;this&lt;/b&gt; escaped?"));
+        assert!(exchange_page.contains("<p>Yes &amp; no.<br />\nMostly yes.</p>\n<p>The end.</p>"));
+        assert!(exchange_page.contains("<div class=\"meta\">u/creator_1</div>"));
+        // No page asks for anything that is not in the site.
+        for page in [&index_page, &creator_page, &exchange_page] {
+            assert!(!page.contains("src=\"http") && !page.contains("href=\"//"));
+        }
+        let search_entries: serde_json::Value = serde_json::from_str(
+            raw_search_index.trim().strip_prefix("var AMA_SEARCH_INDEX = ").unwrap().strip_suffix(';').unwrap()
+        ).unwrap();
+        assert_eq!(search_entries[2]["page"], "exchanges/url_id3.html");
+    }
+
+}
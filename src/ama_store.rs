@@ -0,0 +1,525 @@
+//! This module reads and writes the archive database over a single connection.
+//! - Store: Owns the connection, and wraps every batch of writes in a transaction.
+//! - Store::open: Opens the archive database, bringing it up to the latest schema version.
+//! - Store::upsert_ama_index: Saves a compiled index over the saved one, and reports what changed.
+//! - Store::save_ama_query: Saves a scraped exchange, together with its thread.
+//! - Store::replace_ama_queries: Saves exchanges over any saved before, and clears their failure marks.
+//! - Store::apply_field_edits: Applies fields edited by hand, and records each of them in 'ama_edits'.
+//! - Store::search: Ranks the exchanges whose question or answer matches a full-text query.
+use std::path::Path;
+
+use crate::ama_filetree::{EditedField, FieldEdit};
+use crate::ama_indexer::{AmaRecord, IndexChanges};
+use crate::ama_scraper::{AmaComment, AmaQuery, CommentMeta};
+use crate::ama_schema;
+use crate::ama_search::{SearchResult, HIGHLIGHT_END, HIGHLIGHT_START};
+use crate::error::{Error, Result};
+
+const INSERT_QUERY_SQL: &str = "INSERT INTO ama_queries (
+        url_id, question_text, answer_text,
+        question_comment_id, question_author, question_datetime, question_score, question_edited, question_permalink,
+        answer_comment_id, answer_author, answer_datetime, answer_score, answer_edited, answer_permalink,
+        question_markdown, answer_markdown
+    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17);";
+const INSERT_COMMENT_SQL: &str = "INSERT INTO ama_comments (
+        url_id, comment_id, parent_id, depth, position, body_text, author, datetime, score, edited, permalink, body_markdown
+    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12);";
+
+/// A handle on the archive database. Every method that writes does so in a single transaction,
+/// and returns the number of rows it affected.
+pub struct Store {
+    cnxn: rusqlite::Connection,
+}
+
+impl Store {
+    /// Opens the archive database at `full_dbpath`, creating it, or upgrading it to the latest
+    /// schema version, if need be.
+    /// - full_dbpath: tells the function where the database file is.
+    pub fn open(full_dbpath: impl AsRef<Path>) -> Result<Store> {
+        let cnxn: rusqlite::Connection = ama_schema::open_cnxn(full_dbpath)?;
+        Ok(Store { cnxn })
+    }
+
+    /// Saves `ama_index` over the saved index, keyed by (cc_name, fan_name), so that it can be
+    /// compiled again without duplicating any record. Records that are no longer in `ama_index` are
+    /// deleted, as are the queries of url_ids that no record links to any more. Returns how the
+    /// saved index changed.
+    /// - ama_index: List of ama_index dict-records, with no url_id shared.
+    pub fn upsert_ama_index(&mut self, ama_index: &[AmaRecord]) -> Result<IndexChanges> {
+        let tx: rusqlite::Transaction = self.cnxn.transaction()?;
+        let old_index: Vec<AmaRecord> = {
+            let mut stmt: rusqlite::CachedStatement = tx.prepare_cached("SELECT url_id, cc_name, fan_name FROM ama_index;")?;
+            let ama_record_iter = stmt.query_map([], get_ama_record)?;
+            ama_record_iter.collect::<rusqlite::Result<Vec<AmaRecord>>>()?
+        };
+        let is_same_record = |ama_record: &AmaRecord, other_record: &AmaRecord| ama_record.cc_name == other_record.cc_name && ama_record.fan_name == other_record.fan_name;
+        let mut index_changes = IndexChanges::default();
+        for ama_record in ama_index {
+            match old_index.iter().find(|old_record| is_same_record(ama_record, old_record)) {
+                None => index_changes.added.push(ama_record.clone()),
+                Some(old_record) if old_record.url_id != ama_record.url_id => index_changes.changed.push((ama_record.clone(), old_record.url_id.clone())),
+                Some(_) => (),
+            };
+        }
+        index_changes.removed = old_index.into_iter()
+            .filter(|old_record| !ama_index.iter().any(|ama_record| is_same_record(ama_record, old_record)))
+            .collect();
+        // Queries follow their url_id, so they are only deleted once no record links to it any more.
+        tx.pragma_update(None, "defer_foreign_keys", true)?;
+        {
+            // Deleting a record deletes its query and failure along with it.
+            let mut delete_stmt: rusqlite::CachedStatement = tx.prepare_cached("DELETE FROM ama_index WHERE cc_name = ?1 AND fan_name = ?2;")?;
+            for ama_record in index_changes.removed.iter() {
+                delete_stmt.execute((&ama_record.cc_name, &ama_record.fan_name))?;
+            }
+            // Changed records give up their url_ids before any is written again, so that two records may trade them.
+            let mut release_stmt: rusqlite::CachedStatement = tx.prepare_cached("UPDATE ama_index SET url_id = '~' || rowid WHERE cc_name = ?1 AND fan_name = ?2;")?;
+            for (ama_record, _) in index_changes.changed.iter() {
+                release_stmt.execute((&ama_record.cc_name, &ama_record.fan_name))?;
+            }
+            let mut upsert_stmt: rusqlite::CachedStatement = tx.prepare_cached(
+                "INSERT INTO ama_index (url_id, cc_name, fan_name, position) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (cc_name, fan_name) DO UPDATE SET url_id = excluded.url_id, position = excluded.position
+                WHERE url_id IS NOT excluded.url_id OR position IS NOT excluded.position;"
+            )?;
+            for (position, ama_record) in ama_index.iter().enumerate() {
+                upsert_stmt.execute((&ama_record.url_id, &ama_record.cc_name, &ama_record.fan_name, position))?;
+            }
+        }
+        let () = ama_schema::delete_unlinked_rows(&tx)?;
+        tx.commit()?;
+        Ok(index_changes)
+    }
+
+    /// Loads the table `ama_index`, in the order of the compendium it was compiled from. Records saved
+    /// without a position come last, in the order in which they were inserted.
+    pub fn load_ama_index(&self) -> Result<Vec<AmaRecord>> {
+        let mut stmt: rusqlite::CachedStatement = self.cnxn.prepare_cached(
+            "SELECT url_id, cc_name, fan_name FROM ama_index ORDER BY position IS NULL, position, rowid;"
+        )?;
+        let ama_record_iter = stmt.query_map([], get_ama_record)?;
+        Ok(ama_record_iter.collect::<rusqlite::Result<Vec<AmaRecord>>>()?)
+    }
+
+    /// Saves `ama_query` into the 'ama_queries' table, together with its thread, which replaces any
+    /// thread saved earlier. Returns the number of rows written, the query's and its comments'.
+    /// - ama_query: populated dict to be loaded into the database.
+    pub fn save_ama_query(&mut self, ama_query: &AmaQuery) -> Result<usize> {
+        let tx: rusqlite::Transaction = self.cnxn.transaction()?;
+        let num_rows: usize = insert_ama_query(&tx, ama_query)?;
+        tx.commit()?;
+        Ok(num_rows)
+    }
+
+    /// Saves every query in `ama_queries` over the one saved for its url_id, if any, and clears its
+    /// failure mark, all in one transaction. Returns the number of rows written.
+    /// - ama_queries: populated dicts to be loaded into the database.
+    pub fn replace_ama_queries(&mut self, ama_queries: &[AmaQuery]) -> Result<usize> {
+        let tx: rusqlite::Transaction = self.cnxn.transaction()?;
+        let mut num_rows: usize = 0;
+        for ama_query in ama_queries {
+            let _ = tx.prepare_cached("DELETE FROM ama_queries WHERE url_id = ?1;")?.execute([&ama_query.url_id])?;
+            num_rows += insert_ama_query(&tx, ama_query)?;
+            let _ = tx.prepare_cached("DELETE FROM ama_failures WHERE url_id = ?1;")?.execute([&ama_query.url_id])?;
+        }
+        tx.commit()?;
+        Ok(num_rows)
+    }
+
+    /// Deletes the saved query for `url_id` and its thread, so that it can be saved again.
+    /// Returns the number of queries deleted.
+    pub fn delete_ama_query(&mut self, url_id: &str) -> Result<usize> {
+        let tx: rusqlite::Transaction = self.cnxn.transaction()?;
+        let num_rows: usize = tx.execute("DELETE FROM ama_queries WHERE url_id = ?1;", [url_id])?;
+        tx.execute("DELETE FROM ama_comments WHERE url_id = ?1;", [url_id])?;
+        tx.commit()?;
+        Ok(num_rows)
+    }
+
+    /// Loads the 'ama_queries' table, without their threads.
+    pub fn load_ama_queries(&self) -> Result<Vec<AmaQuery>> {
+        let mut stmt: rusqlite::CachedStatement = self.cnxn.prepare_cached(
+            "SELECT url_id, question_text, answer_text,
+                question_comment_id, question_author, question_datetime, question_score, question_edited, question_permalink,
+                answer_comment_id, answer_author, answer_datetime, answer_score, answer_edited, answer_permalink,
+                question_markdown, answer_markdown
+            FROM ama_queries;"
+        )?;
+        let ama_query_iter = stmt.query_map(
+            [],
+            |row| {
+                Ok(
+                    AmaQuery {
+                        url_id: row.get(0)?,
+                        question_text: Some(row.get(1)?),
+                        answer_text: Some(row.get(2)?),
+                        question_markdown: row.get(15)?,
+                        answer_markdown: row.get(16)?,
+                        question_meta: CommentMeta {
+                            comment_id: row.get(3)?,
+                            author: row.get(4)?,
+                            datetime: row.get(5)?,
+                            score: row.get(6)?,
+                            edited: row.get(7)?,
+                            permalink: row.get(8)?,
+                        },
+                        answer_meta: CommentMeta {
+                            comment_id: row.get(9)?,
+                            author: row.get(10)?,
+                            datetime: row.get(11)?,
+                            score: row.get(12)?,
+                            edited: row.get(13)?,
+                            permalink: row.get(14)?,
+                        },
+                        thread: Vec::new(),
+                    }
+                )
+            }
+        )?;
+        Ok(ama_query_iter.collect::<rusqlite::Result<Vec<AmaQuery>>>()?)
+    }
+
+    /// Saves `thread` as the comments on the page for `url_id`, replacing any saved earlier.
+    /// Returns the number of comments saved.
+    pub fn save_ama_thread(&mut self, url_id: &str, thread: &[AmaComment]) -> Result<usize> {
+        let tx: rusqlite::Transaction = self.cnxn.transaction()?;
+        let num_rows: usize = insert_ama_thread(&tx, url_id, thread)?;
+        tx.commit()?;
+        Ok(num_rows)
+    }
+
+    /// Loads the comments on the page for `url_id`, in the order in which they appeared.
+    pub fn load_ama_thread(&self, url_id: &str) -> Result<Vec<AmaComment>> {
+        let mut stmt: rusqlite::CachedStatement = self.cnxn.prepare_cached(
+            "SELECT comment_id, parent_id, depth, body_text, author, datetime, score, edited, permalink, body_markdown
+            FROM ama_comments WHERE url_id = ?1 ORDER BY position;"
+        )?;
+        let ama_comment_iter = stmt.query_map(
+            [url_id],
+            |row| {
+                Ok(
+                    AmaComment {
+                        comment_id: row.get(0)?,
+                        parent_id: row.get(1)?,
+                        depth: row.get(2)?,
+                        body_text: row.get(3)?,
+                        body_markdown: row.get(9)?,
+                        meta: CommentMeta {
+                            comment_id: row.get(0)?,
+                            author: row.get(4)?,
+                            datetime: row.get(5)?,
+                            score: row.get(6)?,
+                            edited: row.get(7)?,
+                            permalink: row.get(8)?,
+                        },
+                    }
+                )
+            }
+        )?;
+        Ok(ama_comment_iter.collect::<rusqlite::Result<Vec<AmaComment>>>()?)
+    }
+
+    /// Applies every edit in `field_edits`, in order, and records each of them in 'ama_edits', all in
+    /// one transaction. An edited url_id moves the record's query, thread and failure mark along with
+    /// it, and may not be one that another record links to. Returns the number of edits applied.
+    /// - field_edits: Fields edited by hand, as found by `ama_filetree::find_edits`.
+    pub fn apply_field_edits(&mut self, field_edits: &[FieldEdit]) -> Result<usize> {
+        let tx: rusqlite::Transaction = self.cnxn.transaction()?;
+        // A query and its comments only point at their new url_id once all of them are moved.
+        tx.pragma_update(None, "defer_foreign_keys", true)?;
+        for field_edit in field_edits {
+            match field_edit.field {
+                // The Markdown was of the text as fetched, so it gives way to the edited text.
+                EditedField::QuestionText => tx.execute("UPDATE ama_queries SET question_text = ?1, question_markdown = NULL WHERE url_id = ?2;", (&field_edit.new_value, &field_edit.url_id))?,
+                EditedField::AnswerText => tx.execute("UPDATE ama_queries SET answer_text = ?1, answer_markdown = NULL WHERE url_id = ?2;", (&field_edit.new_value, &field_edit.url_id))?,
+                EditedField::UrlId => {
+                    let num_taken: usize = tx.query_row("SELECT COUNT(*) FROM ama_index WHERE url_id = ?1;", [&field_edit.new_value], |row| row.get(0))?;
+                    if num_taken > 0 {
+                        return Err(Error::Validation(format!("Cannot move {} to {} to 'url_id' {}, which another record links to.", field_edit.fan_name, field_edit.cc_name, field_edit.new_value)));
+                    }
+                    // The record is moved before the rows that refer to it, which follow it in turn.
+                    let num_rows: usize = tx.execute(
+                        "UPDATE ama_index SET url_id = ?1 WHERE cc_name = ?2 AND fan_name = ?3;",
+                        (&field_edit.new_value, &field_edit.cc_name, &field_edit.fan_name)
+                    )?;
+                    for table_name in ["ama_queries", "ama_comments", "ama_failures"] {
+                        tx.execute(&format!("UPDATE {} SET url_id = ?1 WHERE url_id = ?2;", table_name), (&field_edit.new_value, &field_edit.url_id))?;
+                    }
+                    num_rows
+                },
+            };
+            tx.prepare_cached("INSERT INTO ama_edits (cc_name, fan_name, url_id, field, old_value, new_value) VALUES (?1, ?2, ?3, ?4, ?5, ?6);")?.execute(
+                (&field_edit.cc_name, &field_edit.fan_name, &field_edit.url_id, field_edit.field.get_name(), &field_edit.old_value, &field_edit.new_value)
+            )?;
+        }
+        tx.commit()?;
+        Ok(field_edits.len())
+    }
+
+    /// Loads every edit recorded in 'ama_edits', in the order in which they were applied. Fails
+    /// with `Error::Parse` on an edit to a field that cannot be edited.
+    pub fn load_field_edits(&self) -> Result<Vec<FieldEdit>> {
+        let mut stmt: rusqlite::CachedStatement = self.cnxn.prepare_cached(
+            "SELECT cc_name, fan_name, url_id, field, old_value, new_value FROM ama_edits ORDER BY rowid;"
+        )?;
+        let row_iter = stmt.query_map(
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get::<_, String>(3)?, row.get(4)?, row.get(5)?))
+        )?;
+        let mut field_edits: Vec<FieldEdit> = Vec::new();
+        for row in row_iter {
+            let (cc_name, fan_name, url_id, field, old_value, new_value) = row?;
+            field_edits.push(
+                FieldEdit {
+                    cc_name,
+                    fan_name,
+                    url_id,
+                    field: field.parse()?,
+                    old_value,
+                    new_value,
+                }
+            );
+        }
+        Ok(field_edits)
+    }
+
+    /// Returns the exchanges whose question or answer matches `match_query`, best match first.
+    /// - match_query: FTS5 query, e.g. from `ama_search::get_match_query`.
+    /// - cc_name: Only return exchanges with this creator, without regard to case.
+    /// - limit: Most results to return.
+    pub fn search(&self, match_query: &str, cc_name: Option<&str>, limit: usize) -> Result<Vec<SearchResult>> {
+        let mut stmt: rusqlite::CachedStatement = self.cnxn.prepare_cached(
+            "SELECT ama_search.url_id, ama_index.cc_name, ama_index.fan_name,
+                snippet(ama_search, -1, ?4, ?5, '...', 16), bm25(ama_search)
+            FROM ama_search JOIN ama_index ON ama_index.url_id = ama_search.url_id
+            WHERE ama_search MATCH ?1 AND (?2 IS NULL OR ama_index.cc_name = ?2 COLLATE NOCASE)
+            ORDER BY bm25(ama_search), ama_search.url_id LIMIT ?3;"
+        )?;
+        let search_result_iter = stmt.query_map(
+            rusqlite::params![match_query, cc_name, limit as i64, HIGHLIGHT_START, HIGHLIGHT_END],
+            |row| {
+                Ok(
+                    SearchResult {
+                        url_id: row.get(0)?,
+                        cc_name: row.get(1)?,
+                        fan_name: row.get(2)?,
+                        snippet: row.get(3)?,
+                        rank: row.get(4)?,
+                    }
+                )
+            }
+        )?;
+        Ok(search_result_iter.collect::<rusqlite::Result<Vec<SearchResult>>>()?)
+    }
+
+    /// Marks the query for `url_id` as failed, replacing any earlier failure.
+    /// - url_id: Query whose retries ran out.
+    /// - num_attempts: How many times it was tried.
+    /// - last_error: What went wrong on the last attempt.
+    pub fn save_failed_query(&mut self, url_id: &str, num_attempts: u32, last_error: &Error) -> Result<usize> {
+        let mut stmt: rusqlite::CachedStatement = self.cnxn.prepare_cached("INSERT OR REPLACE INTO ama_failures VALUES (?1, ?2, ?3);")?;
+        Ok(stmt.execute((url_id, num_attempts, last_error.to_string()))?)
+    }
+
+    /// Clears the failure mark for `url_id`, once it has been fetched after all.
+    pub fn clear_failed_query(&mut self, url_id: &str) -> Result<usize> {
+        let mut stmt: rusqlite::CachedStatement = self.cnxn.prepare_cached("DELETE FROM ama_failures WHERE url_id = ?1;")?;
+        Ok(stmt.execute([url_id])?)
+    }
+
+    /// Loads the url_ids of every query that is marked failed.
+    pub fn load_failed_urlids(&self) -> Result<Vec<String>> {
+        let mut stmt: rusqlite::CachedStatement = self.cnxn.prepare_cached("SELECT url_id FROM ama_failures;")?;
+        let urlid_iter = stmt.query_map([], |row| row.get(0))?;
+        Ok(urlid_iter.collect::<rusqlite::Result<Vec<String>>>()?)
+    }
+}
+
+fn get_ama_record(row: &rusqlite::Row) -> rusqlite::Result<AmaRecord> {
+    Ok(
+        AmaRecord {
+            url_id: row.get(0)?,
+            cc_name: row.get(1)?,
+            fan_name: row.get(2)?,
+        }
+    )
+}
+
+/// Inserts `ama_query` and its thread within `tx`. Returns the number of rows written.
+fn insert_ama_query(tx: &rusqlite::Transaction, ama_query: &AmaQuery) -> Result<usize> {
+    let (question_text, answer_text): (&String, &String) = match (&ama_query.question_text, &ama_query.answer_text) {
+        (Some(question_text), Some(answer_text)) => (question_text, answer_text),
+        _ => return Err(Error::Validation(format!("Query for 'url_id' {} is missing its question or answer.", ama_query.url_id))),
+    };
+    let (question_meta, answer_meta): (&CommentMeta, &CommentMeta) = (&ama_query.question_meta, &ama_query.answer_meta);
+    // There are more columns than rusqlite takes as a tuple.
+    let mut num_rows: usize = tx.prepare_cached(INSERT_QUERY_SQL)?.execute(
+        rusqlite::params![
+            &ama_query.url_id,
+            question_text,
+            answer_text,
+            &question_meta.comment_id,
+            &question_meta.author,
+            &question_meta.datetime,
+            question_meta.score,
+            &question_meta.edited,
+            &question_meta.permalink,
+            &answer_meta.comment_id,
+            &answer_meta.author,
+            &answer_meta.datetime,
+            answer_meta.score,
+            &answer_meta.edited,
+            &answer_meta.permalink,
+            &ama_query.question_markdown,
+            &ama_query.answer_markdown,
+        ]
+    )?;
+    num_rows += insert_ama_thread(tx, &ama_query.url_id, &ama_query.thread)?;
+    Ok(num_rows)
+}
+
+/// Replaces the thread of `url_id` with `thread` within `tx`. Returns the number of comments written.
+fn insert_ama_thread(tx: &rusqlite::Transaction, url_id: &str, thread: &[AmaComment]) -> Result<usize> {
+    let _ = tx.prepare_cached("DELETE FROM ama_comments WHERE url_id = ?1;")?.execute([url_id])?;
+    let mut stmt: rusqlite::CachedStatement = tx.prepare_cached(INSERT_COMMENT_SQL)?;
+    let mut num_rows: usize = 0;
+    for (position, ama_comment) in thread.iter().enumerate() {
+        num_rows += stmt.execute(
+            (
+                url_id,
+                &ama_comment.comment_id,
+                &ama_comment.parent_id,
+                ama_comment.depth,
+                position,
+                &ama_comment.body_text,
+                &ama_comment.meta.author,
+                &ama_comment.meta.datetime,
+                ama_comment.meta.score,
+                &ama_comment.meta.edited,
+                &ama_comment.meta.permalink,
+                &ama_comment.body_markdown,
+            )
+        )?;
+    }
+    Ok(num_rows)
+}
+
+#[cfg(test)]
+mod ama_store_tests {
+    use std::fs;
+
+    use super::Store;
+    use crate::ama_indexer::AmaRecord;
+    use crate::ama_scraper::{AmaComment, AmaQuery, CommentMeta};
+
+    /// Opens a new store at `full_dbpath`, with a record for each of `url_ids`.
+    fn open_indexed_store(full_dbpath: &str, url_ids: &[&str]) -> Store {
+        let _ = fs::remove_file(full_dbpath);
+        let mut store: Store = Store::open(full_dbpath).unwrap();
+        let ama_index: Vec<AmaRecord> = url_ids.iter()
+            .map(|url_id| AmaRecord {
+                cc_name: "cc_name".to_string(),
+                fan_name: url_id.to_string(),
+                url_id: url_id.to_string(),
+            })
+            .collect();
+        let _ = store.upsert_ama_index(&ama_index).unwrap();
+        store
+    }
+
+    fn get_ama_query(url_id: &str, answer_text: &str, num_comments: usize) -> AmaQuery {
+        AmaQuery {
+            url_id: url_id.to_string(),
+            question_text: Some("question_text".to_string()),
+            answer_text: Some(answer_text.to_string()),
+            thread: (0..num_comments)
+                .map(|position| AmaComment {
+                    comment_id: format!("{}_{}", url_id, position),
+                    meta: CommentMeta {
+                        comment_id: Some(format!("{}_{}", url_id, position)),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_save_ama_query() {
+        let full_dbpath: &str = "output/ama_store-save_test.db";
+        let mut store: Store = open_indexed_store(full_dbpath, &["url_id1", "url_id2"]);
+        // The query's own row, and one for each of its comments.
+        assert_eq!(store.save_ama_query(&get_ama_query("url_id1", "answer_text", 3)).unwrap(), 4);
+        // A query that cannot be saved leaves nothing behind.
+        let mut incomplete_query: AmaQuery = get_ama_query("url_id2", "answer_text", 2);
+        incomplete_query.answer_text = None;
+        assert!(store.save_ama_query(&incomplete_query).is_err());
+        let num_comments: usize = store.load_ama_thread("url_id2").unwrap().len();
+        let ama_queries: Vec<AmaQuery> = store.load_ama_queries().unwrap();
+        let _ = fs::remove_file(full_dbpath);
+        assert_eq!(num_comments, 0);
+        assert_eq!(ama_queries.len(), 1);
+    }
+
+    #[test]
+    fn test_replace_ama_queries() {
+        let full_dbpath: &str = "output/ama_store-replace_test.db";
+        let mut store: Store = open_indexed_store(full_dbpath, &["url_id1", "url_id2"]);
+        let _ = store.save_ama_query(&get_ama_query("url_id1", "answer_text", 3)).unwrap();
+        let last_error = crate::Error::Validation("No answer found.".to_string());
+        let _ = store.save_failed_query("url_id2", 5, &last_error).unwrap();
+        let ama_queries: Vec<AmaQuery> = vec![
+            get_ama_query("url_id1", "new_answer_text", 1),
+            get_ama_query("url_id2", "answer_text", 0),
+        ];
+        assert_eq!(store.replace_ama_queries(&ama_queries).unwrap(), 3);
+        let mut actual: Vec<AmaQuery> = store.load_ama_queries().unwrap();
+        for ama_query in actual.iter_mut() {
+            ama_query.thread = store.load_ama_thread(&ama_query.url_id).unwrap();
+        }
+        let failed_urlids: Vec<String> = store.load_failed_urlids().unwrap();
+        let _ = fs::remove_file(full_dbpath);
+        assert_eq!(actual, ama_queries);
+        assert_eq!(failed_urlids, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_delete_cascade() {
+        let full_dbpath: &str = "output/ama_store-cascade_test.db";
+        let mut store: Store = open_indexed_store(full_dbpath, &["url_id1", "url_id2"]);
+        let _ = store.save_ama_query(&get_ama_query("url_id1", "answer_text", 3)).unwrap();
+        let last_error = crate::Error::Validation("No answer found.".to_string());
+        let _ = store.save_failed_query("url_id2", 5, &last_error).unwrap();
+        // A query cannot be saved for a url_id that no record links to.
+        let unlinked_result = store.save_ama_query(&get_ama_query("url_id3", "answer_text", 0));
+        let num_deleted: usize = store.cnxn.execute("DELETE FROM ama_index WHERE url_id IN ('url_id1', 'url_id2');", ()).unwrap();
+        let ama_queries: Vec<AmaQuery> = store.load_ama_queries().unwrap();
+        let num_comments: usize = store.load_ama_thread("url_id1").unwrap().len();
+        let failed_urlids: Vec<String> = store.load_failed_urlids().unwrap();
+        let _ = fs::remove_file(full_dbpath);
+        assert!(unlinked_result.is_err());
+        assert_eq!(num_deleted, 2);
+        assert_eq!(ama_queries, Vec::new());
+        assert_eq!(num_comments, 0);
+        assert_eq!(failed_urlids, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_load_field_edits_unknown_field() {
+        let full_dbpath: &str = "output/ama_store-edits_test.db";
+        let store: Store = open_indexed_store(full_dbpath, &["url_id1"]);
+        let _ = store.cnxn.execute(
+            "INSERT INTO ama_edits (cc_name, fan_name, url_id, field, old_value, new_value) VALUES ('cc_name', 'url_id1', 'url_id1', 'fan_name', NULL, 'new_value');",
+            ()
+        ).unwrap();
+        let load_result = store.load_field_edits();
+        let _ = fs::remove_file(full_dbpath);
+        match load_result {
+            Err(crate::Error::Parse(_)) => (),
+            other => panic!("Expected a parse error, got: {:?}", other),
+        };
+    }
+
+}
@@ -0,0 +1,204 @@
+//! This module checks the scraped exchanges against what the index says about them.
+//! - ValidationIssue: Something in the archive that is likely to be wrong.
+//! - find_author_mismatches: Flags answers that were not written by the creator they are credited to.
+//! - find_question_mismatches: Flags url_ids whose question was not asked by the fan they are credited to.
+use std::fmt;
+
+use crate::ama_config::AmaConfig;
+use crate::ama_indexer::{self, AmaRecord};
+use crate::ama_scraper::{AmaQuery, CommentMeta};
+
+/// Something in the archive that is likely to be wrong.
+#[derive(PartialEq)]
+#[derive(Debug)]
+pub enum ValidationIssue {
+    /// The config lists no Reddit usernames for `cc_name`, so their answers cannot be checked.
+    UnmappedCreator { cc_name: String },
+    /// The answer for `url_id` was written by `answer_author`, who is not one of `cc_name`'s accounts.
+    /// - answer_author: None if the account was deleted.
+    AuthorMismatch { url_id: String, cc_name: String, fan_name: String, answer_author: Option<String> },
+    /// The question at `url_id` was asked by `question_author`, not by `fan_name`, so the compendium
+    /// likely links `fan_name` to someone else's question.
+    QuestionMismatch { url_id: String, cc_name: String, fan_name: String, question_author: String },
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationIssue::UnmappedCreator { cc_name } => write!(f, "No Reddit usernames are configured for '{}'.", cc_name),
+            ValidationIssue::AuthorMismatch { url_id, cc_name, fan_name, answer_author } => match answer_author {
+                Some(answer_author) => write!(f, "'url_id' {} ({} to {}) was answered by u/{}, who is not {}.", url_id, fan_name, cc_name, answer_author, cc_name),
+                None => write!(f, "'url_id' {} ({} to {}) was answered by a deleted account.", url_id, fan_name, cc_name),
+            },
+            ValidationIssue::QuestionMismatch { url_id, cc_name, fan_name, question_author } => {
+                write!(f, "'url_id' {} ({} to {}) leads to a question by u/{}, who is not {}.", url_id, fan_name, cc_name, question_author, fan_name)
+            },
+        }
+    }
+}
+
+/// Returns whether `author` is one of the Reddit accounts of `cc_name`. Usernames are compared
+/// without regard to case, as Reddit does.
+fn is_creator_account(ama_config: &AmaConfig, cc_name: &str, author: &str) -> Option<bool> {
+    let usernames: &Vec<String> = ama_config.creators.get(cc_name)?;
+    Some(usernames.iter().any(|username| username.eq_ignore_ascii_case(author)))
+}
+
+/// Flags every scraped answer whose author is not one of the accounts that `ama_config.creators`
+/// lists for the creator it is credited to. Creators without any listed accounts are flagged once.
+/// - ama_config: Maps each creator's display name to their Reddit usernames.
+/// - ama_index: Records that credit each url_id to a creator.
+/// - ama_queries: Scraped exchanges. Records that were not scraped are not checked.
+pub fn find_author_mismatches(ama_config: &AmaConfig, ama_index: &[AmaRecord], ama_queries: &[AmaQuery]) -> Vec<ValidationIssue> {
+    let mut validation_issues: Vec<ValidationIssue> = Vec::new();
+    for ama_record in ama_index {
+        if !ama_config.creators.contains_key(&ama_record.cc_name) {
+            let unmapped_creator = ValidationIssue::UnmappedCreator { cc_name: ama_record.cc_name.clone() };
+            if !validation_issues.contains(&unmapped_creator) {
+                validation_issues.push(unmapped_creator);
+            }
+            continue;
+        }
+        let ama_query: &AmaQuery = match ama_queries.iter().find(|ama_query| ama_query.url_id == ama_record.url_id) {
+            Some(ama_query) => ama_query,
+            None => continue,
+        };
+        let answer_author: Option<&String> = ama_query.answer_meta.author.as_ref();
+        let is_match: bool = match answer_author {
+            Some(answer_author) => is_creator_account(ama_config, &ama_record.cc_name, answer_author) == Some(true),
+            None => false,
+        };
+        if !is_match {
+            validation_issues.push(
+                ValidationIssue::AuthorMismatch {
+                    url_id: ama_record.url_id.clone(),
+                    cc_name: ama_record.cc_name.clone(),
+                    fan_name: ama_record.fan_name.clone(),
+                    answer_author: answer_author.cloned(),
+                }
+            );
+        }
+    }
+    validation_issues
+}
+
+/// Flags every scraped question whose author is not the fan that its record credits it to, e.g.
+/// Joe_Zt's link that leads to ShinySaturn's question. Fan names are compared without the ' (again)'
+/// that the compendium adds, and without regard to case. Deleted authors are not checked.
+/// - ama_index: Records that credit each url_id to a fan.
+/// - ama_queries: Scraped exchanges. Records that were not scraped are not checked.
+pub fn find_question_mismatches(ama_index: &[AmaRecord], ama_queries: &[AmaQuery]) -> Vec<ValidationIssue> {
+    let mut validation_issues: Vec<ValidationIssue> = Vec::new();
+    for ama_record in ama_index {
+        let question_author: &String = match ama_queries.iter().find(|ama_query| ama_query.url_id == ama_record.url_id) {
+            Some(AmaQuery { question_meta: CommentMeta { author: Some(question_author), .. }, .. }) => question_author,
+            _ => continue,
+        };
+        if !ama_indexer::get_base_fan_name(&ama_record.fan_name).eq_ignore_ascii_case(question_author) {
+            validation_issues.push(
+                ValidationIssue::QuestionMismatch {
+                    url_id: ama_record.url_id.clone(),
+                    cc_name: ama_record.cc_name.clone(),
+                    fan_name: ama_record.fan_name.clone(),
+                    question_author: question_author.clone(),
+                }
+            );
+        }
+    }
+    validation_issues
+}
+
+#[cfg(test)]
+mod ama_validator_tests {
+    use super::ValidationIssue;
+    use crate::ama_indexer::AmaRecord;
+    use crate::ama_scraper::{AmaQuery, CommentMeta};
+
+    fn get_ama_record(cc_name: &str, fan_name: &str, url_id: &str) -> AmaRecord {
+        AmaRecord {
+            cc_name: cc_name.to_string(),
+            fan_name: fan_name.to_string(),
+            url_id: url_id.to_string(),
+        }
+    }
+
+    fn get_ama_query(url_id: &str, question_author: &str, answer_author: Option<&str>) -> AmaQuery {
+        AmaQuery {
+            url_id: url_id.to_string(),
+            question_text: Some("question_text".to_string()),
+            answer_text: Some("answer_text".to_string()),
+            question_meta: CommentMeta {
+                author: Some(question_author.to_string()),
+                ..Default::default()
+            },
+            answer_meta: CommentMeta {
+                author: answer_author.map(|answer_author| answer_author.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_find_author_mismatches() {
+        let ama_config: super::AmaConfig = crate::load_ama_config("tests/fixtures/test_ama.toml").unwrap();
+        let ama_index: Vec<AmaRecord> = vec![
+            get_ama_record("Alice Author", "fan_one", "c00001"),
+            get_ama_record("Alice Author", "fan_two", "c00003"),
+            get_ama_record("Bob Builder", "fan_one (again)", "c00005"),
+            get_ama_record("Carol Cameo", "fan_three", "c00020"),
+            get_ama_record("Carol Cameo", "fan_four", "c00022"),
+        ];
+        let ama_queries: Vec<AmaQuery> = vec![
+            get_ama_query("c00001", "fan_one", Some("Alice_Author")),
+            get_ama_query("c00003", "fan_two", Some("bobbuilds")),
+            get_ama_query("c00005", "fan_one", None),
+        ];
+        let expected: Vec<ValidationIssue> = vec![
+            ValidationIssue::AuthorMismatch {
+                url_id: "c00003".to_string(),
+                cc_name: "Alice Author".to_string(),
+                fan_name: "fan_two".to_string(),
+                answer_author: Some("bobbuilds".to_string()),
+            },
+            ValidationIssue::AuthorMismatch {
+                url_id: "c00005".to_string(),
+                cc_name: "Bob Builder".to_string(),
+                fan_name: "fan_one (again)".to_string(),
+                answer_author: None,
+            },
+            ValidationIssue::UnmappedCreator { cc_name: "Carol Cameo".to_string() },
+        ];
+        assert_eq!(super::find_author_mismatches(&ama_config, &ama_index, &ama_queries), expected);
+    }
+
+    #[test]
+    fn test_find_question_mismatches() {
+        // As in the link compendium, whose link for Joe_Zt leads to ShinySaturn's question.
+        let full_dbpath: &str = "output/ama_validator-questions_test.db";
+        let _ = std::fs::remove_file(full_dbpath);
+        let _ = crate::ama_indexer::upsert_ama_index(&[
+            get_ama_record("Daron Nefcy", "Joe_Zt", "evw8mcl"),
+            get_ama_record("Daron Nefcy", "VeronicaMewniFan (again)", "evw3fne"),
+            get_ama_record("Adam McArthur", "sloppyjeaux", "evwbcnk"),
+        ], full_dbpath).unwrap();
+        let mut deleted_ama_query: AmaQuery = get_ama_query("evwbcnk", "Hiofshao_Quask", Some("AdamMcArthur"));
+        deleted_ama_query.question_meta.author = None;
+        for ama_query in [get_ama_query("evw8mcl", "ShinySaturn", Some("DaronNefcy")), get_ama_query("evw3fne", "veronicamewnifan", Some("DaronNefcy")), deleted_ama_query] {
+            let _ = crate::ama_scraper::save_ama_query_to_db(ama_query, full_dbpath).unwrap();
+        }
+        let ama_index: Vec<AmaRecord> = crate::ama_indexer::load_ama_index(full_dbpath).unwrap();
+        let ama_queries: Vec<AmaQuery> = crate::ama_scraper::load_ama_queries_from_db(full_dbpath).unwrap();
+        let _ = std::fs::remove_file(full_dbpath);
+        let expected: Vec<ValidationIssue> = vec![
+            ValidationIssue::QuestionMismatch {
+                url_id: "evw8mcl".to_string(),
+                cc_name: "Daron Nefcy".to_string(),
+                fan_name: "Joe_Zt".to_string(),
+                question_author: "ShinySaturn".to_string(),
+            },
+        ];
+        assert_eq!(super::find_question_mismatches(&ama_index, &ama_queries), expected);
+    }
+
+}
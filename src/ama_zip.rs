@@ -0,0 +1,180 @@
+//! This module writes zip archives whose files are stored as they are, without compression.
+//! - crc32: Computes the CRC-32 checksum that zip archives keep for each file.
+//! - ZipWriter: Writes files into a zip archive one at a time, then its central directory.
+//!
+//! Every file is stamped with the earliest date that zip can record, 1980-01-01, so that the same
+//! files always make the same archive.
+use std::io::{self, Write};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+/// Zip 1.0, which is all that stored files need.
+const ZIP_VERSION: u16 = 10;
+/// 1980-01-01, in MS-DOS date format.
+const DOS_DATE: u16 = (1 << 5) | 1;
+const DOS_TIME: u16 = 0;
+
+/// Computes the CRC-32 checksum of `bytes`, as zip archives and PNG images use it.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffffffff;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb88320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+/// What the central directory records about each file, once it has been written.
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Writes a zip archive to `writer`, one stored file at a time. Nothing is readable as an archive
+/// until `finish` has written the central directory.
+pub struct ZipWriter<'a> {
+    writer: &'a mut dyn Write,
+    offset: u64,
+    entries: Vec<CentralEntry>,
+}
+
+impl<'a> ZipWriter<'a> {
+    /// Starts an empty archive.
+    pub fn new(writer: &'a mut dyn Write) -> Self {
+        ZipWriter {
+            writer,
+            offset: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let () = self.writer.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Returns `value` as a 32-bit field, or fails if the archive has outgrown plain zip.
+    fn get_u32(value: u64) -> io::Result<u32> {
+        u32::try_from(value).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Archive is too large for zip without ZIP64."))
+    }
+
+    /// Stores `contents` in the archive as `name`. Files are listed in the order they are added.
+    /// - name: Path of the file within the archive, with '/' between directories.
+    /// - contents: Bytes of the file.
+    pub fn add_file(&mut self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let crc: u32 = crc32(contents);
+        let size: u32 = Self::get_u32(contents.len() as u64)?;
+        let offset: u32 = Self::get_u32(self.offset)?;
+        let name_len: u16 = u16::try_from(name.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Name is too long for zip: {}", name)))?;
+        let mut header: Vec<u8> = Vec::with_capacity(30 + name.len());
+        header.extend(LOCAL_HEADER_SIGNATURE.to_le_bytes());
+        header.extend(ZIP_VERSION.to_le_bytes());
+        header.extend(0u16.to_le_bytes()); // No flags.
+        header.extend(0u16.to_le_bytes()); // Stored, not compressed.
+        header.extend(DOS_TIME.to_le_bytes());
+        header.extend(DOS_DATE.to_le_bytes());
+        header.extend(crc.to_le_bytes());
+        header.extend(size.to_le_bytes()); // Compressed size.
+        header.extend(size.to_le_bytes()); // Uncompressed size.
+        header.extend(name_len.to_le_bytes());
+        header.extend(0u16.to_le_bytes()); // No extra field.
+        header.extend(name.as_bytes());
+        let () = self.write_bytes(&header)?;
+        let () = self.write_bytes(contents)?;
+        self.entries.push(CentralEntry { name: name.to_string(), crc, size, offset });
+        Ok(())
+    }
+
+    /// Writes the central directory, which completes the archive. Returns the number of files in it.
+    pub fn finish(mut self) -> io::Result<usize> {
+        let directory_offset: u32 = Self::get_u32(self.offset)?;
+        let mut directory: Vec<u8> = Vec::new();
+        for entry in self.entries.iter() {
+            directory.extend(CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+            directory.extend(ZIP_VERSION.to_le_bytes()); // Made by.
+            directory.extend(ZIP_VERSION.to_le_bytes()); // Needed to extract.
+            directory.extend(0u16.to_le_bytes());
+            directory.extend(0u16.to_le_bytes());
+            directory.extend(DOS_TIME.to_le_bytes());
+            directory.extend(DOS_DATE.to_le_bytes());
+            directory.extend(entry.crc.to_le_bytes());
+            directory.extend(entry.size.to_le_bytes());
+            directory.extend(entry.size.to_le_bytes());
+            directory.extend((entry.name.len() as u16).to_le_bytes());
+            directory.extend(0u16.to_le_bytes()); // No extra field.
+            directory.extend(0u16.to_le_bytes()); // No comment.
+            directory.extend(0u16.to_le_bytes()); // Starts on disk 0.
+            directory.extend(0u16.to_le_bytes()); // No internal attributes.
+            directory.extend(0u32.to_le_bytes()); // No external attributes.
+            directory.extend(entry.offset.to_le_bytes());
+            directory.extend(entry.name.as_bytes());
+        }
+        let num_entries: u16 = u16::try_from(self.entries.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Too many files for zip without ZIP64."))?;
+        let directory_size: u32 = Self::get_u32(directory.len() as u64)?;
+        directory.extend(END_OF_DIRECTORY_SIGNATURE.to_le_bytes());
+        directory.extend(0u16.to_le_bytes()); // This disk.
+        directory.extend(0u16.to_le_bytes()); // Disk with the directory.
+        directory.extend(num_entries.to_le_bytes()); // Entries on this disk.
+        directory.extend(num_entries.to_le_bytes()); // Entries in all.
+        directory.extend(directory_size.to_le_bytes());
+        directory.extend(directory_offset.to_le_bytes());
+        directory.extend(0u16.to_le_bytes()); // No comment.
+        let () = self.write_bytes(&directory)?;
+        let () = self.writer.flush()?;
+        Ok(self.entries.len())
+    }
+}
+
+#[cfg(test)]
+mod ama_zip_tests {
+    use super::ZipWriter;
+
+    fn get_u16(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    fn get_u32(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(super::crc32(b""), 0);
+        assert_eq!(super::crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_zip_writer() {
+        let mut raw_zip: Vec<u8> = Vec::new();
+        let mut zip_writer = ZipWriter::new(&mut raw_zip);
+        zip_writer.add_file("mimetype", b"application/epub+zip").unwrap();
+        zip_writer.add_file("dir/file.txt", b"Hello, zip!").unwrap();
+        assert_eq!(zip_writer.finish().unwrap(), 2);
+        // The first file starts the archive, and its contents follow its 30-byte header and name.
+        assert_eq!(get_u32(&raw_zip, 0), super::LOCAL_HEADER_SIGNATURE);
+        assert_eq!(&raw_zip[30..38], b"mimetype");
+        assert_eq!(&raw_zip[38..58], b"application/epub+zip");
+        assert_eq!(get_u32(&raw_zip, 14), super::crc32(b"application/epub+zip"));
+        // The end of the central directory lists both files, and points back at the directory.
+        let end_offset: usize = raw_zip.len() - 22;
+        assert_eq!(get_u32(&raw_zip, end_offset), super::END_OF_DIRECTORY_SIGNATURE);
+        assert_eq!(get_u16(&raw_zip, end_offset + 10), 2);
+        let directory_offset: usize = get_u32(&raw_zip, end_offset + 16) as usize;
+        assert_eq!(get_u32(&raw_zip, directory_offset), super::CENTRAL_HEADER_SIGNATURE);
+        let second_offset: usize = get_u32(&raw_zip, directory_offset + 46 + 8 + 42) as usize;
+        assert_eq!(&raw_zip[second_offset + 30..second_offset + 42], b"dir/file.txt");
+        assert_eq!(directory_offset + get_u32(&raw_zip, end_offset + 12) as usize, end_offset);
+    }
+
+}
@@ -0,0 +1,80 @@
+//! This module defines the errors that the library reports to its callers.
+//! - Error: Every way in which a stage of the archiver can fail.
+//! - Result: Shorthand for results whose error is `Error`.
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Shorthand for results whose error is `Error`.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Every way in which a stage of the archiver can fail.
+#[derive(Debug)]
+pub enum Error {
+    /// A request to `url` failed, or its response could not be read.
+    /// - retry_after: How long the server asked us to wait before trying again, if it said so.
+    Network { url: String, message: String, retry_after: Option<Duration> },
+    /// Fetched or stored content did not have the expected structure.
+    Parse(String),
+    /// The archive database could not be read from or written to.
+    Storage(rusqlite::Error),
+    /// The file or directory at `path` could not be read from or written to.
+    Io { path: PathBuf, source: io::Error },
+    /// The data is well-formed, but is inconsistent with what the archive expects.
+    Validation(String),
+}
+
+impl Error {
+    /// Wraps an I/O error together with the path that caused it.
+    pub fn io(path: impl AsRef<Path>, source: io::Error) -> Self {
+        Error::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    /// Wraps a failed request to `url`, and keeps any 'Retry-After' header the server sent with it.
+    pub fn network(url: &str, reqerr: ureq::Error) -> Self {
+        let retry_after: Option<Duration> = match &reqerr {
+            // Only the delay-seconds form is honored; an HTTP-date falls back to the usual backoff.
+            ureq::Error::Status(_, resp) => resp.header("Retry-After")
+                .and_then(|seconds| seconds.trim().parse::<u64>().ok())
+                .map(Duration::from_secs),
+            ureq::Error::Transport(_) => None,
+        };
+        Error::Network {
+            url: url.to_string(),
+            message: reqerr.to_string(),
+            retry_after,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Network { url, message, .. } => write!(f, "Unable to get response from '{}': {}", url, message),
+            Error::Parse(message) => write!(f, "Parse error: {}", message),
+            Error::Storage(sql_err) => write!(f, "Database error: {}", sql_err),
+            Error::Io { path, source } => write!(f, "I/O error at {:?}: {}", path, source),
+            Error::Validation(message) => write!(f, "Validation error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Storage(sql_err) => Some(sql_err),
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(sql_err: rusqlite::Error) -> Self {
+        Error::Storage(sql_err)
+    }
+}
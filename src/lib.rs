@@ -8,107 +8,311 @@
 */
 
 // TODO: Implement logging.
-// TODO: Insert code to prevent overwrite... if it's actually necessary to do so.
 
 use std::fs;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
 
-use rusqlite;
-
-mod ama_indexer;
+pub mod ama_indexer;
 //pub use crate::indexer::ama_indexer;
 
-mod ama_scraper;
+pub mod ama_scraper;
 //pub use crate::scraper::ama_scraper;
 
+mod ama_config;
+pub use crate::ama_config::{AmaConfig, load_ama_config};
+
+mod error;
+pub use crate::error::{Error, Result};
+
+pub mod ama_fetcher;
+pub use crate::ama_fetcher::{Fetcher, UreqFetcher, ReplayFetcher, RecordingFetcher};
+
+pub use crate::ama_scraper::{Backend, RetryPolicy};
+
+pub mod ama_validator;
+pub use crate::ama_validator::ValidationIssue;
+
+pub mod ama_corrections;
+pub use crate::ama_corrections::{Correction, CorrectionStatus};
+
+pub mod ama_schema;
+
+pub mod ama_store;
+pub use crate::ama_store::Store;
+
+pub mod ama_filetree;
+
+pub mod ama_search;
+pub use crate::ama_search::SearchResult;
+
+pub mod ama_export;
+pub use crate::ama_export::ExportFormat;
+
+pub mod ama_csv;
+pub use crate::ama_csv::{CsvColumn, CsvOptions};
+
+pub mod ama_markdown;
+
+pub mod ama_site;
+
+pub mod ama_zip;
+
+pub mod ama_epub;
+
+pub mod ama_json;
+
 const LC_FNAME: &str = "link-compendium";
-const ODIR_NAME: &str = "output";
-const LC_URL: &str = "https://old.reddit.com/r/StarVStheForcesofEvil/comments/clnrdv/link_compendium_of_questions_and_answers_from_the/";
-const FIRST_CC_NAME: &str = "Daron Nefcy:";
-const DB_FNAME: &str = "ama_archive.db";
-
-pub fn fix_database() -> () {
-    // - UPDATE ama_index SET url_id='evw8g9o' WHERE fan_name='Joe_Zt' AND cc_name='Daron Nefcy';
-    // - UPDATE ama_index SET url_id='evwbgza' WHERE fan_name='sloppyjeaux' AND cc_name='Adam McArthur';
-    let db_filename: String = format!("{}/{}", ODIR_NAME, DB_FNAME);
-    let cnxn: rusqlite::Connection = rusqlite::Connection::open(db_filename).unwrap();
-    let fix_list: Vec<&str> = Vec::from(
-        [
-            "UPDATE ama_index SET url_id='evw8g9o' WHERE fan_name='Joe_Zt' AND cc_name='Daron Nefcy';",
-            "UPDATE ama_index SET url_id='evwbgza' WHERE fan_name='sloppyjeaux' AND cc_name='Adam McArthur';",
-        ]
-    );
-    for fix in fix_list {
-        match cnxn.execute(&fix, ()) {
-            Ok(_) => println!("{}", fix),
-            Err(_) => println!("Could not execute query."),
+const PAGES_DNAME: &str = "ama_pages";
+const FILETREE_DNAME: &str = "ama_filetree";
+const SITE_DNAME: &str = "ama_site";
+/// Name of the exported archive, to which the extension of its format is added.
+const EXPORT_FNAME: &str = "ama_archive";
+/// Output path that means standard output, instead of a file.
+pub const STDOUT_PATH: &str = "-";
+/// Default directory that every stage reads from and writes to.
+pub const ODIR_NAME: &str = "output";
+/// Default filename of the archive database, relative to the output directory.
+pub const DB_FNAME: &str = "ama_archive.db";
+
+/// Brings the database in `full_dbpath` up to the latest schema version, creating it if need be.
+/// - full_dbpath: Tells function where to find the database.
+pub fn migrate_database(full_dbpath: &str) -> Result<()> {
+    let version: u32 = ama_schema::migrate(full_dbpath)?;
+    println!("'{}' is at schema version {}.", full_dbpath, version);
+    Ok(())
+}
+
+/// Applies the corrections listed in `ama_config` to the `ama_index` table in `full_dbpath`, and
+/// reports what each of them did. Corrections that were applied before are skipped.
+/// The database is upgraded afterwards, since older ones cannot be while their url_ids are shared.
+/// - ama_config: The AMA whose corrections are to be applied.
+/// - full_dbpath: Tells function where to find `ama_index`
+/// - dry_run: Reports what would be applied, without saving any change.
+pub fn fix_database(ama_config: &AmaConfig, full_dbpath: &str, dry_run: bool) -> Result<Vec<CorrectionStatus>> {
+    let correction_statuses: Vec<CorrectionStatus> = ama_corrections::apply_corrections(&ama_config.corrections, full_dbpath, dry_run)?;
+    for (correction, correction_status) in ama_config.corrections.iter().zip(correction_statuses.iter()) {
+        match correction_status {
+            CorrectionStatus::NoMatch => eprintln!("Correction {}: {}", correction_status, correction.reason),
+            _ => println!("Correction {}: {}", correction_status, correction.reason),
         };
+    }
+    match dry_run {
+        true => println!("Dry run: no correction was saved."),
+        false => {
+            let _ = ama_schema::migrate(full_dbpath)?;
+        },
     };
+    Ok(correction_statuses)
 }
 
-pub fn write_filetree() -> () {
-    // Turns out that I didn't need an entire module for this after all.
-    let db_filename: String = format!("{}/{}", ODIR_NAME, DB_FNAME);
-    let ama_queries: Vec<ama_scraper::AmaQuery> = ama_scraper::load_ama_queries_from_db(&db_filename);
-    let ama_index: Vec<ama_indexer::AmaRecord> = ama_indexer::load_ama_index(&db_filename);
-    // query buffer, really
-    let mut temp_query = ama_scraper::AmaQuery {
-        url_id: String::new(),
-        question_text: None,
-        answer_text: None,
-    };
-    let mut root_path = PathBuf::new();
-    root_path.push(ODIR_NAME);
-    root_path.push("ama_filetree");
-    for ama_record in ama_index {
-        // match url_id to ama_query
-        for ama_query in ama_queries.iter() {
-            if ama_record.url_id == ama_query.url_id {
-                temp_query = ama_scraper::AmaQuery {
-                    url_id: ama_query.url_id.clone(),
-                    question_text: (*ama_query).question_text.clone(),
-                    answer_text: (*ama_query).answer_text.clone(),
-                };
-                break;
-            }
-            // create directories
-            root_path.push(&ama_record.cc_name);
-            root_path.push(&ama_record.fan_name);
-            match fs::create_dir_all(&root_path) {
-                Ok(_) => println!("Output directory created: {:?}", &root_path),
-                Err(_) => eprintln!("Output directory already exists: {:?}", &root_path),
-            };
-            for fieldname in ["question_text", "answer_text", "url_id"] {
-                let text_fname = format!("{}.txt", fieldname);
-                root_path.push(text_fname);
-                let contents = match fieldname {
-                    "question_text" => temp_query.question_text.clone().unwrap(),
-                    "answer_text" => temp_query.answer_text.clone().unwrap(),
-                    "url_id" => temp_query.url_id.clone(),
-                    _ => panic!(""),
-                };
-                match fs::write(&root_path, contents) {
-                    Ok(_) => println!("Contents written to {:?}", &root_path),
-                    Err(_) => eprintln!("Contents not written to {:?}", &root_path),
-                };
-                root_path.pop();
+/// Reports every record in `unscraped_records`, which have no scraped query, and so were not `verb`,
+/// e.g. "written" or "exported".
+fn report_unscraped(unscraped_records: &[ama_indexer::AmaRecord], verb: &str) {
+    if unscraped_records.is_empty() {
+        return;
+    }
+    let record_names: Vec<String> = unscraped_records.iter()
+        .map(|ama_record| format!("{} to {} ({})", ama_record.fan_name, ama_record.cc_name, ama_record.url_id))
+        .collect();
+    eprintln!("{} records have no scraped query, and were not {}: {}", unscraped_records.len(), verb, record_names.join("; "));
+}
+
+/// Writes every scraped exchange to `odir_name/ama_filetree/{cc_name}/{fan_name}/`, once each, with
+/// both names made safe for the filesystem. The names that each directory was written for are saved
+/// to `odir_name/ama_filetree/manifest.json`. Records that have no scraped query are reported, and returned.
+/// - ama_config: The AMA whose thread the exchanges are in.
+/// - odir_name: Path of output directory.
+/// - full_dbpath: Tells function where to find `ama_index` and `ama_queries`
+pub fn write_filetree(ama_config: &AmaConfig, odir_name: &str, full_dbpath: &str) -> Result<Vec<ama_indexer::AmaRecord>> {
+    let store: Store = Store::open(full_dbpath)?;
+    let ama_index: Vec<ama_indexer::AmaRecord> = store.load_ama_index()?;
+    let (exchanges, unscraped_records) = ama_export::get_exchanges(ama_config, &ama_index, &store.load_ama_queries()?);
+    let root_path: PathBuf = Path::new(odir_name).join(FILETREE_DNAME);
+    // Directories are named after the whole index, so that they stay put as more of it is scraped.
+    let filetree_entries: Vec<ama_filetree::FiletreeEntry> = ama_filetree::get_filetree_entries(&ama_index);
+    let scraped_entries = filetree_entries.into_iter()
+        .filter(|filetree_entry| !unscraped_records.iter().any(|ama_record| ama_record.cc_name == filetree_entry.cc_name && ama_record.fan_name == filetree_entry.fan_name));
+    let mut written_entries: Vec<ama_filetree::FiletreeEntry> = Vec::new();
+    for (exchange, filetree_entry) in exchanges.iter().zip(scraped_entries) {
+        let record_path: PathBuf = root_path.join(&filetree_entry.path);
+        if let Err(io_err) = fs::create_dir_all(&record_path) {
+            return Err(Error::io(&record_path, io_err));
+        }
+        for (fieldname, contents) in [("question_text", &exchange.question_text), ("answer_text", &exchange.answer_text), ("url_id", &exchange.url_id)] {
+            let text_path: PathBuf = record_path.join(format!("{}.txt", fieldname));
+            if let Err(io_err) = fs::write(&text_path, contents) {
+                return Err(Error::io(&text_path, io_err));
             }
-            // write to disk
-            root_path.pop();
-            root_path.pop();
         }
+        written_entries.push(filetree_entry);
+    }
+    if !written_entries.is_empty() {
+        let () = ama_filetree::save_manifest(&root_path, &written_entries)?;
     }
+    println!("{} exchanges written to {:?}.", written_entries.len(), &root_path);
+    let () = report_unscraped(&unscraped_records, "written");
+    Ok(unscraped_records)
 }
 
+/// Reads back the filetree written by `write_filetree` to `odir_name`, and applies every field that
+/// was edited by hand to `ama_queries` and `ama_index`. Each applied edit is recorded in `ama_edits`.
+/// Directories that match no scraped record are reported, and skipped. Returns the edits found.
+/// - odir_name: Path of output directory.
+/// - full_dbpath: Tells function where to find `ama_index` and `ama_queries`
+/// - dry_run: Reports the edits, without saving any of them.
+pub fn import_filetree(odir_name: &str, full_dbpath: &str, dry_run: bool) -> Result<Vec<ama_filetree::FieldEdit>> {
+    let root_path: PathBuf = Path::new(odir_name).join(FILETREE_DNAME);
+    let mut store: Store = Store::open(full_dbpath)?;
+    let filetree_exchanges: Vec<ama_filetree::FiletreeExchange> = ama_filetree::load_filetree(&root_path)?;
+    let (field_edits, unmatched_paths) = ama_filetree::find_edits(&filetree_exchanges, &store.load_ama_index()?, &store.load_ama_queries()?);
+    if !unmatched_paths.is_empty() {
+        eprintln!("{} directories match no scraped record, and were skipped: {:?}", unmatched_paths.len(), unmatched_paths);
+    }
+    let () = apply_edits(&mut store, &field_edits, filetree_exchanges.len(), dry_run)?;
+    Ok(field_edits)
+}
 
-pub fn compile_queries() -> () {
-    // pseudo-constant
-    let full_dbpath: &str = "output/ama_archive.db";
-    let ama_index: Vec<ama_indexer::AmaRecord> = ama_indexer::load_ama_index(full_dbpath);
-    let () = ama_scraper::create_db(full_dbpath);
-    let scraped_ama_queries: Vec<ama_scraper::AmaQuery> = ama_scraper::load_ama_queries_from_db(full_dbpath);
+/// Reads back a sheet exported by `export_archive` as CSV or TSV, and applies every field that was
+/// edited in it to `ama_queries` and `ama_index`, as `import_filetree` does. Sheets whose name ends
+/// in '.tsv' or '.tab' are read as TSV, and every other sheet as CSV. Returns the edits found.
+/// - full_dbpath: Tells function where to find `ama_index` and `ama_queries`
+/// - sheet_path: Path of the edited sheet. Needs at least its 'cc_name' and 'fan_name' columns.
+/// - dry_run: Reports the edits, without saving any of them.
+pub fn import_csv(full_dbpath: &str, sheet_path: &str, dry_run: bool) -> Result<Vec<ama_filetree::FieldEdit>> {
+    let delimiter: char = match Path::new(sheet_path).extension().and_then(|extension| extension.to_str()) {
+        Some("tsv") | Some("tab") => '\t',
+        _ => ',',
+    };
+    let raw_csv: String = match fs::read_to_string(sheet_path) {
+        Ok(raw_csv) => raw_csv,
+        Err(io_err) => return Err(Error::io(sheet_path, io_err)),
+    };
+    let sheet_exchanges: Vec<ama_filetree::FiletreeExchange> = ama_csv::read_csv_exchanges(&raw_csv, delimiter)?;
+    let mut store: Store = Store::open(full_dbpath)?;
+    let (field_edits, unmatched_rows) = ama_filetree::find_edits(&sheet_exchanges, &store.load_ama_index()?, &store.load_ama_queries()?);
+    if !unmatched_rows.is_empty() {
+        eprintln!("{} rows match no scraped record, and were skipped: {}", unmatched_rows.len(), unmatched_rows.join(", "));
+    }
+    let () = apply_edits(&mut store, &field_edits, sheet_exchanges.len(), dry_run)?;
+    Ok(field_edits)
+}
+
+/// Prints every edit in `field_edits`, and applies them all, unless `dry_run` is set.
+/// - num_exchanges: How many exchanges were read back, for the summary.
+fn apply_edits(store: &mut Store, field_edits: &[ama_filetree::FieldEdit], num_exchanges: usize, dry_run: bool) -> Result<()> {
+    for field_edit in field_edits.iter() {
+        println!("Edited: {}.", field_edit);
+    }
+    match dry_run {
+        true => println!("Dry run: {} edits were found in {} exchanges, and none was saved.", field_edits.len(), num_exchanges),
+        false => {
+            let num_applied: usize = store.apply_field_edits(field_edits)?;
+            println!("{} edits were imported from {} exchanges.", num_applied, num_exchanges);
+        },
+    };
+    Ok(())
+}
+
+/// Exports every scraped exchange, joined to its record in the index, to `output_path`, or to
+/// `odir_name/ama_archive.{extension}` if none is given. Records that have no scraped query are
+/// reported, and returned.
+/// - ama_config: The AMA whose thread the exchanges are in.
+/// - odir_name: Path of output directory.
+/// - full_dbpath: Tells function where to find `ama_index` and `ama_queries`
+/// - export_format: Format to write the exchanges in.
+/// - output_path: Where to write the export. `STDOUT_PATH` writes it to standard output.
+/// - csv_options: Which columns to write to CSV or TSV, and whether to start with a byte order mark.
+pub fn export_archive(ama_config: &AmaConfig, odir_name: &str, full_dbpath: &str, export_format: ExportFormat, output_path: Option<&str>, csv_options: &CsvOptions) -> Result<Vec<ama_indexer::AmaRecord>> {
+    let store: Store = Store::open(full_dbpath)?;
+    let (exchanges, unscraped_records) = ama_export::get_exchanges(ama_config, &store.load_ama_index()?, &store.load_ama_queries()?);
+    let output_path: PathBuf = match output_path {
+        Some(output_path) => PathBuf::from(output_path),
+        None => Path::new(odir_name).join(format!("{}.{}", EXPORT_FNAME, export_format.get_extension())),
+    };
+    let mut writer: Box<dyn Write> = match output_path.to_str() {
+        Some(STDOUT_PATH) => Box::new(io::stdout().lock()),
+        _ => match fs::File::create(&output_path) {
+            Ok(export_file) => Box::new(io::BufWriter::new(export_file)),
+            Err(io_err) => return Err(Error::io(&output_path, io_err)),
+        },
+    };
+    let write_result: io::Result<()> = match export_format {
+        ExportFormat::Json => ama_export::write_json(ama_config, &exchanges, writer.as_mut()),
+        ExportFormat::Jsonl => ama_export::write_jsonl(&exchanges, writer.as_mut()),
+        ExportFormat::Csv => ama_csv::write_csv(&exchanges, ',', csv_options, writer.as_mut()),
+        ExportFormat::Tsv => ama_csv::write_csv(&exchanges, '\t', csv_options, writer.as_mut()),
+        ExportFormat::Markdown => ama_markdown::write_book(ama_config, &exchanges, writer.as_mut()),
+        ExportFormat::Epub => ama_epub::write_epub(ama_config, &exchanges, writer.as_mut()).map(|_| ()),
+    };
+    if let Err(io_err) = write_result.and_then(|()| writer.flush()) {
+        return Err(Error::io(&output_path, io_err));
+    }
+    // The export itself may be on standard output, so it is reported on standard error.
+    eprintln!("{} exchanges exported to {:?} as {}.", exchanges.len(), &output_path, export_format);
+    let () = report_unscraped(&unscraped_records, "exported");
+    Ok(unscraped_records)
+}
+
+/// Writes every scraped exchange to a static website in `site_dir`, or in `odir_name/ama_site` if
+/// none is given, that can be browsed without a network connection. Records that have no scraped
+/// query are reported, and returned.
+/// - ama_config: The AMA whose thread the exchanges are in.
+/// - odir_name: Path of output directory.
+/// - full_dbpath: Tells function where to find `ama_index` and `ama_queries`
+/// - site_dir: Root directory of the site.
+pub fn write_site(ama_config: &AmaConfig, odir_name: &str, full_dbpath: &str, site_dir: Option<&str>) -> Result<Vec<ama_indexer::AmaRecord>> {
+    let store: Store = Store::open(full_dbpath)?;
+    let (exchanges, unscraped_records) = ama_export::get_exchanges(ama_config, &store.load_ama_index()?, &store.load_ama_queries()?);
+    let site_path: PathBuf = match site_dir {
+        Some(site_dir) => PathBuf::from(site_dir),
+        None => Path::new(odir_name).join(SITE_DNAME),
+    };
+    let num_pages: usize = ama_site::write_site(ama_config, &exchanges, &site_path)?;
+    println!("{} pages for {} exchanges written to {:?}.", num_pages, exchanges.len(), &site_path);
+    let () = report_unscraped(&unscraped_records, "written");
+    Ok(unscraped_records)
+}
+
+/// Searches the text of every scraped exchange for `terms`, and prints the matches, best first,
+/// with the matching words highlighted. Returns the matches.
+/// - ama_config: The AMA whose thread the exchanges are in.
+/// - full_dbpath: Tells function where to find `ama_index` and `ama_queries`
+/// - terms: Words that every match must contain, in any order.
+/// - cc_name: Only search the exchanges with this creator.
+/// - limit: Most matches to return.
+pub fn search_archive(ama_config: &AmaConfig, full_dbpath: &str, terms: &str, cc_name: Option<&str>, limit: usize) -> Result<Vec<SearchResult>> {
+    let match_query: String = ama_search::get_match_query(terms);
+    if match_query.is_empty() {
+        return Err(Error::Validation("Search terms must not be empty.".to_string()));
+    }
+    let store: Store = Store::open(full_dbpath)?;
+    let search_results: Vec<SearchResult> = store.search(&match_query, cc_name, limit)?;
+    for (resultno, search_result) in search_results.iter().enumerate() {
+        println!("{}. {}", resultno + 1, search_result);
+        println!("   {}", ama_indexer::get_url(ama_config, search_result.url_id.clone()));
+    }
+    println!("{} matches for '{}' were found, of at most {}.", search_results.len(), terms, limit);
+    Ok(search_results)
+}
+
+/// Scrapes the Q&A text for every record in `ama_index` that has not been scraped yet, and caches
+/// every fetched page in `odir_name/ama_pages/{url_id}.html`, or `.json` for JSON listings.
+/// Records that run out of retries are saved to `ama_failures`, and skipped.
+/// - fetcher: Where to get the pages from, e.g. the live site or a recording.
+/// - backend: Whether to scrape each page, or read its JSON listing.
+/// - ama_config: The AMA whose thread the records point into.
+/// - odir_name: Path of output directory.
+/// - full_dbpath: Tells function where to find `ama_index`, and where to save `ama_queries`
+/// - retry_policy: How many times to fetch each record, and how long to wait in between.
+pub fn compile_queries(fetcher: &dyn Fetcher, backend: Backend, ama_config: &AmaConfig, odir_name: &str, full_dbpath: &str, retry_policy: &RetryPolicy) -> Result<()> {
+    let pages_dir: PathBuf = Path::new(odir_name).join(PAGES_DNAME);
+    let mut store: Store = Store::open(full_dbpath)?;
+    let ama_index: Vec<ama_indexer::AmaRecord> = store.load_ama_index()?;
+    let scraped_ama_queries: Vec<ama_scraper::AmaQuery> = store.load_ama_queries()?;
     let scraped_urls: Vec<String> = scraped_ama_queries.into_iter().map(|query| query.url_id).collect();
     let record_total = ama_index.len();
+    let mut failed_urlids: Vec<String> = Vec::new();
     for (recordno, ama_record) in ama_index.into_iter().enumerate() {
         if scraped_urls.contains(&ama_record.url_id) {
             continue;
@@ -118,41 +322,539 @@ pub fn compile_queries() -> () {
             url_id: ama_record.url_id.clone(),
             question_text: None,
             answer_text: None,
+            ..Default::default()
         };
         let url_id: String = ama_record.url_id;
-        let url: String = ama_indexer::get_url(url_id);
-        let mut num_attempts: u32 = 1;
-        while let None = fetched_ama_query.answer_text {
-            println!("Fetching record... Attempt: {}", num_attempts);
-            let () = ama_scraper::fetch_ama_query(&url, &mut fetched_ama_query);
-            num_attempts += 1;
+        let url: String = ama_indexer::get_url(ama_config, url_id.clone());
+        match ama_scraper::fetch_ama_query_with_retry(fetcher, backend, ama_config, &url, &mut fetched_ama_query, retry_policy, Some(&pages_dir)) {
+            Ok(_) => {
+                let _ = store.save_ama_query(&fetched_ama_query)?;
+                let _ = store.clear_failed_query(&url_id)?;
+            },
+            Err(fetch_err) => {
+                eprintln!("Giving up on 'url_id' {}: {}", url_id, fetch_err);
+                let _ = store.save_failed_query(&url_id, retry_policy.max_attempts, &fetch_err)?;
+                failed_urlids.push(url_id);
+            },
         };
-        let _ = ama_scraper::save_ama_query_to_db(fetched_ama_query, full_dbpath);
     };
-    println!("All {} queries have been scraped.", record_total);
+    match failed_urlids.len() {
+        0 => println!("All {} queries have been scraped.", record_total),
+        num_failed => eprintln!("{} of {} queries could not be scraped, and were marked failed: {:?}", num_failed, record_total, failed_urlids),
+    };
+    Ok(())
+}
+
+/// Rebuilds `ama_queries` from the pages cached by `compile_queries`, without touching the network.
+/// Records whose page was never cached by `backend` are left as they are. Every reparsed query is
+/// saved in one transaction, so that a failed run leaves `ama_queries` as it was.
+/// - ama_config: Names the creators, whose comments are the answers.
+/// - odir_name: Path of output directory.
+/// - full_dbpath: Tells function where to find `ama_index` and `ama_queries`
+/// - backend: Backend whose cached pages are to be reparsed.
+pub fn reparse_queries(ama_config: &AmaConfig, odir_name: &str, full_dbpath: &str, backend: Backend) -> Result<()> {
+    let pages_dir: PathBuf = Path::new(odir_name).join(PAGES_DNAME);
+    let mut store: Store = Store::open(full_dbpath)?;
+    let ama_index: Vec<ama_indexer::AmaRecord> = store.load_ama_index()?;
+    let mut reparsed_ama_queries: Vec<ama_scraper::AmaQuery> = Vec::new();
+    let mut unanswered_urlids: Vec<String> = Vec::new();
+    for ama_record in ama_index {
+        let raw_page: String = match ama_scraper::load_raw_query(&pages_dir, &ama_record.url_id, backend)? {
+            Some(raw_page) => raw_page,
+            None => continue,
+        };
+        let mut reparsed_ama_query = ama_scraper::AmaQuery {
+            url_id: ama_record.url_id.clone(),
+            question_text: None,
+            answer_text: None,
+            ..Default::default()
+        };
+        let () = backend.parse_ama_query(ama_config, &raw_page, &mut reparsed_ama_query)?;
+        if reparsed_ama_query.answer_text.is_none() {
+            unanswered_urlids.push(ama_record.url_id);
+            continue;
+        }
+        reparsed_ama_queries.push(reparsed_ama_query);
+    };
+    let num_rows: usize = store.replace_ama_queries(&reparsed_ama_queries)?;
+    println!("{} queries have been reparsed from {:?}, in {} rows.", reparsed_ama_queries.len(), &pages_dir, num_rows);
+    if !unanswered_urlids.is_empty() {
+        eprintln!("{} cached pages have no answer, and were left as they are: {:?}", unanswered_urlids.len(), unanswered_urlids);
+    }
+    Ok(())
+}
+
+/// Checks every scraped answer against the creator it is credited to, and every scraped question
+/// against the fan it is credited to. Prints, and returns, whatever looks wrong.
+/// - ama_config: Maps each creator to their Reddit usernames.
+/// - full_dbpath: Tells function where to find `ama_index` and `ama_queries`
+pub fn validate_archive(ama_config: &AmaConfig, full_dbpath: &str) -> Result<Vec<ValidationIssue>> {
+    let store: Store = Store::open(full_dbpath)?;
+    let ama_index: Vec<ama_indexer::AmaRecord> = store.load_ama_index()?;
+    let ama_queries: Vec<ama_scraper::AmaQuery> = store.load_ama_queries()?;
+    let mut validation_issues: Vec<ValidationIssue> = ama_validator::find_author_mismatches(ama_config, &ama_index, &ama_queries);
+    validation_issues.extend(ama_validator::find_question_mismatches(&ama_index, &ama_queries));
+    for validation_issue in validation_issues.iter() {
+        eprintln!("{}", validation_issue);
+    }
+    match validation_issues.len() {
+        0 => println!("All {} records are consistent with their scraped exchanges.", ama_index.len()),
+        num_issues => eprintln!("{} issues were found in {} records.", num_issues, ama_index.len()),
+    };
+    Ok(validation_issues)
+}
+
+/// Returns who asked the question at `url_id`, from its cached page if there is one, and otherwise
+/// from a single fetch, which is then cached. Returns None if the page cannot be had.
+fn get_question_author(fetcher: &dyn Fetcher, backend: Backend, ama_config: &AmaConfig, pages_dir: &Path, url_id: &str) -> Option<String> {
+    let mut ama_query = ama_scraper::AmaQuery {
+        url_id: url_id.to_string(),
+        question_text: None,
+        answer_text: None,
+        ..Default::default()
+    };
+    let page_result: Result<()> = match ama_scraper::load_raw_query(pages_dir, url_id, backend) {
+        Ok(Some(raw_page)) => backend.parse_ama_query(ama_config, &raw_page, &mut ama_query),
+        Ok(None) => ama_scraper::fetch_ama_query(fetcher, backend, ama_config, &ama_indexer::get_url(ama_config, url_id.to_string()), &mut ama_query)
+            .and_then(|raw_page| ama_scraper::save_raw_query(&raw_page, pages_dir, url_id, backend)),
+        Err(load_err) => Err(load_err),
+    };
+    if let Err(page_err) = page_result {
+        eprintln!("Could not check who asked 'url_id' {}: {}", url_id, page_err);
+    }
+    ama_query.question_meta.author
 }
 
-pub fn compile_index() -> () {
+/// Reports every url_id that more than one record in `ama_index` links to, unless a correction in
+/// `ama_config` already covers it, and proposes which records to correct. Returns how many were
+/// not covered.
+fn report_duplicates(fetcher: &dyn Fetcher, backend: Backend, ama_config: &AmaConfig, odir_name: &str, ama_index: &[ama_indexer::AmaRecord]) -> usize {
+    let pages_dir: PathBuf = Path::new(odir_name).join(PAGES_DNAME);
+    let mut num_uncorrected: usize = 0;
+    for duplicate in ama_indexer::identify_duplicates(ama_index) {
+        let fan_names: Vec<&str> = duplicate.positions.iter().map(|position| ama_index[*position].fan_name.as_str()).collect();
+        let corrections: Vec<&Correction> = ama_config.corrections.iter()
+            .filter(|correction| duplicate.positions.iter().any(|position| correction.matches(&ama_index[*position])))
+            .collect();
+        if !corrections.is_empty() {
+            println!("'url_id' {} is shared by {:?}, and is corrected by: {:?}", duplicate.url_id, fan_names, corrections.iter().map(|correction| &correction.reason).collect::<Vec<&String>>());
+            continue;
+        }
+        num_uncorrected += 1;
+        eprintln!("'url_id' {} is shared by {:?}.", duplicate.url_id, fan_names);
+        let question_author: Option<String> = get_question_author(fetcher, backend, ama_config, &pages_dir, &duplicate.url_id);
+        let proposed_corrections: Vec<ama_indexer::ProposedCorrection> = ama_indexer::propose_corrections(ama_index, &duplicate, question_author.as_deref());
+        if proposed_corrections.is_empty() {
+            eprintln!("Could not tell which of them is linked by mistake. Inspect.");
+        }
+        for proposed_correction in proposed_corrections {
+            eprintln!("Proposed correction:\n{}", proposed_correction);
+        }
+    }
+    num_uncorrected
+}
+
+/// Scrapes the link compendium into `odir_name`, applies the corrections in `ama_config`, and saves
+/// the compiled index to `full_dbpath`. Every url_id that several records link to is reported,
+/// together with proposed corrections. Since each url_id leads to a single exchange, records that
+/// still share one keep the url_id they were saved with before, and new ones are skipped, except
+/// for the first if none of them was saved before. No saved record is ever removed for sharing a
+/// url_id. Records whose link has no url_id are reported and skipped. Url_ids edited by hand are
+/// applied again after the corrections, so that indexing does not undo an import.
+/// The index is saved over any that was compiled before, and the records added, changed and removed
+/// since are reported, and returned.
+/// - fetcher: Where to get the link compendium from, e.g. the live site or a recording.
+/// - backend: Whether to scrape the compendium's page, or read its JSON listing. Either way, the
+///   body of the compendium is cached as HTML.
+/// - ama_config: The AMA whose link compendium is to be indexed.
+/// - odir_name: Path of output directory.
+/// - full_dbpath: Tells function where to save `ama_index`
+/// - fail_on_duplicates: Fails before anything is saved, if any shared url_id has no correction.
+pub fn compile_index(fetcher: &dyn Fetcher, backend: Backend, ama_config: &AmaConfig, odir_name: &str, full_dbpath: &str, fail_on_duplicates: bool) -> Result<ama_indexer::IndexChanges> {
     // If the file DNE, then scrape the index off the source, and save it to disk.
-    let raw_htmlfile: String = format!("{}/{}.html", ODIR_NAME, LC_FNAME);
+    let raw_htmlfile: String = format!("{}/{}.html", odir_name, LC_FNAME);
     let raw_htmlpath: &Path = Path::new(&raw_htmlfile);
     if !raw_htmlpath.exists() {
-        let raw_html: String = ama_indexer::fetch_raw_index(LC_URL);
-        let () = ama_indexer::save_raw_index(raw_html, ODIR_NAME, LC_FNAME);
+        let raw_page: String = ama_indexer::fetch_raw_index(fetcher, &backend.get_page_url(&ama_config.compendium_url))?;
+        let () = ama_indexer::save_raw_index(backend.get_post_html(&raw_page)?, odir_name, LC_FNAME)?;
     };
     // Grab text off file, and convert it to AmaRecord format.
-    let raw_html: String = fs::read_to_string(raw_htmlfile).unwrap();
-    let mut ama_index: Vec<ama_indexer::AmaRecord> = ama_indexer::compile_ama_index(raw_html, FIRST_CC_NAME);
+    let raw_html: String = match fs::read_to_string(&raw_htmlfile) {
+        Ok(raw_html) => raw_html,
+        Err(io_err) => return Err(Error::io(&raw_htmlfile, io_err)),
+    };
+    let mut ama_index: Vec<ama_indexer::AmaRecord> = Vec::new();
     // Do some data finalizing, and then save ama index
-    for ama_record in &mut ama_index {
+    for mut ama_record in ama_indexer::compile_ama_index(raw_html, &ama_config.first_cc_name)? {
         // 0507
         let url_id: String = ama_record.url_id.clone();
-        ama_record.url_id = ama_indexer::get_urlid(url_id);
-    };
-    let full_dbpath: String = format!("{}/{}", ODIR_NAME, DB_FNAME);
-    let () = ama_indexer::create_db(&full_dbpath);
-    match ama_indexer::save_ama_index(ama_index, &full_dbpath) {
-        Ok(num_bytes) => println!("{} bytes written.", num_bytes),
-        Err(save_err) => eprintln!("Could not save to disk: {:?}", save_err),
+        match ama_indexer::get_urlid(ama_config, url_id) {
+            Ok(url_id) => ama_record.url_id = url_id,
+            Err(parse_err) => {
+                eprintln!("Skipping {} to {}: {}", ama_record.fan_name, ama_record.cc_name, parse_err);
+                continue;
+            },
+        };
+        ama_index.push(ama_record);
     };
+    let num_uncorrected: usize = report_duplicates(fetcher, backend, ama_config, odir_name, &ama_index);
+    if fail_on_duplicates && num_uncorrected > 0 {
+        return Err(Error::Validation(format!("{} url_ids are shared by several records, and have no correction.", num_uncorrected)));
+    }
+    let mut store: Store = Store::open(full_dbpath)?;
+    let old_index: Vec<ama_indexer::AmaRecord> = store.load_ama_index()?;
+    // Url_ids edited by hand are kept, for as long as the compendium links to what they were edited from.
+    let urlid_edits: Vec<ama_filetree::FieldEdit> = store.load_field_edits()?.into_iter()
+        .filter(|field_edit| field_edit.field == ama_filetree::EditedField::UrlId)
+        .collect();
+    for ama_record in &mut ama_index {
+        for correction in ama_config.corrections.iter() {
+            let _ = correction.apply(ama_record);
+        }
+        for field_edit in urlid_edits.iter() {
+            if field_edit.cc_name == ama_record.cc_name && field_edit.fan_name == ama_record.fan_name && field_edit.url_id == ama_record.url_id {
+                ama_record.url_id = field_edit.new_value.clone();
+            }
+        }
+    }
+    let mut skipped_positions: Vec<usize> = Vec::new();
+    for duplicate in ama_indexer::identify_duplicates(&ama_index) {
+        let old_urlids: Vec<Option<String>> = duplicate.positions.iter()
+            .map(|position| {
+                old_index.iter()
+                    .find(|old_record| old_record.cc_name == ama_index[*position].cc_name && old_record.fan_name == ama_index[*position].fan_name)
+                    .map(|old_record| old_record.url_id.clone())
+            })
+            .collect();
+        // The shared url_id stays with whoever was saved with it, or else goes to the first new record.
+        let keeper: Option<usize> = old_urlids.iter().position(|old_url_id| old_url_id.as_ref() == Some(&duplicate.url_id))
+            .or_else(|| old_urlids.iter().position(Option::is_none));
+        for (nth, (position, old_url_id)) in duplicate.positions.into_iter().zip(old_urlids).enumerate() {
+            if Some(nth) == keeper {
+                continue;
+            }
+            match old_url_id {
+                Some(old_url_id) => {
+                    eprintln!("Keeping {} to {} at 'url_id' {}, since {} is taken. Add a correction for it, and index again.", ama_index[position].fan_name, ama_index[position].cc_name, old_url_id, duplicate.url_id);
+                    ama_index[position].url_id = old_url_id;
+                },
+                None => {
+                    eprintln!("Skipping {} to {}, whose 'url_id' {} is taken. Add a correction for it, and index again.", ama_index[position].fan_name, ama_index[position].cc_name, duplicate.url_id);
+                    skipped_positions.push(position);
+                },
+            };
+        }
+    }
+    let ama_index: Vec<ama_indexer::AmaRecord> = ama_index.into_iter()
+        .enumerate()
+        .filter(|(position, _)| !skipped_positions.contains(position))
+        .map(|(_, ama_record)| ama_record)
+        .collect();
+    // A saved url_id may have been given to another record since, and no record is dropped to make room.
+    let num_duplicates: usize = ama_indexer::identify_duplicates(&ama_index).len();
+    if num_duplicates > 0 {
+        return Err(Error::Validation(format!("{} url_ids would still be shared by several records. Add corrections for them, and index again.", num_duplicates)));
+    }
+    let index_changes: ama_indexer::IndexChanges = store.upsert_ama_index(&ama_index)?;
+    for ama_record in index_changes.added.iter() {
+        println!("Added: {} to {}, at 'url_id' {}.", ama_record.fan_name, ama_record.cc_name, ama_record.url_id);
+    }
+    for (ama_record, old_url_id) in index_changes.changed.iter() {
+        println!("Changed: {} to {}, from 'url_id' {} to {}.", ama_record.fan_name, ama_record.cc_name, old_url_id, ama_record.url_id);
+    }
+    for ama_record in index_changes.removed.iter() {
+        println!("Removed: {} to {}, at 'url_id' {}.", ama_record.fan_name, ama_record.cc_name, ama_record.url_id);
+    }
+    println!("{} records indexed: {}.", ama_index.len(), index_changes);
+    Ok(index_changes)
+}
+
+#[cfg(test)]
+mod ama_archiver_tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use super::ama_filetree::EditedField;
+
+    const REPLAY_DIR: &str = "tests/fixtures/replay";
+    const CONFIG_PATH: &str = "tests/fixtures/test_ama.toml";
+
+    fn get_retry_policy() -> super::RetryPolicy {
+        super::RetryPolicy {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+            jitter: false,
+        }
+    }
+
+    /// Indexes and scrapes the test AMA into `odir_name`, starting from nothing, with `backend`.
+    /// Returns the path of the database.
+    fn compile_archive(odir_name: &str, backend: super::Backend) -> String {
+        let full_dbpath: String = format!("{}/{}", odir_name, super::DB_FNAME);
+        let _ = fs::remove_dir_all(odir_name);
+        let fetcher = super::ReplayFetcher::from_dir(REPLAY_DIR).unwrap();
+        let ama_config: super::AmaConfig = super::load_ama_config(CONFIG_PATH).unwrap();
+        super::compile_index(&fetcher, backend, &ama_config, odir_name, &full_dbpath, true).unwrap();
+        super::compile_queries(&fetcher, backend, &ama_config, odir_name, &full_dbpath, &get_retry_policy()).unwrap();
+        full_dbpath
+    }
+
+    #[test]
+    fn test_pipeline_offline() {
+        let odir_name: &str = "output/pipeline_test";
+        let full_dbpath: String = compile_archive(odir_name, super::Backend::Html);
+        let ama_config: super::AmaConfig = super::load_ama_config(CONFIG_PATH).unwrap();
+        let ama_index = super::ama_indexer::load_ama_index(&full_dbpath).unwrap();
+        let ama_queries = super::ama_scraper::load_ama_queries_from_db(&full_dbpath).unwrap();
+        let ama_thread = super::ama_scraper::load_ama_thread_from_db("c00003", &full_dbpath).unwrap();
+        let validation_issues = super::validate_archive(&ama_config, &full_dbpath).unwrap();
+        let num_pages: usize = fs::read_dir(format!("{}/ama_pages", odir_name)).unwrap().count();
+        let _ = fs::remove_dir_all(odir_name);
+        assert_eq!(num_pages, 3);
+        let urlids: Vec<&str> = ama_index.iter().map(|ama_record| ama_record.url_id.as_str()).collect();
+        assert_eq!(urlids, vec!["c00001", "c00003", "c00005"]);
+        assert_eq!(ama_queries.len(), 3);
+        assert_eq!(ama_queries[2].question_text, Some("Bob, what is your favorite episode?\n\n".to_string()));
+        assert_eq!(ama_thread.len(), 4);
+        assert_eq!(validation_issues, Vec::new());
+    }
+
+    #[test]
+    fn test_pipeline_offline_json() {
+        let odir_name: &str = "output/pipeline_json_test";
+        let full_dbpath: String = compile_archive(odir_name, super::Backend::Json);
+        let ama_config: super::AmaConfig = super::load_ama_config(CONFIG_PATH).unwrap();
+        let ama_index = super::ama_indexer::load_ama_index(&full_dbpath).unwrap();
+        let ama_queries = super::ama_scraper::load_ama_queries_from_db(&full_dbpath).unwrap();
+        let validation_issues = super::validate_archive(&ama_config, &full_dbpath).unwrap();
+        // The cached listings are reparsed just as they were parsed when fetched.
+        super::reparse_queries(&ama_config, odir_name, &full_dbpath, super::Backend::Json).unwrap();
+        let reparsed_ama_queries = super::ama_scraper::load_ama_queries_from_db(&full_dbpath).unwrap();
+        let json_page_exists: bool = super::ama_scraper::get_raw_query_path(&std::path::Path::new(odir_name).join("ama_pages"), "c00003", super::Backend::Json).exists();
+        let _ = fs::remove_dir_all(odir_name);
+        assert!(json_page_exists);
+        let urlids: Vec<&str> = ama_index.iter().map(|ama_record| ama_record.url_id.as_str()).collect();
+        assert_eq!(urlids, vec!["c00001", "c00003", "c00005"]);
+        assert_eq!(ama_queries.len(), 3);
+        assert_eq!(ama_queries[2].question_text, Some("Bob, what is your favorite episode?\n\n".to_string()));
+        assert_eq!(ama_queries[2].answer_markdown, Some("> favorite episode\n\nDefinitely **the finale**, because >!everyone comes home!<.".to_string()));
+        assert_eq!(ama_queries[2].answer_meta.datetime, Some("2019-08-03T17:50:00+00:00".to_string()));
+        assert_eq!(reparsed_ama_queries, ama_queries);
+        assert_eq!(validation_issues, Vec::new());
+    }
+
+    #[test]
+    fn test_compile_index_duplicates() {
+        // The real link compendium has two url_ids that are each shared by two fans.
+        let odir_name: &str = "output/duplicates_test";
+        let full_dbpath: String = format!("{}/{}", odir_name, super::DB_FNAME);
+        let _ = fs::remove_dir_all(odir_name);
+        let fetcher = super::ReplayFetcher::from_dir(REPLAY_DIR).unwrap();
+        let mut ama_config = super::AmaConfig::default();
+        super::compile_index(&fetcher, super::Backend::Html, &ama_config, odir_name, &full_dbpath, true).unwrap();
+        let expected = super::ama_indexer::load_ama_index(&full_dbpath).unwrap();
+        // Indexing again changes nothing, and keeps the corrections.
+        assert!(super::compile_index(&fetcher, super::Backend::Html, &ama_config, odir_name, &full_dbpath, true).unwrap().is_empty());
+        assert_eq!(super::fix_database(&ama_config, &full_dbpath, false).unwrap(), vec![super::CorrectionStatus::AlreadyApplied, super::CorrectionStatus::AlreadyApplied]);
+        let ama_query = super::ama_scraper::AmaQuery {
+            url_id: "evw8g9o".to_string(),
+            question_text: Some("question_text".to_string()),
+            answer_text: Some("answer_text".to_string()),
+            ..Default::default()
+        };
+        let _ = super::Store::open(&full_dbpath).unwrap().save_ama_query(&ama_query).unwrap();
+        // Without the corrections that cover them, the index stage fails before anything is saved.
+        ama_config.corrections.clear();
+        let index_result = super::compile_index(&fetcher, super::Backend::Html, &ama_config, odir_name, &full_dbpath, true);
+        let failed_index = super::ama_indexer::load_ama_index(&full_dbpath).unwrap();
+        // Otherwise, each corrected record keeps its url_id, and its query, rather than being dropped.
+        let index_changes = super::compile_index(&fetcher, super::Backend::Html, &ama_config, odir_name, &full_dbpath, false).unwrap();
+        let kept_index = super::ama_indexer::load_ama_index(&full_dbpath).unwrap();
+        let kept_urlids: Vec<String> = super::ama_scraper::load_ama_queries_from_db(&full_dbpath).unwrap().into_iter().map(|ama_query| ama_query.url_id).collect();
+        // Starting from nothing, each shared url_id goes to the first record that links to it.
+        let _ = fs::remove_file(&full_dbpath);
+        let _ = super::compile_index(&fetcher, super::Backend::Html, &ama_config, odir_name, &full_dbpath, false).unwrap();
+        let fresh_index = super::ama_indexer::load_ama_index(&full_dbpath).unwrap();
+        let _ = fs::remove_dir_all(odir_name);
+        match index_result {
+            Err(super::Error::Validation(_)) => (),
+            other => panic!("Expected a validation error, got: {:?}", other),
+        };
+        assert_eq!(failed_index, expected);
+        assert!(index_changes.is_empty(), "Expected no changes, got: {}", index_changes);
+        assert_eq!(kept_index, expected);
+        assert_eq!(kept_urlids, vec!["evw8g9o".to_string()]);
+        assert_eq!(super::ama_indexer::identify_duplicates(&fresh_index).len(), 0);
+        assert_eq!(fresh_index.len(), expected.len() - 2);
+    }
+
+    #[test]
+    fn test_reparse_queries() {
+        let odir_name: &str = "output/reparse_test";
+        let full_dbpath: String = compile_archive(odir_name, super::Backend::Html);
+        let ama_config: super::AmaConfig = super::load_ama_config(CONFIG_PATH).unwrap();
+        let expected = super::ama_scraper::load_ama_queries_from_db(&full_dbpath).unwrap();
+        // Lose one query, and damage another, then rebuild both from the cache.
+        let cnxn = rusqlite::Connection::open(&full_dbpath).unwrap();
+        cnxn.execute("DELETE FROM ama_queries WHERE url_id = 'c00001';", ()).unwrap();
+        cnxn.execute("UPDATE ama_queries SET answer_text = '' WHERE url_id = 'c00003';", ()).unwrap();
+        super::reparse_queries(&ama_config, odir_name, &full_dbpath, super::Backend::Html).unwrap();
+        let mut actual = super::ama_scraper::load_ama_queries_from_db(&full_dbpath).unwrap();
+        let _ = fs::remove_dir_all(odir_name);
+        actual.sort_by(|a, b| a.url_id.cmp(&b.url_id));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_search_archive() {
+        let odir_name: &str = "output/search_test";
+        let full_dbpath: String = compile_archive(odir_name, super::Backend::Html);
+        let ama_config: super::AmaConfig = super::load_ama_config(CONFIG_PATH).unwrap();
+        let search_results = super::search_archive(&ama_config, &full_dbpath, "favorite episode", None, 10).unwrap();
+        let creator_results = super::search_archive(&ama_config, &full_dbpath, "favorite episode", Some("Alice Author"), 10).unwrap();
+        let empty_result = super::search_archive(&ama_config, &full_dbpath, "  ", None, 10);
+        let _ = fs::remove_dir_all(odir_name);
+        assert_eq!(search_results.len(), 1);
+        assert_eq!((search_results[0].url_id.as_str(), search_results[0].cc_name.as_str()), ("c00005", "Bob Builder"));
+        assert!(search_results[0].snippet.contains("**favorite** **episode**"));
+        assert!(creator_results.is_empty());
+        assert!(empty_result.is_err());
+    }
+
+    #[test]
+    fn test_export_archive() {
+        let odir_name: &str = "output/export_test";
+        let full_dbpath: String = compile_archive(odir_name, super::Backend::Html);
+        let ama_config: super::AmaConfig = super::load_ama_config(CONFIG_PATH).unwrap();
+        let _ = super::ama_scraper::delete_ama_query_from_db("c00003", &full_dbpath).unwrap();
+        let unscraped_records = super::export_archive(&ama_config, odir_name, &full_dbpath, super::ExportFormat::Jsonl, None, &super::CsvOptions::default()).unwrap();
+        let _ = super::export_archive(&ama_config, odir_name, &full_dbpath, super::ExportFormat::Json, None, &super::CsvOptions::default()).unwrap();
+        let _ = super::export_archive(&ama_config, odir_name, &full_dbpath, super::ExportFormat::Markdown, None, &super::CsvOptions::default()).unwrap();
+        let _ = super::export_archive(&ama_config, odir_name, &full_dbpath, super::ExportFormat::Epub, None, &super::CsvOptions::default()).unwrap();
+        let raw_epub = fs::read(format!("{}/ama_archive.epub", odir_name)).unwrap();
+        let raw_jsonl = fs::read_to_string(format!("{}/ama_archive.jsonl", odir_name)).unwrap();
+        let raw_json = fs::read_to_string(format!("{}/ama_archive.json", odir_name)).unwrap();
+        let raw_book = fs::read_to_string(format!("{}/ama_archive.md", odir_name)).unwrap();
+        let _ = fs::remove_dir_all(odir_name);
+        assert_eq!(unscraped_records.iter().map(|ama_record| ama_record.url_id.as_str()).collect::<Vec<&str>>(), vec!["c00003"]);
+        let exchanges: Vec<serde_json::Value> = raw_jsonl.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let bob_exchange = exchanges.iter().find(|exchange| exchange["url_id"] == "c00005").unwrap();
+        assert_eq!(bob_exchange["cc_name"], "Bob Builder");
+        assert_eq!(bob_exchange["url"], "https://old.reddit.com/r/testama/comments/abc123/test_ama/c00005/?context=3");
+        assert_eq!(bob_exchange["answer_meta"]["author"], "bobbuilds");
+        let archive_document: serde_json::Value = serde_json::from_str(&raw_json).unwrap();
+        assert_eq!(archive_document["num_exchanges"], exchanges.len());
+        assert_eq!(archive_document["subreddit"], "testama");
+        // Alice Author comes first in the compendium.
+        assert!(raw_book.find("## Alice Author").unwrap() < raw_book.find("## Bob Builder").unwrap());
+        assert!(raw_book.contains("[View on Reddit](https://old.reddit.com/r/testama/comments/abc123/test_ama/c00005/?context=3)"));
+        assert_eq!(&raw_epub[30..58], b"mimetypeapplication/epub+zip");
+    }
+
+    #[test]
+    fn test_import_csv() {
+        let odir_name: &str = "output/csv_test";
+        let full_dbpath: String = compile_archive(odir_name, super::Backend::Html);
+        let sheet_path: String = format!("{}/ama_archive.tsv", odir_name);
+        let ama_config: super::AmaConfig = super::load_ama_config(CONFIG_PATH).unwrap();
+        let _ = super::export_archive(&ama_config, odir_name, &full_dbpath, super::ExportFormat::Tsv, None, &super::CsvOptions { bom: true, ..Default::default() }).unwrap();
+        // Nothing was edited yet.
+        assert_eq!(super::import_csv(&full_dbpath, &sheet_path, false).unwrap(), Vec::new());
+        let raw_tsv: String = fs::read_to_string(&sheet_path).unwrap();
+        let mut rows = super::ama_csv::parse_csv(&raw_tsv, '\t').unwrap();
+        let bob_row: &mut Vec<String> = rows.iter_mut().find(|row| row[2] == "c00005").unwrap();
+        bob_row[5] = "Star vs. the \"Forces\"\tof Evil,\nall of it.".to_string();
+        bob_row[2] = "c00099".to_string();
+        let edited_rows: Vec<String> = rows.iter()
+            .map(|row| row.iter().map(|field| super::ama_csv::quote_field(field, '\t').into_owned()).collect::<Vec<String>>().join("\t"))
+            .collect();
+        fs::write(&sheet_path, edited_rows.join("\n")).unwrap();
+        assert_eq!(super::import_csv(&full_dbpath, &sheet_path, true).unwrap().len(), 2);
+        let field_edits = super::import_csv(&full_dbpath, &sheet_path, false).unwrap();
+        let ama_queries = super::ama_scraper::load_ama_queries_from_db(&full_dbpath).unwrap();
+        let _ = fs::remove_dir_all(odir_name);
+        let fields: Vec<EditedField> = field_edits.iter().map(|field_edit| field_edit.field).collect();
+        assert_eq!(fields, vec![EditedField::AnswerText, EditedField::UrlId]);
+        let edited_query = ama_queries.iter().find(|ama_query| ama_query.url_id == "c00099").unwrap();
+        assert_eq!(edited_query.answer_text, Some("Star vs. the \"Forces\"\tof Evil,\nall of it.".to_string()));
+    }
+
+    #[test]
+    fn test_write_site() {
+        let odir_name: &str = "output/site_test";
+        let full_dbpath: String = compile_archive(odir_name, super::Backend::Html);
+        let ama_config: super::AmaConfig = super::load_ama_config(CONFIG_PATH).unwrap();
+        let unscraped_records = super::write_site(&ama_config, odir_name, &full_dbpath, None).unwrap();
+        let site_path = std::path::Path::new(odir_name).join("ama_site");
+        let exchange_page = fs::read_to_string(site_path.join("exchanges/c00005.html")).unwrap();
+        let creator_page = fs::read_to_string(site_path.join("creators/bob-builder.html")).unwrap();
+        let _ = fs::remove_dir_all(odir_name);
+        assert!(unscraped_records.is_empty());
+        assert!(exchange_page.contains("Bob, what is your favorite episode?"));
+        assert!(exchange_page.contains("<a href=\"https://old.reddit.com/r/testama/comments/abc123/test_ama/c00005/?context=3\">View on Reddit</a>"));
+        assert!(creator_page.contains("<a href=\"../exchanges/c00005.html\">"));
+    }
+
+    #[test]
+    fn test_import_filetree() {
+        let odir_name: &str = "output/import_test";
+        let full_dbpath: String = compile_archive(odir_name, super::Backend::Html);
+        let ama_config: super::AmaConfig = super::load_ama_config(CONFIG_PATH).unwrap();
+        super::write_filetree(&ama_config, odir_name, &full_dbpath).unwrap();
+        // Nothing was edited yet.
+        assert_eq!(super::import_filetree(odir_name, &full_dbpath, false).unwrap(), Vec::new());
+        let root_path = std::path::Path::new(odir_name).join("ama_filetree");
+        let filetree_entries = super::ama_filetree::load_manifest(&root_path).unwrap();
+        let record_path = root_path.join(&filetree_entries[2].path);
+        fs::write(record_path.join("answer_text.txt"), "Corrected answer.").unwrap();
+        fs::write(record_path.join("url_id.txt"), "c00099\n").unwrap();
+        // Filetrees written before the manifest are read by their directory names.
+        fs::remove_file(root_path.join("manifest.json")).unwrap();
+        assert_eq!(super::import_filetree(odir_name, &full_dbpath, true).unwrap().len(), 2);
+        let field_edits = super::import_filetree(odir_name, &full_dbpath, false).unwrap();
+        // Indexing again keeps the edited url_id, and the query that moved with it.
+        let fetcher = super::ReplayFetcher::from_dir(REPLAY_DIR).unwrap();
+        let index_changes = super::compile_index(&fetcher, super::Backend::Html, &ama_config, odir_name, &full_dbpath, true).unwrap();
+        let store = super::Store::open(&full_dbpath).unwrap();
+        let ama_index = store.load_ama_index().unwrap();
+        let ama_queries = store.load_ama_queries().unwrap();
+        let ama_thread = store.load_ama_thread("c00099").unwrap();
+        let recorded_edits = store.load_field_edits().unwrap();
+        let _ = fs::remove_dir_all(odir_name);
+        let fields: Vec<(EditedField, &str)> = field_edits.iter().map(|field_edit| (field_edit.field, field_edit.new_value.as_str())).collect();
+        assert_eq!(fields, vec![(EditedField::AnswerText, "Corrected answer."), (EditedField::UrlId, "c00099")]);
+        let edited_query = ama_queries.iter().find(|ama_query| ama_query.url_id == "c00099").unwrap();
+        assert_eq!(edited_query.answer_text, Some("Corrected answer.".to_string()));
+        assert!(!ama_thread.is_empty());
+        assert_eq!(recorded_edits, field_edits);
+        assert!(index_changes.changed.is_empty());
+        assert_eq!(ama_index[2].url_id, "c00099");
+    }
+
+    #[test]
+    fn test_write_filetree() {
+        let odir_name: &str = "output/filetree_test";
+        let full_dbpath: String = compile_archive(odir_name, super::Backend::Html);
+        let ama_config: super::AmaConfig = super::load_ama_config(CONFIG_PATH).unwrap();
+        // An exchange that was never scraped is reported, and skipped.
+        let _ = super::ama_scraper::delete_ama_query_from_db("c00003", &full_dbpath).unwrap();
+        let unscraped_records = super::write_filetree(&ama_config, odir_name, &full_dbpath).unwrap();
+        let ama_index = super::ama_indexer::load_ama_index(&full_dbpath).unwrap();
+        let record_paths: Vec<std::path::PathBuf> = ama_index.iter()
+            .map(|ama_record| std::path::Path::new(odir_name).join("ama_filetree").join(&ama_record.cc_name).join(&ama_record.fan_name))
+            .collect();
+        let url_ids: Vec<Option<String>> = record_paths.iter()
+            .map(|record_path| fs::read_to_string(record_path.join("url_id.txt")).ok())
+            .collect();
+        let answer_text: String = fs::read_to_string(record_paths[2].join("answer_text.txt")).unwrap();
+        let filetree_entries = super::ama_filetree::load_manifest(&std::path::Path::new(odir_name).join("ama_filetree")).unwrap();
+        let _ = fs::remove_dir_all(odir_name);
+        assert_eq!(unscraped_records, vec![ama_index[1].clone()]);
+        assert_eq!(url_ids, vec![Some("c00001".to_string()), None, Some("c00005".to_string())]);
+        assert!(!answer_text.is_empty());
+        let manifest_urlids: Vec<&str> = filetree_entries.iter().map(|filetree_entry| filetree_entry.url_id.as_str()).collect();
+        assert_eq!(manifest_urlids, vec!["c00001", "c00005"]);
+    }
+
 }
@@ -1,16 +1,256 @@
-// TODO: Either write Makefile, or create CLI for this.
-use ama_archiver::{compile_queries, compile_index, write_filetree, fix_database};
-
-// compile index
-// correct the database.
-// compile queries
-// write filetree
-
-fn main() {
-    //compile_index();
-    fix_database();
-    // - UPDATE ama_index SET url_id='evw8g9o' WHERE fan_name='Joe_Zt' AND cc_name='Daron Nefcy';
-    // - UPDATE ama_index SET url_id='evwbgza' WHERE fan_name='sloppyjeaux' AND cc_name='Adam McArthur';
-    //compile_queries();
-    //write_filetree();
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+
+use ama_archiver::{compile_queries, compile_index, write_filetree, import_filetree, search_archive, export_archive, import_csv, write_site, fix_database, migrate_database, reparse_queries, validate_archive, load_ama_config, AmaConfig, Backend, CsvColumn, CsvOptions, Error, ExportFormat, Result, RetryPolicy, DB_FNAME, ODIR_NAME};
+use ama_archiver::{Fetcher, UreqFetcher, ReplayFetcher, RecordingFetcher};
+
+/// Archives the Q&A exchanges of a Reddit AMA.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Directory that every stage reads from and writes to.
+    #[arg(long, global = true, default_value = ODIR_NAME)]
+    output_dir: PathBuf,
+    /// Path of the archive database. Defaults to `<OUTPUT_DIR>/ama_archive.db`.
+    #[arg(long, global = true)]
+    db: Option<PathBuf>,
+    /// TOML file that defines the AMA to archive. Defaults to the Star vs. the Forces of Evil AMA.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Subreddit that hosts the AMA thread.
+    #[arg(long, global = true)]
+    subreddit: Option<String>,
+    /// Id of the AMA thread, as found in '/comments/{thread_id}/'.
+    #[arg(long, global = true)]
+    thread_id: Option<String>,
+    /// Slug that follows the thread id in comment URLs.
+    #[arg(long, global = true)]
+    thread_slug: Option<String>,
+    /// URL of the post that links to every exchange.
+    #[arg(long, global = true)]
+    compendium_url: Option<String>,
+    /// Text of the first creator heading in the compendium, e.g. 'Daron Nefcy:'.
+    #[arg(long, global = true)]
+    first_cc_name: Option<String>,
+    /// Serve every page from a recorded directory or HAR file, instead of from Reddit.
+    #[arg(long, global = true, conflicts_with = "record")]
+    replay: Option<PathBuf>,
+    /// Save every page fetched from Reddit to this directory, so that it can be replayed later.
+    #[arg(long, global = true)]
+    record: Option<PathBuf>,
+    /// Where to read the comments from: 'html' scrapes old Reddit's pages, and 'json' reads Reddit's '.json' listings, which keep the Markdown as it was written.
+    #[arg(long, global = true, default_value_t = Backend::Html)]
+    backend: Backend,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compile the index of exchanges from the link compendium.
+    Index(IndexArgs),
+    /// Apply the corrections listed in the AMA definition to the compiled index.
+    Fix {
+        /// Report what each correction would change, without saving anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Scrape the Q&A text for every indexed exchange.
+    Scrape(RetryArgs),
+    /// Rebuild the scraped Q&A text from the pages cached by the chosen backend, without touching the network.
+    Reparse,
+    /// Check that every answer was written by the creator it is credited to, and every question by the fan.
+    Validate {
+        /// Fail if anything is flagged, instead of only reporting it.
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Write every scraped exchange to a directory tree.
+    Filetree,
+    /// Bring the edits made by hand to the directory tree back into the database.
+    Import {
+        /// Report what was edited, without saving anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Export every scraped exchange, together with its creator, fan and URL.
+    Export {
+        /// Format to export in: 'json' groups the exchanges by creator, 'jsonl' writes one per line, 'csv' or 'tsv' one per row, and 'md' or 'epub' writes a book with a chapter per creator.
+        #[arg(long, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// File to export to, or '-' for standard output. Defaults to `<OUTPUT_DIR>/ama_archive.<FORMAT>`.
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// Columns to write to CSV or TSV, separated by commas, e.g. 'cc_name,fan_name,answer_text,answer_score'.
+        #[arg(long, value_delimiter = ',', default_values_t = CsvOptions::default().columns)]
+        columns: Vec<CsvColumn>,
+        /// Start CSV or TSV with a byte order mark, so that Excel reads it as UTF-8.
+        #[arg(long)]
+        bom: bool,
+    },
+    /// Bring the edits made to an exported CSV or TSV sheet back into the database.
+    ImportCsv {
+        /// Sheet to import. Read as TSV if it ends in '.tsv', and as CSV otherwise.
+        sheet: PathBuf,
+        /// Report what was edited, without saving anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Write every scraped exchange to a static website, which can be browsed offline.
+    Site {
+        /// Directory to write the site to. Defaults to `<OUTPUT_DIR>/ama_site`.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Search the scraped questions and answers, best match first.
+    Search {
+        /// Words that every match must contain, in any order.
+        terms: String,
+        /// Only search the exchanges with this creator, e.g. 'Daron Nefcy'.
+        #[arg(long)]
+        creator: Option<String>,
+        /// Most matches to show.
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Upgrade the archive database to the latest schema, in place. Every other stage does so as well.
+    Migrate,
+    /// Run every stage, in order.
+    All {
+        #[command(flatten)]
+        index_args: IndexArgs,
+        #[command(flatten)]
+        retry_args: RetryArgs,
+    },
+}
+
+/// Settings for compiling the index.
+#[derive(Args)]
+struct IndexArgs {
+    /// Fail before the index is saved, if several records share a url_id that no correction covers.
+    #[arg(long)]
+    fail_on_duplicates: bool,
+}
+
+/// Settings for re-fetching exchanges that fail to load.
+#[derive(Args)]
+struct RetryArgs {
+    /// Attempts per exchange, including the first, before it is marked failed and skipped.
+    #[arg(long, default_value_t = RetryPolicy::default().max_attempts, value_parser = clap::value_parser!(u32).range(1..))]
+    max_attempts: u32,
+    /// Seconds to wait after the first failed attempt. Doubles with each attempt after.
+    #[arg(long, default_value_t = RetryPolicy::default().base_delay.as_secs_f64(), value_parser = parse_delay)]
+    base_delay: f64,
+    /// Longest wait between attempts, in seconds, unless Reddit asks for longer with 'Retry-After'.
+    #[arg(long, default_value_t = RetryPolicy::default().max_delay.as_secs_f64(), value_parser = parse_delay)]
+    max_delay: f64,
+    /// Wait exactly the backoff delay, instead of a random amount between half and all of it.
+    #[arg(long)]
+    no_jitter: bool,
+}
+
+/// Parses a delay in seconds, refusing any that is negative, not a number, or too long to wait.
+fn parse_delay(arg: &str) -> std::result::Result<f64, String> {
+    let secs: f64 = match arg.parse() {
+        Ok(secs) => secs,
+        Err(parse_err) => return Err(format!("{}", parse_err)),
+    };
+    match Duration::try_from_secs_f64(secs) {
+        Ok(_) => Ok(secs),
+        Err(_) => Err(format!("'{}' is not a number of seconds that can be waited.", arg)),
+    }
+}
+
+impl RetryArgs {
+    fn get_retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            base_delay: Duration::from_secs_f64(self.base_delay),
+            max_delay: Duration::from_secs_f64(self.max_delay),
+            jitter: !self.no_jitter,
+        }
+    }
+}
+
+/// Loads the AMA definition from `--config`, and applies any overrides given on the command line.
+fn get_ama_config(cli: &Cli) -> Result<AmaConfig> {
+    let mut ama_config: AmaConfig = match &cli.config {
+        Some(config_path) => load_ama_config(config_path)?,
+        None => AmaConfig::default(),
+    };
+    if let Some(subreddit) = &cli.subreddit {
+        ama_config.subreddit = subreddit.clone();
+    }
+    if let Some(thread_id) = &cli.thread_id {
+        ama_config.thread_id = thread_id.clone();
+    }
+    if let Some(thread_slug) = &cli.thread_slug {
+        ama_config.thread_slug = thread_slug.clone();
+    }
+    if let Some(compendium_url) = &cli.compendium_url {
+        ama_config.compendium_url = compendium_url.clone();
+    }
+    if let Some(first_cc_name) = &cli.first_cc_name {
+        ama_config.first_cc_name = first_cc_name.clone();
+    }
+    Ok(ama_config)
+}
+
+/// Picks where pages come from: a recording, the live site, or the live site while recording.
+fn get_fetcher(cli: &Cli) -> Result<Box<dyn Fetcher>> {
+    let fetcher: Box<dyn Fetcher> = match (&cli.replay, &cli.record) {
+        (Some(replay_path), _) => Box::new(ReplayFetcher::load(replay_path)?),
+        (None, Some(record_dir)) => Box::new(RecordingFetcher::new(UreqFetcher::new(), record_dir)?),
+        (None, None) => Box::new(UreqFetcher::new()),
+    };
+    Ok(fetcher)
+}
+
+fn main() -> ExitCode {
+    let cli: Cli = Cli::parse();
+    let (ama_config, fetcher): (AmaConfig, Box<dyn Fetcher>) = match get_ama_config(&cli).and_then(|ama_config| Ok((ama_config, get_fetcher(&cli)?))) {
+        Ok(setup) => setup,
+        Err(setup_err) => {
+            eprintln!("{}", setup_err);
+            return ExitCode::FAILURE;
+        },
+    };
+    let odir_name: String = cli.output_dir.to_string_lossy().into_owned();
+    let full_dbpath: String = match cli.db {
+        Some(db) => db.to_string_lossy().into_owned(),
+        None => format!("{}/{}", odir_name, DB_FNAME),
+    };
+    let run_result: Result<()> = match cli.command {
+        Command::Index(index_args) => compile_index(fetcher.as_ref(), cli.backend, &ama_config, &odir_name, &full_dbpath, index_args.fail_on_duplicates).map(|_| ()),
+        Command::Fix { dry_run } => fix_database(&ama_config, &full_dbpath, dry_run).map(|_| ()),
+        Command::Scrape(retry_args) => compile_queries(fetcher.as_ref(), cli.backend, &ama_config, &odir_name, &full_dbpath, &retry_args.get_retry_policy()),
+        Command::Reparse => reparse_queries(&ama_config, &odir_name, &full_dbpath, cli.backend),
+        Command::Validate { strict } => validate_archive(&ama_config, &full_dbpath)
+            .and_then(|validation_issues| match (strict, validation_issues.len()) {
+                (true, num_issues) if num_issues > 0 => Err(Error::Validation(format!("{} issues were found.", num_issues))),
+                _ => Ok(()),
+            }),
+        Command::Filetree => write_filetree(&ama_config, &odir_name, &full_dbpath).map(|_| ()),
+        Command::Import { dry_run } => import_filetree(&odir_name, &full_dbpath, dry_run).map(|_| ()),
+        Command::Export { format, output, columns, bom } => export_archive(&ama_config, &odir_name, &full_dbpath, format, output.as_ref().map(|output| output.to_string_lossy()).as_deref(), &CsvOptions { columns, bom }).map(|_| ()),
+        Command::ImportCsv { sheet, dry_run } => import_csv(&full_dbpath, &sheet.to_string_lossy(), dry_run).map(|_| ()),
+        Command::Site { output } => write_site(&ama_config, &odir_name, &full_dbpath, output.as_ref().map(|output| output.to_string_lossy()).as_deref()).map(|_| ()),
+        Command::Search { terms, creator, limit } => search_archive(&ama_config, &full_dbpath, &terms, creator.as_deref(), limit).map(|_| ()),
+        Command::Migrate => migrate_database(&full_dbpath),
+        Command::All { index_args, retry_args } => compile_index(fetcher.as_ref(), cli.backend, &ama_config, &odir_name, &full_dbpath, index_args.fail_on_duplicates).map(|_| ())
+            .and_then(|()| fix_database(&ama_config, &full_dbpath, false).map(|_| ()))
+            .and_then(|()| compile_queries(fetcher.as_ref(), cli.backend, &ama_config, &odir_name, &full_dbpath, &retry_args.get_retry_policy()))
+            .and_then(|()| validate_archive(&ama_config, &full_dbpath).map(|_| ()))
+            .and_then(|()| write_filetree(&ama_config, &odir_name, &full_dbpath).map(|_| ())),
+    };
+    match run_result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(run_err) => {
+            eprintln!("{}", run_err);
+            ExitCode::FAILURE
+        },
+    }
 }